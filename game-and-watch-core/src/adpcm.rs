@@ -0,0 +1,235 @@
+use crate::stream::{DecodeError, Decoder};
+
+// IMA ADPCM, 4 bits a sample, as found in WAV files (format 0x11). The data
+// is split into blocks of block_align bytes, each starting with a 4 byte
+// header holding the first sample and the step index, then two samples a
+// byte, low nibble first. Every block can be decoded on its own, which is
+// what seeking relies on. Only mono files.
+
+pub const WAVE_FORMAT_IMA_ADPCM: u16 = 0x11;
+pub const BLOCK_HEADER_SIZE: usize = 4;
+
+const STEPS: [i16; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73, 80, 88, 97,
+    107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796,
+    876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871,
+    5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623,
+    27086, 29794, 32767,
+];
+const INDEX_ADJUST: [i8; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+const MAX_INDEX: u8 = STEPS.len() as u8 - 1;
+
+/// Predictor and step index, the whole state of the codec. The host side
+/// encoder uses the same code so the two can't drift apart.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AdpcmState {
+    pub predictor: i16,
+    pub index: u8,
+}
+
+impl AdpcmState {
+    /// Apply one 4 bit code, returns the sample
+    pub fn decode(&mut self, nibble: u8) -> i16 {
+        let step = STEPS[self.index as usize] as i32;
+        let mut diff = step >> 3;
+        if nibble & 1 != 0 {
+            diff += step >> 2;
+        }
+        if nibble & 2 != 0 {
+            diff += step >> 1;
+        }
+        if nibble & 4 != 0 {
+            diff += step;
+        }
+        if nibble & 8 != 0 {
+            diff = -diff;
+        }
+
+        self.predictor = (self.predictor as i32 + diff).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        self.index = (self.index as i8 + INDEX_ADJUST[nibble as usize & 7]).clamp(0, MAX_INDEX as i8) as u8;
+        self.predictor
+    }
+
+    /// The code that gets closest to `sample`, leaving the state where
+    /// the decoder will have it
+    pub fn encode(&mut self, sample: i16) -> u8 {
+        let step = STEPS[self.index as usize] as i32;
+        let mut diff = sample as i32 - self.predictor as i32;
+        let mut nibble = 0;
+        if diff < 0 {
+            nibble = 8;
+            diff = -diff;
+        }
+        if diff >= step {
+            nibble |= 4;
+            diff -= step;
+        }
+        if diff >= step >> 1 {
+            nibble |= 2;
+            diff -= step >> 1;
+        }
+        if diff >= step >> 2 {
+            nibble |= 1;
+        }
+        self.decode(nibble);
+        nibble
+    }
+}
+
+/// Samples in a block of `block_align` bytes
+pub const fn samples_per_block(block_align: usize) -> usize {
+    1 + (block_align - BLOCK_HEADER_SIZE) * 2
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Mono IMA ADPCM decoder, reading the WAV data in place
+pub struct ImaAdpcm<'a> {
+    data: &'a [u8],
+    block_align: usize,
+    samples_per_block: u32,
+    len: u32,
+    sample_rate: u32,
+    block: usize,
+    /// Next sample within the block, 0 is the header
+    in_block: u32,
+    state: AdpcmState,
+}
+
+impl<'a> ImaAdpcm<'a> {
+    /// Open a WAV file, e.g. one written by the host `encode` tool
+    pub fn from_wav(wav: &'a [u8]) -> Result<Self, DecodeError> {
+        if wav.len() < 12 {
+            return Err(DecodeError::Truncated);
+        }
+        if wav[0..4] != *b"RIFF" || wav[8..12] != *b"WAVE" {
+            return Err(DecodeError::BadHeader);
+        }
+
+        let mut fmt = None;
+        let mut fact = None;
+        let mut data = None;
+        let mut at = 12;
+        while at + 8 <= wav.len() {
+            let size = u32_at(wav, at + 4).ok_or(DecodeError::Truncated)? as usize;
+            // a made up size mustn't wrap around on 32 bits
+            let end = (at + 8).checked_add(size).ok_or(DecodeError::Truncated)?;
+            let body = wav.get(at + 8..end).ok_or(DecodeError::Truncated)?;
+            match &wav[at..at + 4] {
+                b"fmt " => fmt = Some(body),
+                b"fact" => fact = u32_at(body, 0),
+                b"data" => data = Some(body),
+                _ => {}
+            }
+            // chunks are padded to an even length
+            at = end + (size & 1);
+        }
+        let fmt = fmt.ok_or(DecodeError::BadHeader)?;
+        let data = data.ok_or(DecodeError::BadHeader)?;
+
+        let format = u16_at(fmt, 0).ok_or(DecodeError::Truncated)?;
+        let channels = u16_at(fmt, 2).ok_or(DecodeError::Truncated)?;
+        let sample_rate = u32_at(fmt, 4).ok_or(DecodeError::Truncated)?;
+        let block_align = u16_at(fmt, 12).ok_or(DecodeError::Truncated)? as usize;
+        let bits = u16_at(fmt, 14).ok_or(DecodeError::Truncated)?;
+        if format != WAVE_FORMAT_IMA_ADPCM {
+            return Err(DecodeError::BadHeader);
+        }
+        if channels != 1 || bits != 4 || block_align <= BLOCK_HEADER_SIZE {
+            return Err(DecodeError::Unsupported);
+        }
+
+        let per_block = samples_per_block(block_align);
+        let full_blocks = data.len() / block_align;
+        let rest = data.len() % block_align;
+        let mut len = full_blocks * per_block;
+        if rest >= BLOCK_HEADER_SIZE {
+            len += samples_per_block(rest);
+        }
+        // the fact chunk trims the padding off the last block
+        let len = fact.map_or(len, |fact| len.min(fact as usize)) as u32;
+
+        Ok(Self {
+            data,
+            block_align,
+            samples_per_block: per_block as u32,
+            len,
+            sample_rate,
+            block: 0,
+            in_block: 0,
+            state: AdpcmState::default(),
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn next(&mut self) -> i16 {
+        let start = self.block * self.block_align;
+        let sample = if self.in_block == 0 {
+            let header = &self.data[start..start + BLOCK_HEADER_SIZE];
+            self.state = AdpcmState {
+                predictor: i16::from_le_bytes([header[0], header[1]]),
+                index: header[2].min(MAX_INDEX),
+            };
+            self.state.predictor
+        } else {
+            let k = self.in_block as usize - 1;
+            let byte = self.data[start + BLOCK_HEADER_SIZE + k / 2];
+            let nibble = if k & 1 == 0 { byte & 0x0f } else { byte >> 4 };
+            self.state.decode(nibble)
+        };
+
+        self.in_block += 1;
+        if self.in_block == self.samples_per_block {
+            self.block += 1;
+            self.in_block = 0;
+        }
+        sample
+    }
+}
+
+impl Decoder for ImaAdpcm<'_> {
+    fn len(&self) -> u32 {
+        self.len
+    }
+
+    fn seek(&mut self, sample: u32) {
+        self.block = (sample / self.samples_per_block) as usize;
+        self.in_block = 0;
+        for _ in 0..sample % self.samples_per_block {
+            self.next();
+        }
+    }
+
+    fn decode(&mut self, out: &mut [i16]) {
+        for sample in out {
+            *sample = self.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_sizes_past_the_end_are_truncated() {
+        let mut wav = [0; 20];
+        wav[0..4].copy_from_slice(b"RIFF");
+        wav[8..12].copy_from_slice(b"WAVE");
+        wav[12..16].copy_from_slice(b"fmt ");
+        for size in [9, u32::MAX - 7, u32::MAX] {
+            wav[16..20].copy_from_slice(&size.to_le_bytes());
+            assert_eq!(ImaAdpcm::from_wav(&wav).err(), Some(DecodeError::Truncated));
+        }
+    }
+}
@@ -0,0 +1,55 @@
+// Hardware independent side of the audio path. The firmware pulls mono
+// 16 bit samples from an AudioSource, scales them by the master volume and
+// hands them to the SAI.
+
+/// Output rates the SAI clock can be set up for
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SampleRate {
+    Hz32000,
+    Hz44100,
+    #[default]
+    Hz48000,
+}
+
+impl SampleRate {
+    pub const fn hz(self) -> u32 {
+        match self {
+            SampleRate::Hz32000 => 32_000,
+            SampleRate::Hz44100 => 44_100,
+            SampleRate::Hz48000 => 48_000,
+        }
+    }
+
+    /// Samples in `ms` milliseconds, rounded down
+    pub const fn samples(self, ms: u32) -> usize {
+        (self.hz() as u64 * ms as u64 / 1000) as usize
+    }
+}
+
+/// Something that produces mono samples
+pub trait AudioSource {
+    /// Write the next samples to `out`. Returns how many were written,
+    /// fewer than `out.len()` only once the source has ended.
+    fn fill(&mut self, out: &mut [i16]) -> usize;
+}
+
+impl<S: AudioSource + ?Sized> AudioSource for &mut S {
+    fn fill(&mut self, out: &mut [i16]) -> usize {
+        (**self).fill(out)
+    }
+}
+
+pub const MAX_VOLUME: u8 = 255;
+
+/// Scale `samples` by a master volume of 0..=255. The curve is squared,
+/// which sounds closer to even steps than a linear one.
+pub fn apply_volume(samples: &mut [i16], volume: u8) {
+    if volume == MAX_VOLUME {
+        return;
+    }
+    let gain = volume as i32 * volume as i32; // 0..=65025
+    for sample in samples {
+        *sample = ((*sample as i32 * gain) >> 16) as i16;
+    }
+}
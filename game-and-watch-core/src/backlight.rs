@@ -0,0 +1,144 @@
+// Backlight brightness with fades and dimming after a while without
+// input. Like the frame pacer this is only bookkeeping on microsecond
+// timestamps, the display backend does the actual dimming.
+
+pub const MAX_BRIGHTNESS: u8 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fade {
+    from: u8,
+    to: u8,
+    start_us: u64,
+    duration_us: u64,
+}
+
+impl Fade {
+    fn level_at(&self, now_us: u64) -> u8 {
+        let elapsed = now_us.saturating_sub(self.start_us);
+        if elapsed >= self.duration_us {
+            return self.to;
+        }
+        let (from, to) = (self.from as i64, self.to as i64);
+        (from + (to - from) * elapsed as i64 / self.duration_us as i64) as u8
+    }
+
+    fn done(&self, now_us: u64) -> bool {
+        now_us.saturating_sub(self.start_us) >= self.duration_us
+    }
+}
+
+/// Works out the backlight level over time.
+///
+/// Call [`input`](Self::input) whenever a button is pressed and
+/// [`update`](Self::update) once a frame, then hand the returned level to
+/// [`DisplayBackend::set_brightness`](crate::display::DisplayBackend::set_brightness).
+pub struct BacklightControl {
+    brightness: u8,
+    level: u8,
+    fade: Option<Fade>,
+    fade_us: u64,
+    last_input_us: u64,
+    dim_after_us: Option<u64>,
+    dim_level: u8,
+    dimmed: bool,
+}
+
+impl BacklightControl {
+    /// Starts at `brightness` with a 250ms fade and no auto dimming
+    pub fn new(brightness: u8) -> Self {
+        Self {
+            brightness,
+            level: brightness,
+            fade: None,
+            fade_us: 250_000,
+            last_input_us: 0,
+            dim_after_us: None,
+            dim_level: 0,
+            dimmed: false,
+        }
+    }
+
+    /// The level picked with [`set_brightness`](Self::set_brightness), the
+    /// output can be lower while dimmed or fading
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// The current output level
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn is_dimmed(&self) -> bool {
+        self.dimmed
+    }
+
+    /// How long fades take
+    pub fn set_fade_time(&mut self, fade_us: u64) {
+        self.fade_us = fade_us;
+    }
+
+    /// Fade to `dim_level` once there's been no input for `after_us`,
+    /// `None` turns dimming off
+    pub fn set_auto_dim(&mut self, after_us: Option<u64>, dim_level: u8) {
+        self.dim_after_us = after_us;
+        self.dim_level = dim_level;
+    }
+
+    /// Fade to a new brightness
+    pub fn set_brightness(&mut self, brightness: u8, now_us: u64) {
+        self.brightness = brightness;
+        if !self.dimmed {
+            self.fade_to(brightness, now_us);
+        }
+    }
+
+    /// Step the brightness up or down, e.g. from a settings menu
+    pub fn adjust(&mut self, delta: i16, now_us: u64) {
+        let brightness = (self.brightness as i16 + delta).clamp(0, MAX_BRIGHTNESS as i16) as u8;
+        self.set_brightness(brightness, now_us);
+    }
+
+    /// Record user activity, this brings the backlight back if it was dimmed
+    pub fn input(&mut self, now_us: u64) {
+        self.last_input_us = now_us;
+        if self.dimmed {
+            self.dimmed = false;
+            self.fade_to(self.brightness, now_us);
+        }
+    }
+
+    /// Returns the level the backlight should be at now
+    pub fn update(&mut self, now_us: u64) -> u8 {
+        if let Some(after) = self.dim_after_us {
+            if !self.dimmed && now_us.saturating_sub(self.last_input_us) >= after {
+                self.dimmed = true;
+                self.fade_to(self.dim_level.min(self.brightness), now_us);
+            }
+        }
+
+        if let Some(fade) = self.fade {
+            self.level = fade.level_at(now_us);
+            if fade.done(now_us) {
+                self.fade = None;
+            }
+        }
+
+        self.level
+    }
+
+    fn fade_to(&mut self, to: u8, now_us: u64) {
+        self.fade = Some(Fade {
+            from: self.level,
+            to,
+            start_us: now_us,
+            duration_us: self.fade_us,
+        });
+    }
+}
+
+impl Default for BacklightControl {
+    fn default() -> Self {
+        Self::new(MAX_BRIGHTNESS)
+    }
+}
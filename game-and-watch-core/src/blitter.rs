@@ -0,0 +1,274 @@
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+use crate::framebuffer::TargetPixelType;
+
+/// Block operations on a framebuffer of `P` pixels, `dst_width` pixels
+/// wide.
+///
+/// `area` is always inside the destination buffer and `src_origin` + the
+/// size of `area` always inside the source, callers clip beforehand.
+pub trait Blitter<P: Copy = TargetPixelType> {
+    /// Fill `area` with a solid colour
+    fn fill(&mut self, dst: &mut [P], dst_width: usize, area: &Rectangle, color: P);
+
+    /// Copy an image in the same format into `area`
+    fn copy(
+        &mut self,
+        src: &[P],
+        src_width: usize,
+        src_origin: Point,
+        dst: &mut [P],
+        dst_width: usize,
+        area: &Rectangle,
+    );
+}
+
+/// Blending on RGB565 framebuffers
+pub trait BlendBlitter: Blitter<TargetPixelType> {
+    /// Alpha blend an ARGB8888 image over `area`
+    fn blend_argb8888(
+        &mut self,
+        src: &[u32],
+        src_width: usize,
+        src_origin: Point,
+        dst: &mut [TargetPixelType],
+        dst_width: usize,
+        area: &Rectangle,
+    );
+}
+
+/// Index of the first pixel of `area` and the row length, in pixels
+pub fn span(area: &Rectangle, width: usize) -> (usize, usize) {
+    let start = area.top_left.y as usize * width + area.top_left.x as usize;
+    (start, area.size.width as usize)
+}
+
+/// Expand a 5 or 6 bit channel to 8 bits by repeating the top bits,
+/// the way the DMA2D pixel format converter does it
+fn expand(value: u16, bits: u32) -> u32 {
+    let value = value as u32;
+    (value << (8 - bits)) | (value >> (2 * bits - 8))
+}
+
+/// Blend one ARGB8888 pixel over an RGB565 pixel using the DMA2D formula
+/// for an opaque background: out = (fg * a + bg * (255 - a)) / 255
+pub fn blend_pixel(fg: u32, bg: TargetPixelType) -> TargetPixelType {
+    let alpha = fg >> 24;
+    match alpha {
+        0 => return bg,
+        255 => return rgb888_to_565(fg),
+        _ => (),
+    }
+
+    let bg = [expand(bg >> 11, 5), expand((bg >> 5) & 0x3f, 6), expand(bg & 0x1f, 5)];
+    let fg = [(fg >> 16) & 0xff, (fg >> 8) & 0xff, fg & 0xff];
+    let mix = |f: u32, b: u32| (f * alpha + b * (255 - alpha)) / 255;
+
+    rgb888_to_565((mix(fg[0], bg[0]) << 16) | (mix(fg[1], bg[1]) << 8) | mix(fg[2], bg[2]))
+}
+
+/// Truncate an (A)RGB8888 pixel to RGB565
+pub fn rgb888_to_565(color: u32) -> TargetPixelType {
+    let r = (color >> 19) & 0x1f;
+    let g = (color >> 10) & 0x3f;
+    let b = (color >> 3) & 0x1f;
+    ((r << 11) | (g << 5) | b) as TargetPixelType
+}
+
+/// Plain CPU implementation. Gives the same output as the DMA2D and is
+/// what the host tools use.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SoftwareBlitter;
+
+impl<P: Copy> Blitter<P> for SoftwareBlitter {
+    fn fill(&mut self, dst: &mut [P], dst_width: usize, area: &Rectangle, color: P) {
+        let (start, len) = span(area, dst_width);
+
+        for row in 0..area.size.height as usize {
+            let line = start + row * dst_width;
+            dst[line..line + len].fill(color);
+        }
+    }
+
+    fn copy(
+        &mut self,
+        src: &[P],
+        src_width: usize,
+        src_origin: Point,
+        dst: &mut [P],
+        dst_width: usize,
+        area: &Rectangle,
+    ) {
+        let (dst_start, len) = span(area, dst_width);
+        let (src_start, _) = span(&Rectangle::new(src_origin, area.size), src_width);
+
+        for row in 0..area.size.height as usize {
+            let d = dst_start + row * dst_width;
+            let s = src_start + row * src_width;
+            dst[d..d + len].copy_from_slice(&src[s..s + len]);
+        }
+    }
+}
+
+impl BlendBlitter for SoftwareBlitter {
+    fn blend_argb8888(
+        &mut self,
+        src: &[u32],
+        src_width: usize,
+        src_origin: Point,
+        dst: &mut [TargetPixelType],
+        dst_width: usize,
+        area: &Rectangle,
+    ) {
+        let (dst_start, len) = span(area, dst_width);
+        let (src_start, _) = span(&Rectangle::new(src_origin, area.size), src_width);
+
+        for row in 0..area.size.height as usize {
+            let d = dst_start + row * dst_width;
+            let s = src_start + row * src_width;
+            for (out, &fg) in dst[d..d + len].iter_mut().zip(&src[s..s + len]) {
+                *out = blend_pixel(fg, *out);
+            }
+        }
+    }
+}
+
+/// ARGB8888 foreground, RGB565 background and what blending the one over
+/// the other has to give, at alpha 0, 128, 255 and next to them
+pub const BLEND_VECTORS: [(u32, TargetPixelType, TargetPixelType); 10] = [
+    (0x00ff_ffff, 0x1234, 0x1234),
+    (0x0000_0000, 0xffff, 0xffff),
+    (0x80ff_ffff, 0x0000, 0x8410),
+    (0x8000_0000, 0xffff, 0x7bef),
+    (0x80ff_0000, 0x001f, 0x800f),
+    (0x8012_3456, 0x7bef, 0x42cd),
+    (0xff12_3456, 0xffff, 0x11aa),
+    (0xff00_ff00, 0xf800, 0x07e0),
+    (0x01ff_ffff, 0x0000, 0x0000),
+    (0xfe00_0000, 0xffff, 0x0000),
+];
+
+/// Which part of [`check_blitter`] came out wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BlitterMismatch {
+    Fill,
+    Copy,
+    /// Index into [`BLEND_VECTORS`]
+    Blend(usize),
+}
+
+static COPY_SRC: [TargetPixelType; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+/// Run a fill, a copy and [`BLEND_VECTORS`] through `blitter` and compare
+/// with known results, so a hardware blitter can be checked on the device.
+/// The buffers are on the stack, which the blitter has to be able to reach.
+pub fn check_blitter<B: BlendBlitter>(blitter: &mut B) -> Result<(), BlitterMismatch> {
+    // 3x2 inside a 5x4 buffer, the rest has to stay as it was
+    let mut dst = [0x1111; 20];
+    blitter.fill(&mut dst, 5, &Rectangle::new(Point::new(1, 1), Size::new(3, 2)), 0xabcd);
+    #[rustfmt::skip]
+    let filled = [
+        0x1111, 0x1111, 0x1111, 0x1111, 0x1111,
+        0x1111, 0xabcd, 0xabcd, 0xabcd, 0x1111,
+        0x1111, 0xabcd, 0xabcd, 0xabcd, 0x1111,
+        0x1111, 0x1111, 0x1111, 0x1111, 0x1111,
+    ];
+    if dst != filled {
+        return Err(BlitterMismatch::Fill);
+    }
+
+    // 2x2 from (1, 1) of a 4x3 image to (2, 1) of a 5x4 buffer
+    let mut dst = [0; 20];
+    blitter.copy(&COPY_SRC, 4, Point::new(1, 1), &mut dst, 5, &Rectangle::new(Point::new(2, 1), Size::new(2, 2)));
+    #[rustfmt::skip]
+    let copied = [
+        0, 0, 0, 0, 0,
+        0, 0, 6, 7, 0,
+        0, 0, 10, 11, 0,
+        0, 0, 0, 0, 0,
+    ];
+    if dst != copied {
+        return Err(BlitterMismatch::Copy);
+    }
+
+    const N: usize = BLEND_VECTORS.len();
+    let src = BLEND_VECTORS.map(|(fg, _, _)| fg);
+    let mut dst = BLEND_VECTORS.map(|(_, bg, _)| bg);
+    blitter.blend_argb8888(&src, N, Point::zero(), &mut dst, N, &Rectangle::new(Point::zero(), Size::new(N as u32, 1)));
+    match dst.iter().zip(&BLEND_VECTORS).position(|(&out, &(_, _, expected))| out != expected) {
+        Some(i) => Err(BlitterMismatch::Blend(i)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_repeats_the_top_bits() {
+        assert_eq!([0, 1, 0x10, 0x1f].map(|v| expand(v, 5)), [0x00, 0x08, 0x84, 0xff]);
+        assert_eq!([0, 1, 0x20, 0x3f].map(|v| expand(v, 6)), [0x00, 0x04, 0x82, 0xff]);
+    }
+
+    #[test]
+    fn rgb888_to_565_truncates() {
+        assert_eq!(rgb888_to_565(0xff_ffff), 0xffff);
+        assert_eq!(rgb888_to_565(0x07_03_07), 0);
+        assert_eq!(rgb888_to_565(0xff12_3456), 0x11aa);
+    }
+
+    #[test]
+    fn blend_pixel_vectors() {
+        for (i, &(fg, bg, expected)) in BLEND_VECTORS.iter().enumerate() {
+            assert_eq!(blend_pixel(fg, bg), expected, "vector {i}: {fg:08x} over {bg:04x}");
+        }
+    }
+
+    #[test]
+    fn software_blitter_passes_the_check() {
+        assert_eq!(check_blitter(&mut SoftwareBlitter), Ok(()));
+    }
+
+    /// Copies the foreground instead of blending it
+    struct NoBlend;
+
+    impl Blitter for NoBlend {
+        fn fill(&mut self, dst: &mut [TargetPixelType], dst_width: usize, area: &Rectangle, color: TargetPixelType) {
+            SoftwareBlitter.fill(dst, dst_width, area, color);
+        }
+
+        fn copy(
+            &mut self,
+            src: &[TargetPixelType],
+            src_width: usize,
+            src_origin: Point,
+            dst: &mut [TargetPixelType],
+            dst_width: usize,
+            area: &Rectangle,
+        ) {
+            SoftwareBlitter.copy(src, src_width, src_origin, dst, dst_width, area);
+        }
+    }
+
+    impl BlendBlitter for NoBlend {
+        fn blend_argb8888(
+            &mut self,
+            src: &[u32],
+            src_width: usize,
+            src_origin: Point,
+            dst: &mut [TargetPixelType],
+            dst_width: usize,
+            area: &Rectangle,
+        ) {
+            let src: [TargetPixelType; BLEND_VECTORS.len()] = core::array::from_fn(|i| rgb888_to_565(src[i]));
+            SoftwareBlitter.copy(&src, src_width, src_origin, dst, dst_width, area);
+        }
+    }
+
+    #[test]
+    fn check_finds_a_wrong_blend() {
+        assert_eq!(check_blitter(&mut NoBlend), Err(BlitterMismatch::Blend(0)));
+    }
+}
@@ -0,0 +1,160 @@
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+/// Smallest rectangle covering both `a` and `b`
+pub fn union(a: &Rectangle, b: &Rectangle) -> Rectangle {
+    if a.is_zero_sized() {
+        return *b;
+    }
+    if b.is_zero_sized() {
+        return *a;
+    }
+
+    let top_left = Point::new(a.top_left.x.min(b.top_left.x), a.top_left.y.min(b.top_left.y));
+    let a_end = a.top_left + a.size;
+    let b_end = b.top_left + b.size;
+    let bottom_right = Point::new(a_end.x.max(b_end.x), a_end.y.max(b_end.y));
+
+    Rectangle::with_corners(top_left, bottom_right - Point::new(1, 1))
+}
+
+fn area(rect: &Rectangle) -> u32 {
+    rect.size.width * rect.size.height
+}
+
+/// A small set of rectangles that need redrawing. Once it's full new
+/// rectangles get merged into whichever existing one grows the least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRegion<const M: usize = 8> {
+    rects: [Rectangle; M],
+    len: usize,
+}
+
+impl<const M: usize> DirtyRegion<M> {
+    pub const fn new() -> Self {
+        Self {
+            rects: [Rectangle::zero(); M],
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rectangle> {
+        self.rects[..self.len].iter()
+    }
+
+    pub fn bounding_box(&self) -> Rectangle {
+        self.iter().fold(Rectangle::zero(), |acc, r| union(&acc, r))
+    }
+
+    pub fn add(&mut self, rect: Rectangle) {
+        if rect.is_zero_sized() {
+            return;
+        }
+
+        // already covered
+        if self.iter().any(|r| r.intersection(&rect) == rect) {
+            return;
+        }
+
+        if self.len < M {
+            self.rects[self.len] = rect;
+            self.len += 1;
+            return;
+        }
+
+        let (best, _) = self.rects.iter()
+            .enumerate()
+            .map(|(i, r)| (i, area(&union(r, &rect)) - area(r)))
+            .min_by_key(|&(_, growth)| growth)
+            .unwrap();
+        self.rects[best] = union(&self.rects[best], &rect);
+    }
+
+    pub fn extend(&mut self, other: &DirtyRegion<M>) {
+        for r in other.iter() {
+            self.add(*r);
+        }
+    }
+}
+
+impl<const M: usize> Default for DirtyRegion<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Rectangle {
+        Rectangle::new(Point::new(x, y), Size::new(width, height))
+    }
+
+    #[test]
+    fn union_covers_both() {
+        assert_eq!(union(&rect(0, 0, 2, 2), &rect(3, 1, 2, 3)), rect(0, 0, 5, 4));
+        assert_eq!(union(&rect(-2, 4, 1, 1), &rect(1, 1, 1, 1)), rect(-2, 1, 4, 4));
+        // one inside the other
+        assert_eq!(union(&rect(0, 0, 5, 5), &rect(1, 1, 2, 2)), rect(0, 0, 5, 5));
+    }
+
+    #[test]
+    fn union_ignores_empty_rectangles() {
+        // an empty rectangle somewhere else doesn't stretch the result
+        assert_eq!(union(&rect(10, 10, 0, 0), &rect(1, 1, 2, 2)), rect(1, 1, 2, 2));
+        assert_eq!(union(&rect(1, 1, 2, 2), &rect(-5, 0, 3, 0)), rect(1, 1, 2, 2));
+        assert!(union(&Rectangle::zero(), &Rectangle::zero()).is_zero_sized());
+    }
+
+    #[test]
+    fn rectangles_are_kept_apart_while_there_is_room() {
+        let mut dirty = DirtyRegion::<4>::new();
+        assert!(dirty.is_empty());
+        dirty.add(rect(0, 0, 2, 2));
+        dirty.add(rect(10, 10, 2, 2));
+        dirty.add(Rectangle::zero());
+        // already covered by the first one
+        dirty.add(rect(1, 1, 1, 1));
+
+        assert!(dirty.iter().eq([rect(0, 0, 2, 2), rect(10, 10, 2, 2)].iter()));
+        assert_eq!(dirty.bounding_box(), rect(0, 0, 12, 12));
+
+        dirty.clear();
+        assert!(dirty.is_empty());
+        assert!(dirty.bounding_box().is_zero_sized());
+    }
+
+    #[test]
+    fn a_full_region_merges_into_the_closest_rectangle() {
+        let mut dirty = DirtyRegion::<2>::new();
+        dirty.add(rect(0, 0, 2, 2));
+        dirty.add(rect(10, 10, 2, 2));
+        // next to the first one, far from the second
+        dirty.add(rect(2, 0, 2, 2));
+        assert!(dirty.iter().eq([rect(0, 0, 4, 2), rect(10, 10, 2, 2)].iter()));
+
+        dirty.add(rect(9, 12, 1, 1));
+        assert!(dirty.iter().eq([rect(0, 0, 4, 2), rect(9, 10, 3, 3)].iter()));
+    }
+
+    #[test]
+    fn extend_adds_every_rectangle() {
+        let mut a = DirtyRegion::<2>::new();
+        a.add(rect(0, 0, 2, 2));
+        let mut b = DirtyRegion::<2>::new();
+        b.add(rect(1, 1, 1, 1));
+        b.add(rect(5, 5, 1, 1));
+        b.add(rect(6, 5, 1, 1));
+
+        a.extend(&b);
+        assert!(a.iter().eq([rect(0, 0, 2, 2), rect(5, 5, 2, 1)].iter()));
+    }
+}
@@ -0,0 +1,160 @@
+use crate::backlight::MAX_BRIGHTNESS;
+use crate::framebuffer::{Frame, FrameSink, TargetPixelType};
+
+/// Everything the game needs from the display hardware: showing frames
+/// of `P` pixels, the backlight and panel power
+#[allow(async_fn_in_trait)]
+pub trait DisplayBackend<P = TargetPixelType>: FrameSink<P> {
+    fn set_backlight(&mut self, on: bool);
+
+    fn backlight(&self) -> bool;
+
+    /// Level the backlight runs at while it's on, 0..=255. Doesn't turn
+    /// it on by itself.
+    fn set_brightness(&mut self, level: u8);
+
+    fn brightness(&self) -> u8;
+
+    fn toggle_backlight(&mut self) {
+        let on = self.backlight();
+        self.set_backlight(!on);
+    }
+
+    /// Bring the panel up, or back from a suspend
+    async fn power_on(&mut self) -> Result<(), Self::Error>;
+
+    /// Get the panel into a safe low power state
+    async fn power_off(&mut self) -> Result<(), Self::Error>;
+
+    fn is_powered(&self) -> bool;
+}
+
+/// A display that only remembers what was done to it. Presented frames
+/// are copied into `frame` so they can be inspected afterwards.
+pub struct MockDisplay<'a> {
+    frame: &'a mut [TargetPixelType],
+    presented: usize,
+    backlight: bool,
+    brightness: u8,
+    powered: bool,
+}
+
+impl<'a> MockDisplay<'a> {
+    pub fn new(frame: &'a mut [TargetPixelType]) -> Self {
+        Self {
+            frame,
+            presented: 0,
+            backlight: false,
+            brightness: MAX_BRIGHTNESS,
+            powered: false,
+        }
+    }
+
+    /// The last presented frame
+    pub fn frame(&self) -> &[TargetPixelType] {
+        self.frame
+    }
+
+    /// Number of frames presented so far
+    pub fn presented(&self) -> usize {
+        self.presented
+    }
+}
+
+/// Returned when a frame doesn't match the size of the mock's buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSizeMismatch;
+
+impl<'a> FrameSink for MockDisplay<'a> {
+    type Error = FrameSizeMismatch;
+
+    fn queue(&mut self, frame: Frame<'_>) -> Result<(), Self::Error> {
+        let width = frame.size.width as usize;
+        if width * frame.size.height as usize != self.frame.len() {
+            return Err(FrameSizeMismatch);
+        }
+        for (out, row) in self.frame.chunks_exact_mut(width).zip(frame.rows()) {
+            out.copy_from_slice(row);
+        }
+        self.presented += 1;
+        Ok(())
+    }
+
+    async fn wait_for_vblank(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<'a> DisplayBackend for MockDisplay<'a> {
+    fn set_backlight(&mut self, on: bool) {
+        self.backlight = on;
+    }
+
+    fn backlight(&self) -> bool {
+        self.backlight
+    }
+
+    fn set_brightness(&mut self, level: u8) {
+        self.brightness = level;
+    }
+
+    fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    async fn power_on(&mut self) -> Result<(), Self::Error> {
+        self.powered = true;
+        self.backlight = true;
+        Ok(())
+    }
+
+    async fn power_off(&mut self) -> Result<(), Self::Error> {
+        self.powered = false;
+        self.backlight = false;
+        Ok(())
+    }
+
+    fn is_powered(&self) -> bool {
+        self.powered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_graphics::prelude::Size;
+
+    use super::*;
+
+    #[test]
+    fn power_off_takes_the_backlight_with_it() {
+        let mut frame = [0; 4];
+        let mut display = MockDisplay::new(&mut frame);
+        assert!(!display.is_powered() && !display.backlight());
+
+        block_on(display.power_on()).unwrap();
+        assert!(display.is_powered() && display.backlight());
+        display.toggle_backlight();
+        assert!(!display.backlight());
+        display.toggle_backlight();
+
+        block_on(display.power_off()).unwrap();
+        assert!(!display.is_powered() && !display.backlight());
+    }
+
+    #[test]
+    fn rejects_frames_of_another_size() {
+        let mut frame = [0; 4];
+        let mut display = MockDisplay::new(&mut frame);
+        let pixels = [1, 2, 3, 4, 5, 6];
+
+        assert_eq!(display.queue(Frame::new(&pixels, Size::new(3, 2))), Err(FrameSizeMismatch));
+        assert_eq!(display.presented(), 0);
+
+        // a 2x2 window into a 3 wide buffer
+        let window = Frame { pixels: &pixels[1..], stride: 3, size: Size::new(2, 2) };
+        display.queue(window).unwrap();
+        assert_eq!(display.frame(), [2, 3, 5, 6]);
+        assert_eq!(display.presented(), 1);
+    }
+}
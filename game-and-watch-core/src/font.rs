@@ -0,0 +1,390 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::Rectangle,
+    text::{
+        renderer::{CharacterStyle, TextMetrics, TextRenderer},
+        Baseline, DecorationColor,
+    },
+};
+
+// Proportional 1 bit fonts in a small binary format, so they can sit in
+// the memory mapped external flash and be used in place. The host `font`
+// tool makes them from BDF files.
+//
+// All numbers are little endian.
+//
+//   header, 8 bytes
+//     0  b"GWF1"
+//     4  u8  line height
+//     5  u8  ascent, baseline to the top of the line
+//     6  u16 number of glyphs
+//   glyphs, 16 bytes each, sorted by code point
+//     0  u32 code point
+//     4  u32 bitmap offset, from the end of the glyph table
+//     8  u8  bitmap width
+//     9  u8  bitmap height
+//     10 i8  x offset of the bitmap from the pen position
+//     11 i8  y offset of the bitmap top from the baseline, negative is up
+//     12 u8  advance
+//     13 3 bytes reserved
+//   bitmaps, 1 bit per pixel, MSB first, each row padded to a byte
+
+pub const FONT_MAGIC: [u8; 4] = *b"GWF1";
+pub const FONT_HEADER_SIZE: usize = 8;
+pub const GLYPH_ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FontError {
+    BadMagic,
+    /// The data ends before the glyph table or a bitmap does
+    Truncated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Glyph<'a> {
+    pub width: u8,
+    pub height: u8,
+    pub x_offset: i8,
+    pub y_offset: i8,
+    pub advance: u8,
+    bitmap: &'a [u8],
+}
+
+impl<'a> Glyph<'a> {
+    fn row_bytes(&self) -> usize {
+        (self.width as usize).div_ceil(8)
+    }
+
+    /// Draw with the pen at `origin` on the baseline, only the set pixels
+    /// are touched
+    pub fn draw<D: DrawTarget>(&self, origin: Point, color: D::Color, target: &mut D) -> Result<(), D::Error> {
+        let top_left = origin + Point::new(self.x_offset as i32, self.y_offset as i32);
+        let row_bytes = self.row_bytes();
+
+        let pixels = (0..self.height as usize).flat_map(move |y| {
+            let row = &self.bitmap[y * row_bytes..(y + 1) * row_bytes];
+            (0..self.width as usize)
+                .filter(move |x| row[x / 8] & (0x80 >> (x % 8)) != 0)
+                .map(move |x| Pixel(top_left + Point::new(x as i32, y as i32), color))
+        });
+        target.draw_iter(pixels)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BitmapFont<'a> {
+    data: &'a [u8],
+    line_height: u8,
+    ascent: u8,
+    glyphs: usize,
+    /// Drawn for characters the font doesn't have
+    fallback: Option<Glyph<'a>>,
+}
+
+impl<'a> BitmapFont<'a> {
+    /// Use a font in place, e.g. straight from memory mapped flash
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, FontError> {
+        if data.len() < FONT_HEADER_SIZE {
+            return Err(FontError::Truncated);
+        }
+        if data[0..4] != FONT_MAGIC {
+            return Err(FontError::BadMagic);
+        }
+        let glyphs = u16::from_le_bytes([data[6], data[7]]) as usize;
+        if data.len() < FONT_HEADER_SIZE + glyphs * GLYPH_ENTRY_SIZE {
+            return Err(FontError::Truncated);
+        }
+
+        let mut font = Self {
+            data,
+            line_height: data[4],
+            ascent: data[5],
+            glyphs,
+            fallback: None,
+        };
+        for i in 0..glyphs {
+            font.entry(i)?;
+        }
+        font.fallback = font.glyph('\u{fffd}').or_else(|| font.glyph('?'));
+        Ok(font)
+    }
+
+    pub fn line_height(&self) -> u32 {
+        self.line_height as u32
+    }
+
+    pub fn ascent(&self) -> u32 {
+        self.ascent as u32
+    }
+
+    fn code_point(&self, i: usize) -> u32 {
+        let at = FONT_HEADER_SIZE + i * GLYPH_ENTRY_SIZE;
+        u32::from_le_bytes(self.data[at..at + 4].try_into().unwrap())
+    }
+
+    fn entry(&self, i: usize) -> Result<Glyph<'a>, FontError> {
+        let at = FONT_HEADER_SIZE + i * GLYPH_ENTRY_SIZE;
+        let e = &self.data[at..at + GLYPH_ENTRY_SIZE];
+        let offset = u32::from_le_bytes(e[4..8].try_into().unwrap()) as usize;
+        let (width, height) = (e[8], e[9]);
+
+        // the offset comes from flash, it mustn't wrap around on 32 bits
+        let len = (width as usize).div_ceil(8) * height as usize;
+        let start = (FONT_HEADER_SIZE + self.glyphs * GLYPH_ENTRY_SIZE).checked_add(offset);
+        let end = start.and_then(|start| start.checked_add(len)).ok_or(FontError::Truncated)?;
+        let bitmap = self.data.get(end - len..end).ok_or(FontError::Truncated)?;
+
+        Ok(Glyph {
+            width,
+            height,
+            x_offset: e[10] as i8,
+            y_offset: e[11] as i8,
+            advance: e[12],
+            bitmap,
+        })
+    }
+
+    /// The glyph for `c`, if the font has one
+    pub fn glyph(&self, c: char) -> Option<Glyph<'a>> {
+        let (mut lo, mut hi) = (0, self.glyphs);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.code_point(mid).cmp(&(c as u32)) {
+                core::cmp::Ordering::Less => lo = mid + 1,
+                core::cmp::Ordering::Greater => hi = mid,
+                // checked in from_bytes
+                core::cmp::Ordering::Equal => return self.entry(mid).ok(),
+            }
+        }
+        None
+    }
+
+    /// The glyph for `c`, or the replacement glyph
+    pub fn glyph_or_fallback(&self, c: char) -> Option<Glyph<'a>> {
+        self.glyph(c).or(self.fallback)
+    }
+
+    /// How far the pen moves for `c`
+    pub fn advance(&self, c: char) -> u32 {
+        self.glyph_or_fallback(c).map_or(0, |g| g.advance as u32)
+    }
+
+    /// Width of `text` on one line, newlines aren't handled
+    pub fn text_width(&self, text: &str) -> u32 {
+        text.chars().map(|c| self.advance(c)).sum()
+    }
+}
+
+/// Text style for a [`BitmapFont`], works with `embedded_graphics::text::Text`
+#[derive(Debug, Clone, Copy)]
+pub struct BitmapTextStyle<'a, C> {
+    pub font: &'a BitmapFont<'a>,
+    pub text_color: C,
+    /// Fills the line box behind the text
+    pub background_color: Option<C>,
+}
+
+impl<'a, C: PixelColor> BitmapTextStyle<'a, C> {
+    pub fn new(font: &'a BitmapFont<'a>, text_color: C) -> Self {
+        Self {
+            font,
+            text_color,
+            background_color: None,
+        }
+    }
+
+    /// Baseline y for `position` measured from `baseline`
+    fn baseline_y(&self, position: Point, baseline: Baseline) -> i32 {
+        let line_height = self.font.line_height as i32;
+        let ascent = self.font.ascent as i32;
+        match baseline {
+            Baseline::Top => position.y + ascent,
+            Baseline::Bottom => position.y - (line_height - 1) + ascent,
+            Baseline::Middle => position.y - (line_height - 1) / 2 + ascent,
+            Baseline::Alphabetic => position.y,
+        }
+    }
+
+    fn line_box(&self, x: i32, baseline_y: i32, width: u32) -> Rectangle {
+        Rectangle::new(
+            Point::new(x, baseline_y - self.font.ascent as i32),
+            Size::new(width, self.font.line_height as u32),
+        )
+    }
+}
+
+impl<'a, C: PixelColor> TextRenderer for BitmapTextStyle<'a, C> {
+    type Color = C;
+
+    fn draw_string<D>(&self, text: &str, position: Point, baseline: Baseline, target: &mut D) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let y = self.baseline_y(position, baseline);
+        if let Some(background) = self.background_color {
+            target.fill_solid(&self.line_box(position.x, y, self.font.text_width(text)), background)?;
+        }
+
+        let mut pen = Point::new(position.x, y);
+        for c in text.chars() {
+            if let Some(glyph) = self.font.glyph_or_fallback(c) {
+                glyph.draw(pen, self.text_color, target)?;
+                pen.x += glyph.advance as i32;
+            }
+        }
+        Ok(Point::new(pen.x, position.y))
+    }
+
+    fn draw_whitespace<D>(&self, width: u32, position: Point, baseline: Baseline, target: &mut D) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        if let Some(background) = self.background_color {
+            let y = self.baseline_y(position, baseline);
+            target.fill_solid(&self.line_box(position.x, y, width), background)?;
+        }
+        Ok(position + Point::new(width as i32, 0))
+    }
+
+    fn measure_string(&self, text: &str, position: Point, baseline: Baseline) -> TextMetrics {
+        let width = self.font.text_width(text);
+        let y = self.baseline_y(position, baseline);
+        TextMetrics {
+            bounding_box: self.line_box(position.x, y, width),
+            next_position: position + Point::new(width as i32, 0),
+        }
+    }
+
+    fn line_height(&self) -> u32 {
+        self.font.line_height as u32
+    }
+}
+
+impl<'a, C: PixelColor> CharacterStyle for BitmapTextStyle<'a, C> {
+    type Color = C;
+
+    fn set_text_color(&mut self, text_color: Option<Self::Color>) {
+        if let Some(color) = text_color {
+            self.text_color = color;
+        }
+    }
+
+    fn set_background_color(&mut self, background_color: Option<Self::Color>) {
+        self.background_color = background_color;
+    }
+
+    fn set_underline_color(&mut self, _underline_color: DecorationColor<Self::Color>) {}
+
+    fn set_strikethrough_color(&mut self, _strikethrough_color: DecorationColor<Self::Color>) {}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use embedded_graphics::{mock_display::MockDisplay, pixelcolor::BinaryColor, text::Text};
+
+    use super::*;
+
+    pub(crate) const LINE_HEIGHT: u8 = 10;
+    pub(crate) const ASCENT: u8 = 8;
+
+    /// A GWF1 font of solid boxes, `(char, width, advance)` each. The
+    /// boxes sit on the baseline and are as tall as the ascent.
+    pub(crate) fn font_bytes(glyphs: &[(char, u8, u8)]) -> Vec<u8> {
+        let mut glyphs = glyphs.to_vec();
+        glyphs.sort_by_key(|&(c, _, _)| c);
+
+        let mut data = FONT_MAGIC.to_vec();
+        data.extend_from_slice(&[LINE_HEIGHT, ASCENT]);
+        data.extend_from_slice(&(glyphs.len() as u16).to_le_bytes());
+        let mut bitmaps = Vec::new();
+        for &(c, width, advance) in &glyphs {
+            data.extend_from_slice(&(c as u32).to_le_bytes());
+            data.extend_from_slice(&(bitmaps.len() as u32).to_le_bytes());
+            data.extend_from_slice(&[width, ASCENT, 0, -(ASCENT as i8) as u8, advance, 0, 0, 0]);
+            for _ in 0..ASCENT {
+                for byte in 0..(width as usize).div_ceil(8) {
+                    let bits = (width as usize - byte * 8).min(8);
+                    bitmaps.push((0xff00u16 >> bits) as u8);
+                }
+            }
+        }
+        data.extend_from_slice(&bitmaps);
+        data
+    }
+
+    /// Latin letters 5 wide on a 6 advance, kana 10 wide, space 4
+    pub(crate) fn test_font() -> Vec<u8> {
+        let mut glyphs: Vec<(char, u8, u8)> = ('a'..='z').map(|c| (c, 5, 6)).collect();
+        glyphs.extend([(' ', 0, 4), ('?', 5, 6), ('\u{e9}', 5, 6), ('\u{fc}', 5, 6)]);
+        glyphs.extend(['\u{3002}', '\u{3042}', '\u{3044}', '\u{3046}', '\u{3048}', '\u{30ab}'].map(|c| (c, 10, 10)));
+        font_bytes(&glyphs)
+    }
+
+    #[test]
+    fn reads_the_header_and_glyphs() {
+        let data = test_font();
+        let font = BitmapFont::from_bytes(&data).unwrap();
+        assert_eq!((font.line_height(), font.ascent()), (10, 8));
+
+        let glyph = font.glyph('b').unwrap();
+        assert_eq!((glyph.width, glyph.height, glyph.x_offset, glyph.y_offset, glyph.advance), (5, 8, 0, -8, 6));
+        assert_eq!(glyph.bitmap, [0xf8; 8]);
+        assert_eq!(font.glyph('\u{3042}').unwrap().bitmap, [0xff, 0xc0].repeat(8));
+        assert_eq!(font.glyph('A'), None);
+    }
+
+    #[test]
+    fn measures_latin_1_and_kana() {
+        let data = test_font();
+        let font = BitmapFont::from_bytes(&data).unwrap();
+        assert_eq!(font.text_width("caf\u{e9} m\u{fc}de"), 6 * 8 + 4);
+        assert_eq!(font.text_width("\u{3042}\u{3044}\u{3002}"), 30);
+        // characters it doesn't have take the '?'
+        assert_eq!(font.glyph_or_fallback('Z'), font.glyph('?'));
+        assert_eq!(font.text_width("ZZ"), 12);
+    }
+
+    #[test]
+    fn refuses_broken_fonts() {
+        let data = test_font();
+        assert_eq!(BitmapFont::from_bytes(&data[..7]).err(), Some(FontError::Truncated));
+        assert_eq!(BitmapFont::from_bytes(b"GWF2\x0a\x08\x00\x00").err(), Some(FontError::BadMagic));
+        // the glyph table, then the last bitmap, cut short
+        assert_eq!(BitmapFont::from_bytes(&data[..40]).err(), Some(FontError::Truncated));
+        assert_eq!(BitmapFont::from_bytes(&data[..data.len() - 1]).err(), Some(FontError::Truncated));
+
+        // bitmap offsets that run past the end or wrap around
+        for offset in [data.len() as u32, u32::MAX - 8, u32::MAX] {
+            let mut broken = data.clone();
+            broken[FONT_HEADER_SIZE + 4..FONT_HEADER_SIZE + 8].copy_from_slice(&offset.to_le_bytes());
+            assert_eq!(BitmapFont::from_bytes(&broken).err(), Some(FontError::Truncated), "{offset}");
+        }
+    }
+
+    #[test]
+    fn draws_on_the_baseline() {
+        let data = font_bytes(&[('i', 1, 2), ('?', 2, 3)]);
+        let font = BitmapFont::from_bytes(&data).unwrap();
+        let style = BitmapTextStyle::new(&font, BinaryColor::On);
+
+        let mut display = MockDisplay::new();
+        let end = Text::with_baseline("ii?", Point::new(1, 0), style, Baseline::Top).draw(&mut display).unwrap();
+        assert_eq!(end, Point::new(8, 0));
+        display.assert_pattern(&[
+            " # # ##", " # # ##", " # # ##", " # # ##", " # # ##", " # # ##", " # # ##", " # # ##",
+        ]);
+
+        // the background fills the whole line box
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        let style = BitmapTextStyle { background_color: Some(BinaryColor::Off), ..style };
+        Text::with_baseline("i", Point::new(0, 8), style, Baseline::Alphabetic).draw(&mut display).unwrap();
+        assert_eq!(display.affected_area(), Rectangle::new(Point::zero(), Size::new(2, 10)));
+    }
+}
@@ -0,0 +1,226 @@
+// Frame pacing bookkeeping. Time comes in as microseconds from whatever
+// clock the caller has, so this works the same on the MCU and on the host.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TargetFps {
+    Fps60,
+    Fps50,
+    Fps30,
+}
+
+impl TargetFps {
+    pub fn frame_period_us(self) -> u64 {
+        match self {
+            TargetFps::Fps60 => 1_000_000 / 60,
+            TargetFps::Fps50 => 1_000_000 / 50,
+            TargetFps::Fps30 => 1_000_000 / 30,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameStats {
+    /// Time between the last two presented frames
+    pub frame_time_us: u32,
+    /// Time the last frame spent in update/draw before it was ready
+    pub busy_time_us: u32,
+    /// Frame slots missed because a frame wasn't ready in time
+    pub dropped_frames: u32,
+    /// Frames presented so far
+    pub frames: u32,
+}
+
+impl FrameStats {
+    /// Share of the last frame spent working rather than waiting
+    pub fn cpu_busy_percent(&self) -> u8 {
+        if self.frame_time_us == 0 {
+            return 0;
+        }
+        (self.busy_time_us as u64 * 100 / self.frame_time_us as u64).min(100) as u8
+    }
+}
+
+/// Works out when each frame should be presented to hold a fixed rate.
+///
+/// Call [`work_done`](Self::work_done) once the frame is drawn, wait until
+/// the returned deadline, present, then call [`presented`](Self::presented).
+pub struct FramePacer {
+    target: TargetFps,
+    frame_start_us: Option<u64>,
+    deadline_us: u64,
+    stats: FrameStats,
+}
+
+impl FramePacer {
+    pub fn new(target: TargetFps) -> Self {
+        Self {
+            target,
+            frame_start_us: None,
+            deadline_us: 0,
+            stats: FrameStats::default(),
+        }
+    }
+
+    pub fn target(&self) -> TargetFps {
+        self.target
+    }
+
+    pub fn set_target(&mut self, target: TargetFps) {
+        self.target = target;
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// Returns the time the finished frame should be presented at
+    pub fn work_done(&mut self, now_us: u64) -> u64 {
+        let period = self.target.frame_period_us();
+
+        let Some(frame_start) = self.frame_start_us else {
+            // first frame, nothing to pace against yet
+            self.deadline_us = now_us;
+            return now_us;
+        };
+
+        self.stats.busy_time_us = (now_us - frame_start) as u32;
+        self.deadline_us += period;
+
+        if now_us > self.deadline_us {
+            let missed = (now_us - self.deadline_us).div_ceil(period);
+            self.stats.dropped_frames += missed as u32;
+            self.deadline_us += missed * period;
+        }
+
+        self.deadline_us
+    }
+
+    /// Record that the frame is on screen, this also starts the next frame
+    pub fn presented(&mut self, now_us: u64) {
+        if let Some(frame_start) = self.frame_start_us {
+            self.stats.frame_time_us = (now_us - frame_start) as u32;
+        }
+        self.frame_start_us = Some(now_us);
+        self.stats.frames += 1;
+    }
+}
+
+/// Keeps track of the display's refresh from the vblanks it's told about,
+/// so frames can be paced on the display itself rather than on a timer
+#[derive(Debug, Clone, Copy)]
+pub struct RefreshClock {
+    last_vblank_us: Option<u64>,
+    period_us: u64,
+}
+
+impl RefreshClock {
+    /// Start from the nominal refresh period, it gets corrected from the
+    /// vblanks that come in
+    pub const fn new(period_us: u64) -> Self {
+        Self {
+            last_vblank_us: None,
+            period_us,
+        }
+    }
+
+    pub fn period_us(&self) -> u64 {
+        self.period_us
+    }
+
+    /// A vblank happened at `now_us`
+    pub fn vblank(&mut self, now_us: u64) {
+        if let Some(last) = self.last_vblank_us {
+            let delta = now_us - last;
+            // only back to back vblanks say anything about the period,
+            // averaged a bit to smooth out interrupt latency
+            if delta * 2 > self.period_us && delta * 2 < self.period_us * 3 {
+                self.period_us = (self.period_us * 7 + delta) / 8;
+            }
+        }
+        self.last_vblank_us = Some(now_us);
+    }
+
+    /// When the first vblank after `now_us` is due, `None` until one has
+    /// been seen
+    pub fn next_vblank(&self, now_us: u64) -> Option<u64> {
+        let last = self.last_vblank_us?;
+        let refreshes = now_us.saturating_sub(last) / self.period_us + 1;
+        Some(last + refreshes * self.period_us)
+    }
+
+    /// A frame queued now is shown at the next vblank. If that's more than
+    /// half a refresh before `deadline_us` the frame should wait for
+    /// another vblank first.
+    pub fn too_early(&self, deadline_us: u64, now_us: u64) -> bool {
+        match self.next_vblank(now_us) {
+            Some(next) => next + self.period_us / 2 < deadline_us,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFRESH: u64 = 16_667;
+
+    #[test]
+    fn pacer_drops_missed_slots() {
+        let mut pacer = FramePacer::new(TargetFps::Fps30);
+        let period = TargetFps::Fps30.frame_period_us();
+        assert_eq!(pacer.work_done(1_000), 1_000);
+        pacer.presented(1_000);
+
+        assert_eq!(pacer.work_done(11_000), 1_000 + period);
+        pacer.presented(1_000 + period);
+        assert_eq!(pacer.stats().busy_time_us, 10_000);
+        assert_eq!(pacer.stats().dropped_frames, 0);
+
+        // 2.5 periods of work miss two slots
+        let now = 1_000 + period + period * 5 / 2;
+        assert_eq!(pacer.work_done(now), 1_000 + period * 4);
+        assert_eq!(pacer.stats().dropped_frames, 2);
+    }
+
+    #[test]
+    fn next_vblank_follows_the_last_one() {
+        let mut clock = RefreshClock::new(REFRESH);
+        assert_eq!(clock.next_vblank(0), None);
+        assert!(clock.too_early(0, 0));
+
+        clock.vblank(1_000);
+        assert_eq!(clock.next_vblank(1_000), Some(1_000 + REFRESH));
+        assert_eq!(clock.next_vblank(1_000 + REFRESH + 5), Some(1_000 + REFRESH * 2));
+    }
+
+    #[test]
+    fn period_is_corrected_from_back_to_back_vblanks() {
+        let mut clock = RefreshClock::new(REFRESH);
+        let mut now = 0;
+        for _ in 0..100 {
+            clock.vblank(now);
+            now += 20_000;
+        }
+        assert!(clock.period_us().abs_diff(20_000) < 20);
+
+        // a gap of several refreshes is not a period
+        clock.vblank(now + 100_000);
+        assert!(clock.period_us().abs_diff(20_000) < 20);
+    }
+
+    #[test]
+    fn thirty_fps_on_a_sixty_hz_display() {
+        let mut clock = RefreshClock::new(REFRESH);
+        clock.vblank(0);
+
+        // work is done 5ms into the refresh, the slot is two refreshes on
+        let deadline = REFRESH * 2;
+        assert!(clock.too_early(deadline, 5_000));
+        clock.vblank(REFRESH);
+        // queued now it's shown on the vblank at the deadline
+        assert!(!clock.too_early(deadline, REFRESH + 100));
+    }
+}
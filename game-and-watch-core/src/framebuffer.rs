@@ -0,0 +1,799 @@
+use core::convert::Infallible;
+
+use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::prelude::*;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::primitives::Rectangle;
+
+use crate::blitter::{blend_pixel, span, BlendBlitter, Blitter, SoftwareBlitter};
+use crate::dirty::DirtyRegion;
+use crate::orientation::Orientation;
+
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 240;
+
+pub type TargetPixelType = u16;
+
+/// How pixels of a given colour type are stored in a framebuffer
+pub trait PixelStorage: Copy + Default {
+    type Color: PixelColor;
+
+    fn from_color(color: Self::Color) -> Self;
+}
+
+impl PixelStorage for TargetPixelType {
+    type Color = Rgb565;
+
+    fn from_color(color: Rgb565) -> Self {
+        color.into_storage()
+    }
+}
+
+/// The visible part of a framebuffer
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a, P = TargetPixelType> {
+    /// Pixels from the top left corner of the visible area on
+    pub pixels: &'a [P],
+    /// Distance between the starts of two rows, in pixels
+    pub stride: usize,
+    /// Size of the visible area
+    pub size: Size,
+}
+
+impl<'a, P> Frame<'a, P> {
+    /// A frame covering all of `pixels`
+    pub fn new(pixels: &'a [P], size: Size) -> Self {
+        Self {
+            pixels,
+            stride: size.width as usize,
+            size,
+        }
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [P]> + '_ {
+        let width = self.size.width as usize;
+        (0..self.size.height as usize).map(move |y| &self.pixels[y * self.stride..y * self.stride + width])
+    }
+}
+
+/// Something that can scan out a finished frame, e.g. the LTDC on the
+/// real hardware or a PNG writer on the host
+#[allow(async_fn_in_trait)]
+pub trait FrameSink<P = TargetPixelType> {
+    type Error;
+
+    /// Schedule `frame` to be shown from the next vertical blank on and
+    /// return straight away. The buffer must stay untouched until a later
+    /// frame is on screen.
+    fn queue(&mut self, frame: Frame<'_, P>) -> Result<(), Self::Error>;
+
+    /// Wait until the last queued frame is actually on screen
+    async fn wait_for_vblank(&mut self) -> Result<(), Self::Error>;
+
+    /// Queue `frame` and wait for it to be shown
+    async fn present(&mut self, frame: Frame<'_, P>) -> Result<(), Self::Error> {
+        self.queue(frame)?;
+        self.wait_for_vblank().await
+    }
+}
+
+/// A ring of `N` framebuffers, drawn to one at a time and handed to a
+/// [`FrameSink`] in order.
+///
+/// With two buffers `swap` has to wait for the flip before the next frame
+/// can be drawn. With three or more it returns as soon as the frame is
+/// queued and only waits for the previous flip, so drawing the next frame
+/// overlaps with waiting for vsync.
+///
+/// Areas passed to [`mark_dirty`](Self::mark_dirty) are remembered for
+/// every other buffer. When a buffer comes round again `swap` copies
+/// whatever changed since it was last drawn from the newest frame, so only
+/// the areas that change in the new frame need to be redrawn.
+///
+/// The buffers can be bigger than the screen. Drawing then happens in
+/// the whole virtual area and [`scroll_to`](Self::scroll_to) picks the part
+/// that gets shown, by pointing the display at a different start address
+/// instead of redrawing anything.
+///
+/// Drawing goes through an [`Orientation`], so with a 90 or 270 degree
+/// rotation the DrawTarget is 240x320 and portrait games can be played
+/// with the handheld turned sideways. The viewport and everything else that
+/// deals with the buffers directly stays in framebuffer coordinates.
+///
+/// `P` is the pixel storage, RGB565 by default.
+pub struct SwapChain<'a, const N: usize, B = SoftwareBlitter, P = TargetPixelType> {
+    buffers: [&'a mut [P]; N],
+    back: usize,
+    pending: bool,
+    size: Size,
+    visible: Size,
+    viewport: Point,
+    orientation: Orientation,
+    blitter: B,
+    damage: DirtyRegion,
+    stale: [DirtyRegion; N],
+}
+
+pub type DoubleBuffer<'a, B = SoftwareBlitter> = SwapChain<'a, 2, B>;
+pub type TripleBuffer<'a, B = SoftwareBlitter> = SwapChain<'a, 3, B>;
+
+impl<'a, const N: usize, P: PixelStorage> SwapChain<'a, N, SoftwareBlitter, P> {
+    pub fn new(buffers: [&'a mut [P]; N], size: Size) -> Self {
+        Self::with_blitter(buffers, size, SoftwareBlitter)
+    }
+}
+
+impl<'a, const N: usize, B: Blitter<P>, P: PixelStorage> SwapChain<'a, N, B, P> {
+    pub fn with_blitter(buffers: [&'a mut [P]; N], size: Size, blitter: B) -> Self {
+        assert!(N >= 2, "a swap chain needs at least two buffers");
+        for buf in buffers.iter() {
+            assert!(buf.len() >= (size.width * size.height) as usize, "framebuffer too small");
+        }
+
+        Self {
+            buffers,
+            back: 0,
+            pending: false,
+            size,
+            visible: size,
+            viewport: Point::zero(),
+            orientation: Orientation::default(),
+            blitter,
+            damage: DirtyRegion::new(),
+            stale: [DirtyRegion::new(); N],
+        }
+    }
+
+    /// The buffer currently being drawn to
+    pub fn current(&mut self) -> &mut [P] {
+        self.buffers[self.back]
+    }
+
+    pub async fn swap<S: FrameSink<P>>(&mut self, sink: &mut S) -> Result<(), S::Error> {
+        // only one flip can be pending at a time
+        if self.pending {
+            sink.wait_for_vblank().await?;
+            self.pending = false;
+        }
+
+        sink.queue(self.visible_frame(self.back))?;
+        self.pending = true;
+
+        let newest = self.back;
+        for area in self.damage.iter() {
+            let area = self.orientation.rect_to_physical(area, self.size);
+            for (i, stale) in self.stale.iter_mut().enumerate() {
+                if i != newest {
+                    stale.add(area);
+                }
+            }
+        }
+        self.damage.clear();
+        self.back = (self.back + 1) % N;
+
+        if N < 3 {
+            // the next back buffer is still on screen until the flip
+            sink.wait_for_vblank().await?;
+            self.pending = false;
+        }
+
+        self.catch_up(newest);
+        Ok(())
+    }
+
+    /// The visible part of the newest frame handed to the display, e.g.
+    /// for screenshots. Before the first swap this is the blank last buffer.
+    pub fn front(&self) -> Frame<'_, P> {
+        self.visible_frame((self.back + N - 1) % N)
+    }
+
+    fn visible_frame(&self, buffer: usize) -> Frame<'_, P> {
+        let offset = self.viewport.y as usize * self.size.width as usize + self.viewport.x as usize;
+        Frame {
+            pixels: &self.buffers[buffer][offset..],
+            stride: self.size.width as usize,
+            size: self.visible,
+        }
+    }
+
+    /// Size of the part that's shown, by default the whole buffer
+    pub fn visible_size(&self) -> Size {
+        self.visible
+    }
+
+    /// Only show `visible` of the (virtual) buffer size at a time, this is
+    /// normally the screen size
+    pub fn set_visible_size(&mut self, visible: Size) {
+        assert!(visible.width <= self.size.width && visible.height <= self.size.height, "visible area bigger than the buffer");
+        self.visible = visible;
+        self.scroll_to(self.viewport);
+    }
+
+    /// The part of the virtual buffer that will be shown by the next swap
+    pub fn viewport(&self) -> Rectangle {
+        Rectangle::new(self.viewport, self.visible)
+    }
+
+    /// Move the top left corner of the visible area, clamped so it stays
+    /// inside the buffer. Takes effect with the next swap.
+    pub fn scroll_to(&mut self, pos: Point) {
+        let max_x = (self.size.width - self.visible.width) as i32;
+        let max_y = (self.size.height - self.visible.height) as i32;
+        self.viewport = Point::new(pos.x.clamp(0, max_x), pos.y.clamp(0, max_y));
+    }
+
+    pub fn scroll_by(&mut self, delta: Point) {
+        self.scroll_to(self.viewport + delta);
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Change how drawing maps onto the buffers. What's already in them
+    /// doesn't move, so everything gets marked dirty for a full redraw.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+        self.mark_all_dirty();
+    }
+
+    /// Mark an area as changed in the frame being drawn, in drawing
+    /// coordinates
+    pub fn mark_dirty(&mut self, area: &Rectangle) {
+        self.damage.add(area.intersection(&self.bounding_box()));
+    }
+
+    /// Mark the whole screen as changed, e.g. for the first frame
+    pub fn mark_all_dirty(&mut self) {
+        self.damage.clear();
+        self.damage.add(self.bounding_box());
+    }
+
+    /// Everything marked dirty in the frame being drawn so far
+    pub fn dirty(&self) -> &DirtyRegion {
+        &self.damage
+    }
+
+    /// Bring the back buffer up to date by copying the areas it missed from
+    /// buffer `newest`
+    fn catch_up(&mut self, newest: usize) {
+        let stale = self.stale[self.back];
+        self.stale[self.back].clear();
+
+        let width = self.size.width as usize;
+        let (src, dst) = if newest < self.back {
+            let (lo, hi) = self.buffers.split_at_mut(self.back);
+            (&*lo[newest], &mut *hi[0])
+        } else {
+            let (lo, hi) = self.buffers.split_at_mut(newest);
+            (&*hi[0], &mut *lo[self.back])
+        };
+
+        for area in stale.iter() {
+            self.blitter.copy(src, width, area.top_left, dst, width, area);
+        }
+    }
+
+    /// Clears the buffer to black, or palette entry 0
+    pub fn clear(&mut self) {
+        let area = self.bounding_box();
+        self.fill_area(&area, P::default());
+    }
+
+    /// Copy an image of `src_size` in the framebuffer's format to `pos`,
+    /// clipped to the screen. The blitter can't rotate, so with any other
+    /// orientation this goes pixel by pixel.
+    pub fn blit(&mut self, src: &[P], src_size: Size, pos: Point) {
+        if let Some((area, src_origin)) = self.clip_image(src_size, pos) {
+            let width = self.size.width as usize;
+            let src_width = src_size.width as usize;
+            if self.orientation.is_identity() {
+                let dst = &mut *self.buffers[self.back];
+                self.blitter.copy(src, src_width, src_origin, dst, width, &area);
+            } else {
+                for point in area.points() {
+                    let s = point - area.top_left + src_origin;
+                    let index = self.physical_index(point);
+                    self.buffers[self.back][index] = src[s.y as usize * src_width + s.x as usize];
+                }
+            }
+        }
+    }
+
+    /// Buffer index of a point in drawing coordinates, which has to be on
+    /// screen
+    fn physical_index(&self, point: Point) -> usize {
+        let p = self.orientation.to_physical(point, self.size);
+        p.y as usize * self.size.width as usize + p.x as usize
+    }
+
+    /// Visible part of an image placed at `pos` and where that part starts
+    /// inside the image
+    fn clip_image(&self, src_size: Size, pos: Point) -> Option<(Rectangle, Point)> {
+        let area = Rectangle::new(pos, src_size).intersection(&self.bounding_box());
+        if area.is_zero_sized() {
+            return None;
+        }
+        Some((area, area.top_left - pos))
+    }
+
+    /// Fill an area that is already clipped to the screen
+    fn fill_area(&mut self, area: &Rectangle, color: P) {
+        let width = self.size.width as usize;
+        let area = self.orientation.rect_to_physical(area, self.size);
+        let dst = &mut *self.buffers[self.back];
+        self.blitter.fill(dst, width, &area, color);
+    }
+}
+
+impl<'a, const N: usize, B: BlendBlitter> SwapChain<'a, N, B> {
+    /// Alpha blend an ARGB8888 image of `src_size` over `pos`, clipped to
+    /// the screen
+    pub fn blend_argb8888(&mut self, src: &[u32], src_size: Size, pos: Point) {
+        if let Some((area, src_origin)) = self.clip_image(src_size, pos) {
+            let width = self.size.width as usize;
+            let src_width = src_size.width as usize;
+            if self.orientation.is_identity() {
+                let dst = &mut *self.buffers[self.back];
+                self.blitter.blend_argb8888(src, src_width, src_origin, dst, width, &area);
+            } else {
+                for point in area.points() {
+                    let s = point - area.top_left + src_origin;
+                    let index = self.physical_index(point);
+                    let out = &mut self.buffers[self.back][index];
+                    *out = blend_pixel(src[s.y as usize * src_width + s.x as usize], *out);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, const N: usize, B: Blitter<P>, P: PixelStorage> DrawTarget for SwapChain<'a, N, B, P> {
+    type Color = P::Color;
+    type Error = Infallible;
+
+    /// Draw a pixel
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        // off screen pixels are simply dropped, drawing partly outside the
+        // screen is normal
+        let bounds = self.bounding_box();
+
+        for Pixel(point, color) in pixels {
+            if bounds.contains(point) {
+                let index = self.physical_index(point);
+                self.buffers[self.back][index] = P::from_color(color);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write colours row by row instead of going through draw_iter. Only
+    /// the visible part of `area` is touched, the colours for the rest are
+    /// skipped.
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let visible = area.intersection(&self.bounding_box());
+        if visible.is_zero_sized() {
+            return Ok(());
+        }
+
+        let area_width = area.size.width as usize;
+        let skip_left = (visible.top_left.x - area.top_left.x) as usize;
+        let skip_right = area_width - skip_left - visible.size.width as usize;
+        let skip_above = (visible.top_left.y - area.top_left.y) as usize * area_width;
+
+        let mut colors = colors.into_iter();
+        skip(&mut colors, skip_above);
+
+        for y in visible.rows() {
+            skip(&mut colors, skip_left);
+            if self.orientation.is_identity() {
+                let width = self.size.width as usize;
+                let (start, len) = span(&Rectangle::new(Point::new(visible.top_left.x, y), visible.size), width);
+                for (out, color) in self.buffers[self.back][start..start + len].iter_mut().zip(colors.by_ref()) {
+                    *out = P::from_color(color);
+                }
+            } else {
+                for (x, color) in visible.columns().zip(colors.by_ref()) {
+                    let index = self.physical_index(Point::new(x, y));
+                    self.buffers[self.back][index] = P::from_color(color);
+                }
+            }
+            skip(&mut colors, skip_right);
+        }
+
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        if !area.is_zero_sized() {
+            self.fill_area(&area, P::from_color(color));
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        let area = self.bounding_box();
+        self.fill_area(&area, P::from_color(color));
+        Ok(())
+    }
+}
+
+fn skip<I: Iterator>(iter: &mut I, n: usize) {
+    if n > 0 {
+        iter.nth(n - 1);
+    }
+}
+
+impl<'a, const N: usize, B, P> OriginDimensions for SwapChain<'a, N, B, P> {
+    /// Return the size of the whole (virtual) buffer, as rotated by the
+    /// orientation
+    fn size(&self) -> Size {
+        self.orientation.logical_size(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::blitter::BLEND_VECTORS;
+    use crate::orientation::Rotation;
+    use core::cell::RefCell;
+    use embassy_futures::block_on;
+    use std::vec::Vec;
+
+    #[test]
+    fn fill_solid_clips_to_the_screen() {
+        let (mut a, mut b) = ([0; 16], [0; 16]);
+        let mut disp = DoubleBuffer::new([&mut a, &mut b], Size::new(4, 4));
+        disp.fill_solid(&Rectangle::new(Point::new(-1, 2), Size::new(3, 5)), Rgb565::WHITE).unwrap();
+        disp.fill_solid(&Rectangle::new(Point::new(4, 0), Size::new(2, 2)), Rgb565::WHITE).unwrap();
+
+        assert_eq!(disp.current(), [0, 0, 0, 0, 0, 0, 0, 0, 0xffff, 0xffff, 0, 0, 0xffff, 0xffff, 0, 0]);
+    }
+
+    const ORIENTATIONS: [Orientation; 8] = [
+        Orientation::new(Rotation::Deg0),
+        Orientation::new(Rotation::Deg90),
+        Orientation::new(Rotation::Deg180),
+        Orientation::new(Rotation::Deg270),
+        Orientation::new(Rotation::Deg0).mirrored(true, false),
+        Orientation::new(Rotation::Deg90).mirrored(false, true),
+        Orientation::new(Rotation::Deg180).mirrored(true, true),
+        Orientation::new(Rotation::Deg270).mirrored(true, false),
+    ];
+
+    #[test]
+    fn rotated_chains_report_the_drawing_size() {
+        let (mut a, mut b) = ([0; 12], [0; 12]);
+        let mut disp = DoubleBuffer::new([&mut a, &mut b], Size::new(4, 3));
+        for orientation in ORIENTATIONS {
+            disp.set_orientation(orientation);
+            let expected = match orientation.rotation {
+                Rotation::Deg0 | Rotation::Deg180 => Size::new(4, 3),
+                Rotation::Deg90 | Rotation::Deg270 => Size::new(3, 4),
+            };
+            assert_eq!(disp.size(), expected, "{orientation:?}");
+        }
+    }
+
+    #[test]
+    fn rotated_fill_solid_matches_drawing_pixels() {
+        // partly off screen on every side in one orientation or another
+        let areas = [
+            Rectangle::new(Point::new(1, 0), Size::new(2, 3)),
+            Rectangle::new(Point::new(-1, 2), Size::new(3, 5)),
+            Rectangle::new(Point::new(2, -2), Size::new(4, 3)),
+        ];
+        for orientation in ORIENTATIONS {
+            for area in areas {
+                let (mut a, mut b, mut c, mut d) = ([0; 12], [0; 12], [0; 12], [0; 12]);
+                let mut filled = DoubleBuffer::new([&mut a, &mut b], Size::new(4, 3));
+                let mut drawn = DoubleBuffer::new([&mut c, &mut d], Size::new(4, 3));
+                filled.set_orientation(orientation);
+                drawn.set_orientation(orientation);
+
+                filled.fill_solid(&area, Rgb565::WHITE).unwrap();
+                drawn.draw_iter(area.points().map(|p| Pixel(p, Rgb565::WHITE))).unwrap();
+
+                assert_eq!(filled.current(), drawn.current(), "{orientation:?} {area:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_drawing_lands_on_the_mapped_pixel() {
+        let (mut a, mut b) = ([0; 12], [0; 12]);
+        let mut disp = DoubleBuffer::new([&mut a, &mut b], Size::new(4, 3));
+        disp.set_orientation(Orientation::new(Rotation::Deg90));
+        // the top left of a portrait picture is the top right of the panel
+        Pixel(Point::zero(), Rgb565::WHITE).draw(&mut disp).unwrap();
+        Pixel(Point::new(2, 3), Rgb565::RED).draw(&mut disp).unwrap();
+
+        let mut expected = [0; 12];
+        expected[3] = 0xffff;
+        expected[8] = Rgb565::RED.into_storage();
+        assert_eq!(disp.current(), expected);
+    }
+
+    /// Remembers where the last queued frame starts, the way the LTDC
+    /// start address register would
+    #[derive(Default)]
+    struct AddressSink {
+        address: usize,
+        stride: usize,
+        size: Size,
+    }
+
+    impl FrameSink for AddressSink {
+        type Error = Infallible;
+
+        fn queue(&mut self, frame: Frame<'_>) -> Result<(), Self::Error> {
+            self.address = frame.pixels.as_ptr() as usize;
+            self.stride = frame.stride;
+            self.size = frame.size;
+            Ok(())
+        }
+
+        async fn wait_for_vblank(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn scrolling_stays_inside_the_buffer() {
+        let (mut a, mut b) = ([0; 24], [0; 24]);
+        let mut disp = DoubleBuffer::new([&mut a, &mut b], Size::new(6, 4));
+        // nothing to scroll while the whole buffer is shown
+        disp.scroll_to(Point::new(1, 1));
+        assert_eq!(disp.viewport(), Rectangle::new(Point::zero(), Size::new(6, 4)));
+
+        disp.set_visible_size(Size::new(4, 3));
+        disp.scroll_to(Point::new(-3, 10));
+        assert_eq!(disp.viewport().top_left, Point::new(0, 1));
+        disp.scroll_to(Point::new(5, -1));
+        assert_eq!(disp.viewport().top_left, Point::new(2, 0));
+        disp.scroll_by(Point::new(-1, 1));
+        assert_eq!(disp.viewport(), Rectangle::new(Point::new(1, 1), Size::new(4, 3)));
+
+        // growing the visible area pulls the viewport back in
+        disp.scroll_to(Point::new(2, 1));
+        disp.set_visible_size(Size::new(5, 4));
+        assert_eq!(disp.viewport(), Rectangle::new(Point::new(1, 0), Size::new(5, 4)));
+    }
+
+    #[test]
+    #[should_panic(expected = "visible area bigger than the buffer")]
+    fn visible_size_has_to_fit() {
+        let (mut a, mut b) = ([0; 24], [0; 24]);
+        let mut disp = DoubleBuffer::new([&mut a, &mut b], Size::new(6, 4));
+        disp.set_visible_size(Size::new(4, 5));
+    }
+
+    #[test]
+    fn frames_start_at_the_viewport() {
+        let (mut a, mut b) = ([0; 24], [0; 24]);
+        let mut disp = DoubleBuffer::new([&mut a, &mut b], Size::new(6, 4));
+        let start = disp.current().as_ptr() as usize;
+        for (i, p) in disp.current().iter_mut().enumerate() {
+            *p = i as u16;
+        }
+        disp.set_visible_size(Size::new(4, 3));
+        disp.scroll_to(Point::new(2, 1));
+
+        let mut sink = AddressSink::default();
+        block_on(disp.swap(&mut sink)).unwrap();
+
+        // one row down and two pixels in, with the pitch of the whole buffer
+        let pixel = core::mem::size_of::<TargetPixelType>();
+        assert_eq!(sink.address - start, (6 + 2) * pixel);
+        assert_eq!(sink.stride, 6);
+        assert_eq!(sink.size, Size::new(4, 3));
+        let front = disp.front();
+        let mut rows = front.rows();
+        assert_eq!(rows.next(), Some(&[8, 9, 10, 11][..]));
+        assert_eq!(rows.next(), Some(&[14, 15, 16, 17][..]));
+        assert_eq!(rows.next(), Some(&[20, 21, 22, 23][..]));
+        assert_eq!(rows.next(), None);
+    }
+
+    /// Software blitter that writes down every copy as (destination
+    /// buffer, area)
+    struct CopyLog<'b>(&'b RefCell<Vec<(usize, Rectangle)>>);
+
+    impl Blitter for CopyLog<'_> {
+        fn fill(&mut self, dst: &mut [TargetPixelType], dst_width: usize, area: &Rectangle, color: TargetPixelType) {
+            SoftwareBlitter.fill(dst, dst_width, area, color);
+        }
+
+        fn copy(
+            &mut self,
+            src: &[TargetPixelType],
+            src_width: usize,
+            src_origin: Point,
+            dst: &mut [TargetPixelType],
+            dst_width: usize,
+            area: &Rectangle,
+        ) {
+            self.0.borrow_mut().push((dst.as_ptr() as usize, *area));
+            SoftwareBlitter.copy(src, src_width, src_origin, dst, dst_width, area);
+        }
+    }
+
+    /// Draw one area in each of the first two frames and keep swapping
+    fn catch_up_copies_each_area_once<const N: usize>() {
+        let mut buffers = [[0; 16]; N];
+        let addresses = buffers.each_ref().map(|b| b.as_ptr() as usize);
+        let log = RefCell::new(Vec::new());
+        let mut disp = SwapChain::with_blitter(buffers.each_mut().map(|b| &mut b[..]), Size::new(4, 4), CopyLog(&log));
+        let mut sink = AddressSink::default();
+
+        let areas = [Rectangle::new(Point::new(1, 1), Size::new(2, 1)), Rectangle::new(Point::new(0, 3), Size::new(1, 1))];
+        for (frame, area) in areas.iter().enumerate() {
+            disp.fill_solid(area, Rgb565::WHITE).unwrap();
+            disp.mark_dirty(area);
+            block_on(disp.swap(&mut sink)).unwrap();
+
+            // the next buffer to draw in has everything drawn so far
+            for drawn in &areas[..=frame] {
+                for p in drawn.points() {
+                    assert_eq!(disp.current()[p.y as usize * 4 + p.x as usize], 0xffff, "N={N} frame {frame}");
+                }
+            }
+        }
+        for _ in 0..2 * N {
+            block_on(disp.swap(&mut sink)).unwrap();
+        }
+
+        // into every buffer but the one it was drawn in, and only once
+        let log = log.into_inner();
+        for (frame, area) in areas.iter().enumerate() {
+            for (buffer, &address) in addresses.iter().enumerate() {
+                let copies = log.iter().filter(|&&(dst, a)| dst == address && a == *area).count();
+                let expected = if buffer == frame % N { 0 } else { 1 };
+                assert_eq!(copies, expected, "N={N} area {area:?} buffer {buffer}");
+            }
+        }
+        assert_eq!(log.len(), areas.len() * (N - 1), "N={N}");
+    }
+
+    #[test]
+    fn double_buffers_catch_up_once() {
+        catch_up_copies_each_area_once::<2>();
+    }
+
+    #[test]
+    fn triple_buffers_catch_up_once() {
+        catch_up_copies_each_area_once::<3>();
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Event {
+        /// Queued the buffer starting with this pixel
+        Queue(TargetPixelType),
+        Vblank,
+    }
+
+    /// Writes down what the swap chain asks of the display
+    #[derive(Default)]
+    struct EventSink(Vec<Event>);
+
+    impl FrameSink for EventSink {
+        type Error = Infallible;
+
+        fn queue(&mut self, frame: Frame<'_>) -> Result<(), Self::Error> {
+            self.0.push(Event::Queue(frame.pixels[0]));
+            Ok(())
+        }
+
+        async fn wait_for_vblank(&mut self) -> Result<(), Self::Error> {
+            self.0.push(Event::Vblank);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn triple_buffers_draw_while_a_frame_is_queued() {
+        let (mut a, mut b, mut c) = ([0; 4], [0; 4], [0; 4]);
+        let mut disp = TripleBuffer::new([&mut a, &mut b, &mut c], Size::new(2, 2));
+        let mut sink = EventSink::default();
+
+        for frame in 1..=7 {
+            // frame N - 1 is still queued while N is drawn, and N - 2 is on
+            // screen, so this mustn't be either of their buffers
+            let shown = sink.0.iter().rev().filter_map(|e| match e { Event::Queue(p) => Some(*p), _ => None });
+            for shown in shown.take(2) {
+                assert_ne!(disp.current()[0], shown, "frame {frame}");
+            }
+            disp.current()[0] = frame;
+            block_on(disp.swap(&mut sink)).unwrap();
+        }
+
+        // round and round the buffers, with a single wait for the previous
+        // flip before each queue after the first
+        use Event::*;
+        assert_eq!(
+            sink.0,
+            [Queue(1), Vblank, Queue(2), Vblank, Queue(3), Vblank, Queue(4), Vblank, Queue(5), Vblank, Queue(6), Vblank, Queue(7)]
+        );
+        assert_eq!(disp.front().pixels[0], 7);
+        // buffers were used in order, 7 went into the first one again
+        assert_eq!(disp.current()[0], 5);
+    }
+
+    #[test]
+    fn double_buffers_wait_for_each_flip() {
+        let (mut a, mut b) = ([0; 4], [0; 4]);
+        let mut disp = DoubleBuffer::new([&mut a, &mut b], Size::new(2, 2));
+        let mut sink = EventSink::default();
+
+        for frame in 1..=3 {
+            disp.current()[0] = frame;
+            block_on(disp.swap(&mut sink)).unwrap();
+        }
+
+        use Event::*;
+        assert_eq!(sink.0, [Queue(1), Vblank, Queue(2), Vblank, Queue(3), Vblank]);
+        assert_eq!(disp.current()[0], 2);
+    }
+
+    #[test]
+    fn fill_contiguous_clips_like_drawing_pixels() {
+        // a 3x3 sprite with a different colour in every pixel
+        let colors = (1..=9).map(|i| Rgb565::new(i, 0, 0));
+        let positions = [
+            Point::new(-1, 1), Point::new(-2, -2), Point::new(1, -1), Point::new(3, -2),
+            Point::new(2, 1), Point::new(3, 2), Point::new(1, 2), Point::new(-2, 3),
+            Point::new(1, 1), Point::new(-3, 0), Point::new(4, 4),
+        ];
+        for orientation in [Orientation::default(), Orientation::new(Rotation::Deg270).mirrored(true, false)] {
+            for pos in positions {
+                // guard pixels past the end catch writes outside the 4x4 screen
+                let (mut a, mut b, mut c, mut d) = ([0x1234; 20], [0x1234; 20], [0x1234; 20], [0x1234; 20]);
+                let mut filled = DoubleBuffer::new([&mut a, &mut b], Size::new(4, 4));
+                let mut drawn = DoubleBuffer::new([&mut c, &mut d], Size::new(4, 4));
+                filled.set_orientation(orientation);
+                drawn.set_orientation(orientation);
+
+                let area = Rectangle::new(pos, Size::new(3, 3));
+                filled.fill_contiguous(&area, colors.clone()).unwrap();
+                drawn.draw_iter(area.points().zip(colors.clone()).map(|(p, c)| Pixel(p, c))).unwrap();
+
+                assert_eq!(filled.current(), drawn.current(), "{orientation:?} {pos:?}");
+                assert_eq!(filled.current()[16..], [0x1234; 4], "{orientation:?} {pos:?}");
+                let visible = area.intersection(&filled.bounding_box());
+                let changed = filled.current().iter().filter(|&&p| p != 0x1234).count();
+                assert_eq!(changed as u32, visible.size.width * visible.size.height, "{orientation:?} {pos:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn blit_copies_the_visible_part() {
+        let (mut a, mut b) = ([0; 16], [0; 16]);
+        let mut disp = DoubleBuffer::new([&mut a, &mut b], Size::new(4, 4));
+        disp.blit(&[1, 2, 3, 4, 5, 6], Size::new(3, 2), Point::new(2, -1));
+
+        assert_eq!(disp.current(), [0, 0, 4, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn blend_matches_the_vectors() {
+        const N: usize = BLEND_VECTORS.len();
+        let (mut a, mut b) = ([0; N], [0; N]);
+        let mut disp = DoubleBuffer::new([&mut a, &mut b], Size::new(N as u32, 1));
+        disp.current().copy_from_slice(&BLEND_VECTORS.map(|(_, bg, _)| bg));
+        disp.blend_argb8888(&BLEND_VECTORS.map(|(fg, _, _)| fg), Size::new(N as u32, 1), Point::zero());
+
+        assert_eq!(disp.current(), BLEND_VECTORS.map(|(_, _, out)| out));
+    }
+}
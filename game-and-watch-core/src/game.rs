@@ -0,0 +1,321 @@
+use core::convert::Infallible;
+
+use embedded_graphics::{
+    image::ImageRawLE,
+    prelude::*,
+    primitives::Rectangle, pixelcolor::Rgb565,
+    mono_font::{ascii, MonoTextStyle},
+    text::Text,
+};
+
+use tinybmp::Bmp;
+
+use crate::backlight::BacklightControl;
+use crate::blitter::Blitter;
+use crate::display::DisplayBackend;
+use crate::framebuffer::SwapChain;
+use crate::input::{ButtonClick, ButtonReading};
+use crate::sprite::{Sprite, SpriteList, SpriteSheet};
+use crate::tilemap::{Camera, TileSize, Tilemap};
+
+/// The world ferris walks around in, in 16 pixel tiles
+const WORLD_COLUMNS: usize = 40;
+const WORLD_ROWS: usize = 30;
+const TILE: usize = 16;
+
+/// Two tiles side by side: plain red floor and a darker block
+static TILESET: [u8; 2 * TILE * TILE * 2] = {
+    let floor = 0xf800u16;
+    let block = 0x9800u16;
+    let edge = 0x6000u16;
+    let mut data = [0; 2 * TILE * TILE * 2];
+    let mut y = 0;
+    while y < TILE {
+        let mut x = 0;
+        while x < 2 * TILE {
+            let color = if x < TILE {
+                floor
+            } else if x == TILE || x == 2 * TILE - 1 || y == 0 || y == TILE - 1 {
+                edge
+            } else {
+                block
+            };
+            let at = (y * 2 * TILE + x) * 2;
+            data[at] = color as u8;
+            data[at + 1] = (color >> 8) as u8;
+            x += 1;
+        }
+        y += 1;
+    }
+    data
+};
+
+/// Blocks around the edge and every few tiles, so scrolling shows
+static WORLD: [u16; WORLD_COLUMNS * WORLD_ROWS] = {
+    let mut tiles = [0; WORLD_COLUMNS * WORLD_ROWS];
+    let mut i = 0;
+    while i < tiles.len() {
+        let (column, row) = (i % WORLD_COLUMNS, i / WORLD_COLUMNS);
+        let edge = column == 0 || row == 0 || column == WORLD_COLUMNS - 1 || row == WORLD_ROWS - 1;
+        if edge || (column % 6 == 3 && row % 5 == 2) {
+            tiles[i] = 1;
+        }
+        i += 1;
+    }
+    tiles
+};
+
+static TILESET_IMAGE: ImageRawLE<'static, Rgb565> = ImageRawLE::new(&TILESET, 2 * TILE as u32);
+
+fn world() -> Tilemap<'static, ImageRawLE<'static, Rgb565>> {
+    Tilemap::new(&TILESET_IMAGE, TileSize::Px16, &WORLD, WORLD_COLUMNS as u32)
+}
+
+pub struct GameState {
+    /// Top left of ferris in world pixels
+    pub ferris_pos: Point,
+    pub button_reading: Option<ButtonReading>,
+    pub button_clicks: Option<ButtonClick>,
+    pub backlight: BacklightControl,
+    /// Set when the screenshot chord (PAUSE + A) goes down, the firmware
+    /// clears it once the picture is taken
+    pub screenshot_requested: bool,
+    /// View into the world, follows ferris
+    pub camera: Camera,
+    /// Where ferris was drawn last frame
+    drawn_ferris: Option<Rectangle>,
+    /// Camera the last frame was drawn with
+    drawn_camera: Camera,
+    full_redraw: bool,
+}
+
+impl GameState {
+    pub fn new() -> Self
+    {
+        Self {
+            ferris_pos: Point::new(120, 125),
+            button_reading: None,
+            button_clicks: None,
+            backlight: BacklightControl::default(),
+            screenshot_requested: false,
+            camera: Camera::default(),
+            drawn_ferris: None,
+            drawn_camera: Camera::default(),
+            full_redraw: true,
+        }
+    }
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn draw<D>(gs: &GameState, display: &mut D, ferris: Option<&Bmp<Rgb565>>) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    display.clear(Rgb565::BLACK)?;
+    world().draw(&gs.camera, display)?;
+    let text_style =
+        MonoTextStyle::new(&ascii::FONT_9X18, RgbColor::WHITE);
+    Text::new("Hello Rust!", gs.camera.to_screen(Point::new(120, 100)), text_style)
+        .draw(display)?;
+
+    if let Some(f) = ferris {
+        let sheet = SpriteSheet::single(f);
+        let mut sprites = SpriteList::<_, 1>::new();
+        sprites.push(Sprite::new(&sheet, gs.camera.to_screen(gs.ferris_pos))).ok();
+        sprites.draw(display)?;
+    }
+
+    Ok(())
+}
+
+/// Redraw the parts of the screen that changed since the last frame
+pub fn render<const N: usize, B: Blitter>(
+    gs: &mut GameState,
+    display: &mut SwapChain<'_, N, B>,
+    ferris: Option<&Bmp<Rgb565>>,
+) -> Result<(), Infallible> {
+    let ferris_size = ferris.map_or(Size::zero(), |f| f.size());
+    let center = gs.ferris_pos + Point::new(ferris_size.width as i32 / 2, ferris_size.height as i32 / 2);
+    // the buffer can be bigger than the screen, only the visible part counts
+    let screen = display.orientation().logical_size(display.visible_size());
+    gs.camera.follow(center, world().size(), screen);
+
+    // a scrolled background changes everywhere
+    if gs.full_redraw || gs.camera != gs.drawn_camera {
+        display.mark_all_dirty();
+        gs.full_redraw = false;
+        gs.drawn_camera = gs.camera;
+    }
+
+    let ferris_area = ferris.map(|f| Rectangle::new(gs.camera.to_screen(gs.ferris_pos), f.size()));
+    if ferris_area != gs.drawn_ferris {
+        for area in [gs.drawn_ferris, ferris_area].iter().flatten() {
+            display.mark_dirty(area);
+        }
+        gs.drawn_ferris = ferris_area;
+    }
+
+    let dirty = *display.dirty();
+    for area in dirty.iter() {
+        draw(gs, &mut display.clipped(area), ferris)?;
+    }
+
+    Ok(())
+}
+
+/// Advance the game by one frame using the latest button state
+pub fn update<B: DisplayBackend>(gs: &mut GameState, display: &mut B, reading: ButtonReading, clicks: ButtonClick, now_us: u64) {
+    let chord = |r: &ButtonReading| r.pause && r.a;
+    if chord(&reading) && !gs.button_reading.as_ref().is_some_and(chord) {
+        gs.screenshot_requested = true;
+    }
+
+    gs.button_reading = Some(reading);
+    gs.button_clicks = Some(clicks);
+
+    if reading != ButtonReading::default() || clicks != ButtonClick::default() {
+        gs.backlight.input(now_us);
+    }
+
+    if let Some(button_state) = gs.button_reading {
+        if button_state.left {
+            gs.ferris_pos.x -= 1;
+        }
+        if button_state.right {
+            gs.ferris_pos.x += 1;
+        }
+        if button_state.up {
+            gs.ferris_pos.y -= 1;
+        }
+        if button_state.down {
+            gs.ferris_pos.y +=1;
+        }
+    }
+
+    if let Some(clicks) = gs.button_clicks {
+        if clicks.power {
+            display.toggle_backlight();
+        }
+        if clicks.time {
+            gs.backlight.adjust(-32, now_us);
+        }
+        if clicks.game {
+            gs.backlight.adjust(32, now_us);
+        }
+    }
+
+    let level = gs.backlight.update(now_us);
+    if level != display.brightness() {
+        display.set_brightness(level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::display::MockDisplay;
+    use crate::framebuffer::{DoubleBuffer, HEIGHT, WIDTH};
+
+    const SIZE: Size = Size::new(WIDTH as u32, HEIGHT as u32);
+    const FRAME_US: u64 = 33_333;
+
+    fn pixel(frame: &[u16], x: usize, y: usize) -> u16 {
+        frame[y * WIDTH + x]
+    }
+
+    #[test]
+    fn presents_the_rendered_frame() {
+        let (mut front, mut back, mut shown) = (vec![0; WIDTH * HEIGHT], vec![0; WIDTH * HEIGHT], vec![0; WIDTH * HEIGHT]);
+        let mut disp = DoubleBuffer::new([&mut front, &mut back], SIZE);
+        let mut display = MockDisplay::new(&mut shown);
+        let mut gs = GameState::new();
+
+        update(&mut gs, &mut display, ButtonReading::default(), ButtonClick::default(), 0);
+        render(&mut gs, &mut disp, None).unwrap();
+        block_on(disp.swap(&mut display)).unwrap();
+
+        assert_eq!(display.presented(), 1);
+        assert!(disp.front().rows().eq(display.frame().chunks(WIDTH)));
+        // the camera centres on ferris, which puts the world at (0, 5)
+        assert_eq!(gs.camera.pos, Point::new(0, 5));
+        assert_eq!(pixel(display.frame(), 200, 200), 0xf800);
+        // block at column 3, row 2, and the dark edge around it
+        assert_eq!(pixel(display.frame(), 50, 30), 0x9800);
+        assert_eq!(pixel(display.frame(), 48, 30), 0x6000);
+    }
+
+    #[test]
+    fn walking_scrolls_the_world() {
+        let (mut front, mut back, mut shown) = (vec![0; WIDTH * HEIGHT], vec![0; WIDTH * HEIGHT], vec![0; WIDTH * HEIGHT]);
+        let mut disp = DoubleBuffer::new([&mut front, &mut back], SIZE);
+        let mut display = MockDisplay::new(&mut shown);
+        let mut gs = GameState::new();
+        let right = ButtonReading { right: true, ..Default::default() };
+
+        // the camera stays at the left edge of the world until ferris is
+        // half a screen in
+        for frame in 0..60 {
+            update(&mut gs, &mut display, right, ButtonClick::default(), frame * FRAME_US);
+            render(&mut gs, &mut disp, None).unwrap();
+            block_on(disp.swap(&mut display)).unwrap();
+            if frame == 9 {
+                assert_eq!(gs.camera.pos, Point::new(0, 5));
+            }
+        }
+
+        assert_eq!(display.presented(), 60);
+        assert_eq!(gs.ferris_pos, Point::new(180, 125));
+        assert_eq!(gs.camera.pos, Point::new(20, 5));
+        assert!(disp.front().rows().eq(display.frame().chunks(WIDTH)));
+        // the block at column 3 moved 20 pixels left
+        assert_eq!(pixel(display.frame(), 50, 30), 0xf800);
+        assert_eq!(pixel(display.frame(), 30, 30), 0x9800);
+    }
+
+    #[test]
+    fn power_click_toggles_the_backlight() {
+        let mut shown = vec![0; WIDTH * HEIGHT];
+        let mut display = MockDisplay::new(&mut shown);
+        let mut gs = GameState::new();
+        block_on(display.power_on()).unwrap();
+        assert!(display.is_powered() && display.backlight());
+
+        let power = ButtonClick { power: true, ..Default::default() };
+        update(&mut gs, &mut display, ButtonReading::default(), power, 0);
+        assert!(!display.backlight());
+        update(&mut gs, &mut display, ButtonReading::default(), power, FRAME_US);
+        assert!(display.backlight());
+        assert!(display.is_powered());
+        assert_eq!(display.presented(), 0);
+    }
+
+    #[test]
+    fn time_and_game_fade_the_brightness() {
+        let mut shown = vec![0; WIDTH * HEIGHT];
+        let mut display = MockDisplay::new(&mut shown);
+        let mut gs = GameState::new();
+        let idle = (ButtonReading::default(), ButtonClick::default());
+
+        update(&mut gs, &mut display, idle.0, ButtonClick { time: true, ..Default::default() }, 0);
+        assert_eq!(display.brightness(), 255);
+        update(&mut gs, &mut display, idle.0, idle.1, 125_000);
+        assert!((224..255).contains(&display.brightness()));
+        update(&mut gs, &mut display, idle.0, idle.1, 250_000);
+        assert_eq!(display.brightness(), 223);
+
+        update(&mut gs, &mut display, idle.0, ButtonClick { game: true, ..Default::default() }, 300_000);
+        update(&mut gs, &mut display, idle.0, idle.1, 600_000);
+        assert_eq!(display.brightness(), 255);
+    }
+}
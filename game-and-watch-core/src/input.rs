@@ -0,0 +1,32 @@
+// Button snapshots handed to the game logic once per frame. The firmware
+// fills these from button_driver, the host tools from a script.
+
+/// `true` while the button is held down
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ButtonReading {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub a: bool,
+    pub b: bool,
+    pub time: bool,
+    pub game: bool,
+    pub pause: bool,
+    pub power: bool,
+}
+
+/// `true` if the button was clicked since the last reading
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ButtonClick {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub a: bool,
+    pub b: bool,
+    pub time: bool,
+    pub game: bool,
+    pub pause: bool,
+    pub power: bool,
+}
@@ -0,0 +1,30 @@
+#![no_std]
+
+// Hardware independent parts of the firmware, shared between the
+// STM32 build and the host tools in game-and-watch-host
+
+pub mod adpcm;
+pub mod audio;
+pub mod backlight;
+pub mod blitter;
+pub mod dirty;
+pub mod display;
+pub mod font;
+pub mod frame_pacer;
+pub mod framebuffer;
+pub mod game;
+pub mod input;
+pub mod mixer;
+pub mod orientation;
+pub mod overlay;
+pub mod palette;
+pub mod panel;
+pub mod qoa;
+pub mod screenshot;
+pub mod sprite;
+pub mod stream;
+pub mod synth;
+pub mod text;
+pub mod tilemap;
+pub mod tracker;
+pub mod ui;
@@ -0,0 +1,381 @@
+use crate::audio::AudioSource;
+use crate::stream::Stream;
+use crate::tracker::Player;
+
+// Software mixer: N voices, each a sound in memory, a stream or a song,
+// with its own volume and pan, summed in 32 bits and saturated to 16. The
+// Game & Watch speaker is mono, so pan only matters for stereo output.
+
+/// Samples mixed per pass, the scratch buffer for streams lives on the
+/// stack
+const BLOCK: usize = 64;
+
+/// Full volume for a voice
+pub const VOICE_MAX_VOLUME: u8 = 255;
+
+/// Identifies a playing voice. Stays valid until the sound ends or is
+/// stopped, after that the mixer ignores it even if the voice is reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VoiceId {
+    index: u8,
+    generation: u8,
+}
+
+enum Input<'a> {
+    Sound {
+        samples: &'a [i16],
+        pos: usize,
+        /// Jump back here at the end
+        loop_start: Option<usize>,
+    },
+    Stream(Stream<'a>),
+    Song(Player<'a>),
+    Source(&'a mut (dyn AudioSource + Send)),
+}
+
+struct Voice<'a> {
+    input: Option<Input<'a>>,
+    generation: u8,
+    volume: u8,
+    /// -128 is all left, 127 all right
+    pan: i8,
+}
+
+impl<'a> Voice<'a> {
+    const IDLE: Self = Self {
+        input: None,
+        generation: 0,
+        volume: VOICE_MAX_VOLUME,
+        pan: 0,
+    };
+
+    /// Write up to `out.len()` samples, returns how many. Fewer means the
+    /// voice ended.
+    fn read(&mut self, out: &mut [i16]) -> usize {
+        match &mut self.input {
+            None => 0,
+            Some(Input::Stream(stream)) => stream.fill(out),
+            Some(Input::Song(player)) => player.fill(out),
+            Some(Input::Source(source)) => source.fill(out),
+            Some(Input::Sound { samples, pos, loop_start }) => {
+                let mut written = 0;
+                while written < out.len() {
+                    if *pos >= samples.len() {
+                        match *loop_start {
+                            Some(start) if start < samples.len() => *pos = start,
+                            _ => break,
+                        }
+                    }
+                    let n = (out.len() - written).min(samples.len() - *pos);
+                    out[written..written + n].copy_from_slice(&samples[*pos..*pos + n]);
+                    *pos += n;
+                    written += n;
+                }
+                written
+            }
+        }
+    }
+
+    /// Mono gain, 0..=65536 so that full volume leaves the samples alone
+    fn gain(&self) -> i32 {
+        let volume = self.volume as i32;
+        volume * 257 + (volume >> 7)
+    }
+
+    /// Left and right gains, 0..=65536. Both sides are at full volume in
+    /// the middle and fade out towards the other side.
+    fn gains(&self) -> (i32, i32) {
+        let pan = self.pan as i32;
+        let left = self.gain() * (127 - pan.max(0)) / 127;
+        let right = self.gain() * (128 + pan.min(0)) / 128;
+        (left, right)
+    }
+}
+
+/// Mixes up to `N` voices into mono or interleaved stereo
+pub struct Mixer<'a, const N: usize> {
+    voices: [Voice<'a>; N],
+}
+
+impl<'a, const N: usize> Mixer<'a, N> {
+    pub const fn new() -> Self {
+        assert!(N <= u8::MAX as usize, "too many voices");
+        Self { voices: [Voice::IDLE; N] }
+    }
+
+    fn start(&mut self, input: Input<'a>, volume: u8, pan: i8) -> Option<VoiceId> {
+        let index = self.voices.iter().position(|v| v.input.is_none())?;
+        let voice = &mut self.voices[index];
+        voice.input = Some(input);
+        voice.generation = voice.generation.wrapping_add(1);
+        voice.volume = volume;
+        voice.pan = pan;
+        Some(VoiceId {
+            index: index as u8,
+            generation: voice.generation,
+        })
+    }
+
+    /// Fire off a sound once, e.g. a sound effect. None if all voices are
+    /// busy.
+    pub fn play(&mut self, samples: &'a [i16], volume: u8, pan: i8) -> Option<VoiceId> {
+        self.start(Input::Sound { samples, pos: 0, loop_start: None }, volume, pan)
+    }
+
+    /// Play a sound until stopped, going back to `loop_start` (a sample
+    /// index) each time it reaches the end
+    pub fn play_looping(&mut self, samples: &'a [i16], loop_start: usize, volume: u8, pan: i8) -> Option<VoiceId> {
+        self.start(Input::Sound { samples, pos: 0, loop_start: Some(loop_start) }, volume, pan)
+    }
+
+    /// Play a stream, e.g. music from the memory mapped flash. The voice
+    /// ends when the stream does.
+    pub fn play_stream(&mut self, stream: Stream<'a>, volume: u8, pan: i8) -> Option<VoiceId> {
+        self.start(Input::Stream(stream), volume, pan)
+    }
+
+    /// Play a synth song. The voice ends when the song does.
+    pub fn play_song(&mut self, player: Player<'a>, volume: u8, pan: i8) -> Option<VoiceId> {
+        self.start(Input::Song(player), volume, pan)
+    }
+
+    /// Play from a source that makes its samples as it goes. The voice
+    /// ends when the source does.
+    pub fn play_source(&mut self, source: &'a mut (dyn AudioSource + Send), volume: u8, pan: i8) -> Option<VoiceId> {
+        self.start(Input::Source(source), volume, pan)
+    }
+
+    fn voice(&mut self, id: VoiceId) -> Option<&mut Voice<'a>> {
+        self.voices
+            .get_mut(id.index as usize)
+            .filter(|v| v.generation == id.generation && v.input.is_some())
+    }
+
+    pub fn is_playing(&self, id: VoiceId) -> bool {
+        self.voices
+            .get(id.index as usize)
+            .is_some_and(|v| v.generation == id.generation && v.input.is_some())
+    }
+
+    pub fn stop(&mut self, id: VoiceId) {
+        if let Some(voice) = self.voice(id) {
+            voice.input = None;
+        }
+    }
+
+    pub fn stop_all(&mut self) {
+        for voice in &mut self.voices {
+            voice.input = None;
+        }
+    }
+
+    pub fn set_volume(&mut self, id: VoiceId, volume: u8) {
+        if let Some(voice) = self.voice(id) {
+            voice.volume = volume;
+        }
+    }
+
+    pub fn set_pan(&mut self, id: VoiceId, pan: i8) {
+        if let Some(voice) = self.voice(id) {
+            voice.pan = pan;
+        }
+    }
+
+    /// Voices in use
+    pub fn active(&self) -> usize {
+        self.voices.iter().filter(|v| v.input.is_some()).count()
+    }
+
+    /// Mix all voices into `out`, 1 channel, or 2 interleaved left/right
+    fn mix(&mut self, out: &mut [i16], channels: usize) {
+        let mut acc = [0i32; BLOCK * 2];
+        let mut scratch = [0i16; BLOCK];
+
+        for frames in out.chunks_mut(BLOCK * channels) {
+            let len = frames.len() / channels;
+            let acc = &mut acc[..frames.len()];
+            acc.fill(0);
+
+            for voice in &mut self.voices {
+                if voice.input.is_none() {
+                    continue;
+                }
+                let n = voice.read(&mut scratch[..len]);
+                if channels == 1 {
+                    let gain = voice.gain();
+                    for (a, &s) in acc.iter_mut().zip(&scratch[..n]) {
+                        *a += (s as i32 * gain) >> 16;
+                    }
+                } else {
+                    let (left, right) = voice.gains();
+                    for (a, &s) in acc.chunks_exact_mut(2).zip(&scratch[..n]) {
+                        a[0] += (s as i32 * left) >> 16;
+                        a[1] += (s as i32 * right) >> 16;
+                    }
+                }
+                if n < len {
+                    voice.input = None;
+                }
+            }
+
+            for (o, &a) in frames.iter_mut().zip(acc.iter()) {
+                *o = a.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            }
+        }
+    }
+
+    /// Mix into interleaved left/right pairs
+    pub fn fill_stereo(&mut self, out: &mut [i16]) {
+        self.mix(out, 2);
+    }
+}
+
+impl<'a, const N: usize> Default for Mixer<'a, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, const N: usize> AudioSource for Mixer<'a, N> {
+    /// Mono mix, pan is ignored. Never ends, silence when nothing plays.
+    fn fill(&mut self, out: &mut [i16]) -> usize {
+        self.mix(out, 1);
+        out.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: i32 = 1 << 16;
+
+    fn voice<'a>(volume: u8, pan: i8) -> Voice<'a> {
+        Voice { input: None, generation: 0, volume, pan }
+    }
+
+    #[test]
+    fn full_scale_voices_saturate() {
+        let high = [i16::MAX; 100];
+        let low = [i16::MIN; 100];
+        let mut out = [0; 100];
+
+        let mut mixer = Mixer::<2>::new();
+        mixer.play(&high, VOICE_MAX_VOLUME, 0);
+        mixer.play(&high, VOICE_MAX_VOLUME, 0);
+        mixer.fill(&mut out);
+        assert!(out.iter().all(|&s| s == i16::MAX));
+
+        mixer.stop_all();
+        mixer.play(&low, VOICE_MAX_VOLUME, 0);
+        mixer.play(&low, VOICE_MAX_VOLUME, 0);
+        mixer.fill_stereo(&mut out);
+        assert!(out.iter().all(|&s| s == i16::MIN));
+    }
+
+    #[test]
+    fn full_volume_is_unity_gain() {
+        assert_eq!(voice(VOICE_MAX_VOLUME, 0).gain(), FULL);
+        assert_eq!(voice(0, 0).gain(), 0);
+
+        let samples = [0, 1, -1, 12345, -12345, i16::MAX, i16::MIN];
+        let mut out = [7; 7];
+        let mut mixer = Mixer::<1>::new();
+        mixer.play(&samples, VOICE_MAX_VOLUME, 0);
+        mixer.fill(&mut out);
+        assert_eq!(out, samples);
+
+        let mut out = [7; 14];
+        mixer.stop_all();
+        mixer.play(&samples, VOICE_MAX_VOLUME, 0);
+        mixer.fill_stereo(&mut out);
+        for (pair, &s) in out.chunks(2).zip(&samples) {
+            assert_eq!(pair, [s, s]);
+        }
+    }
+
+    #[test]
+    fn pan_fades_the_other_side() {
+        assert_eq!(voice(VOICE_MAX_VOLUME, 0).gains(), (FULL, FULL));
+        assert_eq!(voice(VOICE_MAX_VOLUME, -128).gains(), (FULL, 0));
+        assert_eq!(voice(VOICE_MAX_VOLUME, 127).gains(), (0, FULL));
+        assert_eq!(voice(VOICE_MAX_VOLUME, -64).gains(), (FULL, FULL / 2));
+
+        let (left, right) = voice(VOICE_MAX_VOLUME, 64).gains();
+        assert_eq!(right, FULL);
+        assert_eq!(left, FULL * 63 / 127);
+
+        // volume scales both
+        let (left, right) = voice(128, -64).gains();
+        assert_eq!(left, voice(128, 0).gain());
+        assert_eq!(right, left / 2);
+
+        let samples = [1000; 4];
+        let mut out = [0; 8];
+        let mut mixer = Mixer::<1>::new();
+        mixer.play(&samples, VOICE_MAX_VOLUME, -128);
+        mixer.fill_stereo(&mut out);
+        assert_eq!(out, [1000, 0, 1000, 0, 1000, 0, 1000, 0]);
+    }
+
+    #[test]
+    fn loops_wrap_to_the_loop_start() {
+        let samples = [1, 2, 3, 4];
+        let mut v = voice(VOICE_MAX_VOLUME, 0);
+        v.input = Some(Input::Sound { samples: &samples, pos: 0, loop_start: Some(2) });
+        let mut out = [0; 9];
+        assert_eq!(v.read(&mut out), 9);
+        assert_eq!(out, [1, 2, 3, 4, 3, 4, 3, 4, 3]);
+        assert_eq!(v.read(&mut out[..3]), 3);
+        assert_eq!(out[..3], [4, 3, 4]);
+
+        // a loop start past the end plays once
+        v.input = Some(Input::Sound { samples: &samples, pos: 0, loop_start: Some(4) });
+        assert_eq!(v.read(&mut out), 4);
+        assert_eq!(out[..4], samples);
+    }
+
+    #[test]
+    fn voices_end_with_their_sound() {
+        let samples = [100; 150];
+        let mut out = [1; 200];
+        let mut mixer = Mixer::<2>::new();
+        let id = mixer.play(&samples, VOICE_MAX_VOLUME, 0).unwrap();
+        let looping = mixer.play_looping(&samples, 0, VOICE_MAX_VOLUME, 0).unwrap();
+        assert_eq!(mixer.play(&samples, VOICE_MAX_VOLUME, 0), None);
+
+        assert_eq!(mixer.fill(&mut out), 200);
+        assert!(out[..150].iter().all(|&s| s == 200));
+        assert!(out[150..].iter().all(|&s| s == 100));
+        assert!(!mixer.is_playing(id));
+        assert!(mixer.is_playing(looping));
+        assert_eq!(mixer.active(), 1);
+
+        mixer.stop(looping);
+        assert_eq!(mixer.active(), 0);
+        mixer.fill(&mut out);
+        assert!(out.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn reused_voices_get_a_new_id() {
+        let samples = [100; 10];
+        let mut out = [0; 10];
+        let mut mixer = Mixer::<1>::new();
+        let old = mixer.play(&samples, VOICE_MAX_VOLUME, 0).unwrap();
+        mixer.stop(old);
+        let new = mixer.play(&samples, VOICE_MAX_VOLUME, 0).unwrap();
+        assert_ne!(old, new);
+        assert!(!mixer.is_playing(old));
+        assert!(mixer.is_playing(new));
+
+        // the old id no longer reaches the voice
+        mixer.set_volume(old, 0);
+        mixer.set_pan(old, -128);
+        mixer.stop(old);
+        assert!(mixer.is_playing(new));
+        mixer.fill_stereo(&mut out);
+        assert_eq!(out, [100; 10]);
+    }
+}
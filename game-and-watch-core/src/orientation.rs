@@ -0,0 +1,181 @@
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+/// Clockwise rotation of the picture on the panel
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+/// How drawing coordinates map onto the framebuffer. Mirroring happens
+/// first, in drawing coordinates, then the result is rotated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Orientation {
+    pub rotation: Rotation,
+    /// Flip left to right
+    pub mirror_x: bool,
+    /// Flip top to bottom
+    pub mirror_y: bool,
+}
+
+impl Orientation {
+    pub const fn new(rotation: Rotation) -> Self {
+        Self {
+            rotation,
+            mirror_x: false,
+            mirror_y: false,
+        }
+    }
+
+    pub const fn mirrored(self, mirror_x: bool, mirror_y: bool) -> Self {
+        Self {
+            rotation: self.rotation,
+            mirror_x,
+            mirror_y,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Size the drawing code sees on a framebuffer of `physical` size
+    pub fn logical_size(&self, physical: Size) -> Size {
+        match self.rotation {
+            Rotation::Deg0 | Rotation::Deg180 => physical,
+            Rotation::Deg90 | Rotation::Deg270 => Size::new(physical.height, physical.width),
+        }
+    }
+
+    /// Map a point in drawing coordinates to the framebuffer
+    pub fn to_physical(&self, point: Point, physical: Size) -> Point {
+        let logical = self.logical_size(physical);
+        let (w, h) = (physical.width as i32, physical.height as i32);
+
+        let x = if self.mirror_x { logical.width as i32 - 1 - point.x } else { point.x };
+        let y = if self.mirror_y { logical.height as i32 - 1 - point.y } else { point.y };
+
+        match self.rotation {
+            Rotation::Deg0 => Point::new(x, y),
+            Rotation::Deg90 => Point::new(w - 1 - y, x),
+            Rotation::Deg180 => Point::new(w - 1 - x, h - 1 - y),
+            Rotation::Deg270 => Point::new(y, h - 1 - x),
+        }
+    }
+
+    /// Map a rectangle in drawing coordinates to the framebuffer
+    pub fn rect_to_physical(&self, rect: &Rectangle, physical: Size) -> Rectangle {
+        let Some(bottom_right) = rect.bottom_right() else {
+            return Rectangle::zero();
+        };
+        let a = self.to_physical(rect.top_left, physical);
+        let b = self.to_physical(bottom_right, physical);
+        Rectangle::with_corners(a, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHYSICAL: Size = Size::new(4, 3);
+    const ROTATIONS: [Rotation; 4] = [Rotation::Deg0, Rotation::Deg90, Rotation::Deg180, Rotation::Deg270];
+
+    /// Where the top left, top right, bottom left and bottom right corners
+    /// of the drawing area end up
+    fn corners(orientation: Orientation) -> [(i32, i32); 4] {
+        let logical = orientation.logical_size(PHYSICAL);
+        let (right, bottom) = (logical.width as i32 - 1, logical.height as i32 - 1);
+        [(0, 0), (right, 0), (0, bottom), (right, bottom)].map(|(x, y)| {
+            let p = orientation.to_physical(Point::new(x, y), PHYSICAL);
+            (p.x, p.y)
+        })
+    }
+
+    #[test]
+    fn corners_land_where_expected() {
+        // (rotation, mirror_x, mirror_y, corners) worked out on paper for a
+        // 4x3 framebuffer
+        let expected = [
+            (Rotation::Deg0, false, false, [(0, 0), (3, 0), (0, 2), (3, 2)]),
+            (Rotation::Deg0, true, false, [(3, 0), (0, 0), (3, 2), (0, 2)]),
+            (Rotation::Deg0, false, true, [(0, 2), (3, 2), (0, 0), (3, 0)]),
+            (Rotation::Deg0, true, true, [(3, 2), (0, 2), (3, 0), (0, 0)]),
+            (Rotation::Deg90, false, false, [(3, 0), (3, 2), (0, 0), (0, 2)]),
+            (Rotation::Deg90, true, false, [(3, 2), (3, 0), (0, 2), (0, 0)]),
+            (Rotation::Deg90, false, true, [(0, 0), (0, 2), (3, 0), (3, 2)]),
+            (Rotation::Deg90, true, true, [(0, 2), (0, 0), (3, 2), (3, 0)]),
+            (Rotation::Deg180, false, false, [(3, 2), (0, 2), (3, 0), (0, 0)]),
+            (Rotation::Deg180, true, false, [(0, 2), (3, 2), (0, 0), (3, 0)]),
+            (Rotation::Deg180, false, true, [(3, 0), (0, 0), (3, 2), (0, 2)]),
+            (Rotation::Deg180, true, true, [(0, 0), (3, 0), (0, 2), (3, 2)]),
+            (Rotation::Deg270, false, false, [(0, 2), (0, 0), (3, 2), (3, 0)]),
+            (Rotation::Deg270, true, false, [(0, 0), (0, 2), (3, 0), (3, 2)]),
+            (Rotation::Deg270, false, true, [(3, 2), (3, 0), (0, 2), (0, 0)]),
+            (Rotation::Deg270, true, true, [(3, 0), (3, 2), (0, 0), (0, 2)]),
+        ];
+        for (rotation, mirror_x, mirror_y, corners_) in expected {
+            let orientation = Orientation::new(rotation).mirrored(mirror_x, mirror_y);
+            assert_eq!(corners(orientation), corners_, "{orientation:?}");
+        }
+    }
+
+    #[test]
+    fn rotations_keep_going_clockwise() {
+        // one step to the right of the top left corner
+        let step = |rotation| Orientation::new(rotation).to_physical(Point::new(1, 0), PHYSICAL);
+        assert_eq!(step(Rotation::Deg0), Point::new(1, 0));
+        assert_eq!(step(Rotation::Deg90), Point::new(3, 1));
+        assert_eq!(step(Rotation::Deg180), Point::new(2, 2));
+        assert_eq!(step(Rotation::Deg270), Point::new(0, 1));
+    }
+
+    #[test]
+    fn every_pixel_maps_to_its_own_pixel() {
+        for rotation in ROTATIONS {
+            for (mirror_x, mirror_y) in [(false, false), (true, false), (false, true), (true, true)] {
+                let orientation = Orientation::new(rotation).mirrored(mirror_x, mirror_y);
+                let logical = orientation.logical_size(PHYSICAL);
+                let mut hits = [0; 12];
+                for point in Rectangle::new(Point::zero(), logical).points() {
+                    let p = orientation.to_physical(point, PHYSICAL);
+                    assert!(Rectangle::new(Point::zero(), PHYSICAL).contains(p), "{orientation:?} {point:?}");
+                    hits[p.y as usize * 4 + p.x as usize] += 1;
+                }
+                assert_eq!(hits, [1; 12], "{orientation:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn sizes_swap_on_the_side() {
+        assert_eq!(Orientation::new(Rotation::Deg0).logical_size(PHYSICAL), PHYSICAL);
+        assert_eq!(Orientation::new(Rotation::Deg90).logical_size(PHYSICAL), Size::new(3, 4));
+        assert_eq!(Orientation::new(Rotation::Deg180).logical_size(PHYSICAL), PHYSICAL);
+        assert_eq!(Orientation::new(Rotation::Deg270).logical_size(PHYSICAL), Size::new(3, 4));
+    }
+
+    #[test]
+    fn rectangles_cover_the_mapped_points() {
+        let rect = Rectangle::new(Point::new(0, 1), Size::new(3, 2));
+        for rotation in ROTATIONS {
+            for (mirror_x, mirror_y) in [(false, false), (true, false), (false, true), (true, true)] {
+                let orientation = Orientation::new(rotation).mirrored(mirror_x, mirror_y);
+                let physical = orientation.rect_to_physical(&rect, PHYSICAL);
+                assert_eq!(physical.size.width * physical.size.height, 6, "{orientation:?}");
+                for point in rect.points() {
+                    assert!(physical.contains(orientation.to_physical(point, PHYSICAL)), "{orientation:?} {point:?}");
+                }
+            }
+        }
+
+        let on_the_side = Orientation::new(Rotation::Deg90).rect_to_physical(&rect, PHYSICAL);
+        assert_eq!(on_the_side, Rectangle::new(Point::new(1, 0), Size::new(2, 3)));
+        assert_eq!(Orientation::new(Rotation::Deg90).rect_to_physical(&Rectangle::zero(), PHYSICAL), Rectangle::zero());
+    }
+}
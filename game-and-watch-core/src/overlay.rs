@@ -0,0 +1,114 @@
+use core::convert::Infallible;
+
+use embedded_graphics::{
+    pixelcolor::{raw::RawU16, Rgb565},
+    prelude::*,
+    primitives::Rectangle,
+};
+
+/// 16 bit colour with 4 bits each of alpha, red, green and blue, the
+/// format the LTDC uses for the overlay layer
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Argb4444(u16);
+
+impl Argb4444 {
+    pub const TRANSPARENT: Argb4444 = Argb4444(0);
+
+    /// Each channel is 0..=15
+    pub const fn new(a: u8, r: u8, g: u8, b: u8) -> Self {
+        Self(((a as u16 & 0xf) << 12) | ((r as u16 & 0xf) << 8) | ((g as u16 & 0xf) << 4) | (b as u16 & 0xf))
+    }
+
+    /// `color` with alpha 0..=15
+    pub fn from_rgb565(color: Rgb565, alpha: u8) -> Self {
+        Self::new(alpha, color.r() >> 1, color.g() >> 2, color.b() >> 1)
+    }
+
+    pub fn a(self) -> u8 {
+        (self.0 >> 12) as u8
+    }
+
+    pub fn into_storage(self) -> u16 {
+        self.0
+    }
+}
+
+impl PixelColor for Argb4444 {
+    type Raw = RawU16;
+}
+
+impl From<RawU16> for Argb4444 {
+    fn from(raw: RawU16) -> Self {
+        Self(raw.into_inner())
+    }
+}
+
+impl From<Argb4444> for RawU16 {
+    fn from(color: Argb4444) -> Self {
+        RawU16::new(color.0)
+    }
+}
+
+impl From<Rgb565> for Argb4444 {
+    /// Fully opaque
+    fn from(color: Rgb565) -> Self {
+        Self::from_rgb565(color, 0xf)
+    }
+}
+
+/// A single ARGB4444 buffer for the overlay layer. Anything left
+/// transparent shows the game layer underneath.
+pub struct OverlayBuffer<'a> {
+    buf: &'a mut [u16],
+    size: Size,
+}
+
+impl<'a> OverlayBuffer<'a> {
+    pub fn new(buf: &'a mut [u16], size: Size) -> Self {
+        assert!(buf.len() >= (size.width * size.height) as usize, "overlay buffer too small");
+        Self { buf, size }
+    }
+
+    pub fn buffer(&self) -> &[u16] {
+        self.buf
+    }
+}
+
+impl<'a> DrawTarget for OverlayBuffer<'a> {
+    type Color = Argb4444;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let area = self.bounding_box();
+        let width = self.size.width as i32;
+
+        for Pixel(point, color) in pixels {
+            if area.contains(point) {
+                self.buf[(point.y * width + point.x) as usize] = color.into_storage();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        let width = self.size.width as usize;
+
+        for y in area.rows() {
+            let start = y as usize * width + area.top_left.x as usize;
+            self.buf[start..start + area.size.width as usize].fill(color.into_storage());
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> OriginDimensions for OverlayBuffer<'a> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
@@ -0,0 +1,198 @@
+use core::ops::RangeInclusive;
+
+use embedded_graphics::{
+    pixelcolor::{raw::RawU8, Rgb888},
+    prelude::*,
+};
+
+use crate::blitter::SoftwareBlitter;
+use crate::framebuffer::{PixelStorage, SwapChain};
+
+// Indexed colour for the L8 layer mode. Each pixel is an index into a
+// 256 entry palette that the LTDC looks up in its CLUT, so a frame is half
+// the size of an RGB565 one and effects like colour cycling or fades only
+// touch the palette.
+
+/// An entry in the palette
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteIndex(pub u8);
+
+impl PixelColor for PaletteIndex {
+    type Raw = RawU8;
+}
+
+impl From<RawU8> for PaletteIndex {
+    fn from(raw: RawU8) -> Self {
+        Self(raw.into_inner())
+    }
+}
+
+impl From<PaletteIndex> for RawU8 {
+    fn from(color: PaletteIndex) -> Self {
+        RawU8::new(color.0)
+    }
+}
+
+impl PixelStorage for u8 {
+    type Color = PaletteIndex;
+
+    fn from_color(color: PaletteIndex) -> Self {
+        color.0
+    }
+}
+
+pub type IndexedDoubleBuffer<'a> = SwapChain<'a, 2, SoftwareBlitter, u8>;
+pub type IndexedTripleBuffer<'a> = SwapChain<'a, 3, SoftwareBlitter, u8>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    colors: [Rgb888; 256],
+}
+
+impl Palette {
+    /// All black
+    pub const fn new() -> Self {
+        Self {
+            colors: [Rgb888::new(0, 0, 0); 256],
+        }
+    }
+
+    /// Palette starting with `colors`, the rest black
+    pub fn from_colors(colors: &[Rgb888]) -> Self {
+        let mut palette = Self::new();
+        for (entry, color) in palette.colors.iter_mut().zip(colors) {
+            *entry = *color;
+        }
+        palette
+    }
+
+    pub fn get(&self, index: u8) -> Rgb888 {
+        self.colors[index as usize]
+    }
+
+    pub fn set(&mut self, index: u8, color: Rgb888) {
+        self.colors[index as usize] = color;
+    }
+
+    pub fn colors(&self) -> &[Rgb888; 256] {
+        &self.colors
+    }
+
+    /// Rotate the entries in `range` by `steps`, positive moves colours to
+    /// higher indices. Called once a frame this gives the classic colour
+    /// cycling animation.
+    pub fn cycle(&mut self, range: RangeInclusive<u8>, steps: i32) {
+        if range.is_empty() {
+            return;
+        }
+        let entries = &mut self.colors[*range.start() as usize..=*range.end() as usize];
+
+        let shift = steps.rem_euclid(entries.len() as i32) as usize;
+        entries.rotate_right(shift);
+    }
+
+    /// Blend towards `target`, `amount` 0 gives `self`, 255 gives `target`
+    pub fn lerp(&self, target: &Palette, amount: u8) -> Palette {
+        let mix = |a: u8, b: u8| {
+            let (a, b, t) = (a as u32, b as u32, amount as u32);
+            ((a * (255 - t) + b * t) / 255) as u8
+        };
+
+        let mut out = Palette::new();
+        for ((out, a), b) in out.colors.iter_mut().zip(&self.colors).zip(&target.colors) {
+            *out = Rgb888::new(mix(a.r(), b.r()), mix(a.g(), b.g()), mix(a.b(), b.b()));
+        }
+        out
+    }
+
+    /// Fade to black, `brightness` 255 is unchanged
+    pub fn faded(&self, brightness: u8) -> Palette {
+        Palette::new().lerp(self, brightness)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entry `i` is red `i` for the first `n` entries
+    fn ramp(n: u8) -> Palette {
+        let mut palette = Palette::new();
+        for i in 0..n {
+            palette.set(i, Rgb888::new(i, 0, 0));
+        }
+        palette
+    }
+
+    /// Red of the first ten entries
+    fn reds(palette: &Palette) -> [u8; 10] {
+        core::array::from_fn(|i| palette.get(i as u8).r())
+    }
+
+    #[test]
+    fn cycling_rotates_only_the_range() {
+        let mut palette = ramp(10);
+        palette.cycle(2..=5, 1);
+        assert_eq!(reds(&palette), [0, 1, 5, 2, 3, 4, 6, 7, 8, 9]);
+        palette.cycle(2..=5, -1);
+        assert_eq!(palette, ramp(10));
+        palette.cycle(2..=5, -2);
+        assert_eq!(reds(&palette), [0, 1, 4, 5, 2, 3, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn cycling_wraps_the_steps() {
+        let mut palette = ramp(10);
+        palette.cycle(2..=5, 4);
+        assert_eq!(palette, ramp(10));
+        palette.cycle(2..=5, -5);
+        assert_eq!(reds(&palette), [0, 1, 3, 4, 5, 2, 6, 7, 8, 9]);
+        palette.cycle(2..=5, 9);
+        assert_eq!(palette, ramp(10));
+
+        // the whole palette, where the last entry comes round to the first
+        let mut palette = ramp(255);
+        palette.set(255, Rgb888::WHITE);
+        palette.cycle(0..=255, 1);
+        assert_eq!(palette.get(0), Rgb888::WHITE);
+        assert_eq!(palette.get(255), Rgb888::new(254, 0, 0));
+    }
+
+    #[test]
+    fn cycling_nothing_changes_nothing() {
+        let mut palette = ramp(10);
+        palette.cycle(3..=3, 1);
+        #[allow(clippy::reversed_empty_ranges)]
+        palette.cycle(5..=2, 1);
+        assert_eq!(palette, ramp(10));
+    }
+
+    #[test]
+    fn lerp_hits_both_ends() {
+        let a = Palette::from_colors(&[Rgb888::new(0, 255, 100), Rgb888::WHITE]);
+        let b = Palette::from_colors(&[Rgb888::new(255, 0, 200), Rgb888::WHITE]);
+        assert_eq!(a.lerp(&b, 0), a);
+        assert_eq!(a.lerp(&b, 255), b);
+
+        let half = a.lerp(&b, 128);
+        assert_eq!(half.get(0), Rgb888::new(128, 127, 150));
+        // equal entries stay put all the way
+        assert!((0..=255).all(|t| a.lerp(&b, t).get(1) == Rgb888::WHITE));
+        assert_eq!(a.lerp(&b, 51).get(0), Rgb888::new(51, 204, 120));
+    }
+
+    #[test]
+    fn fades_go_from_black_to_the_palette() {
+        let palette = Palette::from_colors(&[Rgb888::new(200, 100, 50), Rgb888::WHITE]);
+        assert_eq!(palette.faded(255), palette);
+        assert_eq!(palette.faded(0), Palette::new());
+        assert_eq!(palette.faded(128).get(0), Rgb888::new(100, 50, 25));
+        assert_eq!(palette.faded(128).get(1), Rgb888::new(128, 128, 128));
+    }
+}
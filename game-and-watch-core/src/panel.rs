@@ -0,0 +1,465 @@
+// Command layer for the LCD panel controller.
+//
+// There's no datasheet for the controller in the Game & Watch. Every
+// transfer is two bytes, a register and a value, with CS toggled around
+// it. The register names below are what the writes in the stock init
+// sequence appear to do, everything else that sequence touches is sent as
+// is.
+//
+// That's all this layer covers: sleep out, display on/off, the page lock
+// and raw writes. Sleep in, inversion, mirror/flip and gamma aren't here
+// on purpose. Nothing public documents registers for them, and neither
+// the stock firmware nor the reference code ever sends them, so any
+// values would be made up. Instead:
+//
+// - sleep: "sleeping" only blanks the panel, to save power switch it off
+//   (Lcd::power_off, which the power state machine allows from anywhere)
+// - mirror/flip: the swap chain's Orientation turns and mirrors drawing
+// - inversion and gamma: a Palette on an L8 layer, or the colours drawn
+//
+// If the controller's real command set turns up, they belong in reg and
+// Command next to the others, with their bytes checked in the tests.
+
+/// Registers with a known effect
+pub mod reg {
+    /// `ON` takes the panel out of sleep
+    pub const SLEEP_OUT: u8 = 0x08;
+    /// `ON` opens the page with the panel timing registers, `OFF` closes it
+    pub const PAGE_UNLOCK: u8 = 0x80;
+    /// `ON` starts scanning out the RGB input, `OFF` blanks the panel
+    pub const DISPLAY_ON: u8 = 0x14;
+
+    pub const ON: u8 = 0x80;
+    pub const OFF: u8 = 0x00;
+}
+
+/// A named panel command or a raw register write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    SleepOut,
+    DisplayOn,
+    DisplayOff,
+    Unlock,
+    Lock,
+    Write { register: u8, value: u8 },
+}
+
+impl Command {
+    /// The bytes that go out on the wire
+    pub const fn bytes(self) -> [u8; 2] {
+        match self {
+            Command::SleepOut => [reg::SLEEP_OUT, reg::ON],
+            Command::DisplayOn => [reg::DISPLAY_ON, reg::ON],
+            Command::DisplayOff => [reg::DISPLAY_ON, reg::OFF],
+            Command::Unlock => [reg::PAGE_UNLOCK, reg::ON],
+            Command::Lock => [reg::PAGE_UNLOCK, reg::OFF],
+            Command::Write { register, value } => [register, value],
+        }
+    }
+}
+
+/// The sequence the stock firmware sends after a reset. See
+/// https://github.com/kbeckmann/game-and-watch-retro-go/blob/main/Core/Src/gw_lcd.c
+pub const INIT_SEQUENCE: [Command; 10] = [
+    Command::SleepOut,
+    Command::Write { register: 0x6e, value: 0x80 },
+    Command::Unlock,
+    Command::Write { register: 0x68, value: 0x00 },
+    Command::Write { register: 0xd0, value: 0x00 },
+    Command::Write { register: 0x1b, value: 0x00 },
+    Command::Write { register: 0xe0, value: 0x00 },
+    Command::Write { register: 0x6a, value: 0x80 },
+    Command::Lock,
+    Command::DisplayOn,
+];
+
+/// Whatever carries commands to the panel, SPI with CS on the real thing
+#[allow(async_fn_in_trait)]
+pub trait PanelBus {
+    type Error;
+
+    /// Send one command as a single CS framed transaction
+    async fn write(&mut self, bytes: [u8; 2]) -> Result<(), Self::Error>;
+
+    /// Send several commands back to back, each in its own transaction.
+    /// Stops at the first error, saying how many made it out before it.
+    async fn write_sequence(&mut self, commands: &[Command]) -> Result<(), SequenceError<Self::Error>> {
+        for (sent, command) in commands.iter().enumerate() {
+            self.write(command.bytes()).await.map_err(|error| SequenceError { sent, error })?;
+        }
+        Ok(())
+    }
+}
+
+/// A sequence that failed partway
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SequenceError<E> {
+    /// Commands that went out before the error
+    pub sent: usize,
+    pub error: E,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PanelError<E> {
+    Bus(E),
+}
+
+impl<E> From<E> for PanelError<E> {
+    fn from(e: E) -> Self {
+        PanelError::Bus(e)
+    }
+}
+
+/// Where the panel is in its power up sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerState {
+    /// Rails off, reset held low
+    Off,
+    /// 3.3V and 1.8V on and settled
+    RailsUp,
+    /// Reset pulsed, the controller is waiting for its init sequence
+    Reset,
+    /// Initialised and showing the RGB input
+    Configured,
+    /// Rails still up but the panel is blanked
+    Sleeping,
+}
+
+/// What a driver does to the panel, each one only from some states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerStep {
+    PowerOff,
+    RailsUp,
+    Reset,
+    /// Send the init sequence
+    Configure,
+    Sleep,
+    Wake,
+}
+
+impl PowerStep {
+    /// The state the panel is in once the step is done
+    pub const fn target(self) -> PowerState {
+        match self {
+            PowerStep::PowerOff => PowerState::Off,
+            PowerStep::RailsUp => PowerState::RailsUp,
+            PowerStep::Reset => PowerState::Reset,
+            PowerStep::Configure | PowerStep::Wake => PowerState::Configured,
+            PowerStep::Sleep => PowerState::Sleeping,
+        }
+    }
+}
+
+impl PowerState {
+    /// Whether `step` can be taken from here. Powering off is always
+    /// allowed, and a powered panel can always be reset again.
+    pub fn can(self, step: PowerStep) -> bool {
+        use PowerState::*;
+        matches!(
+            (self, step),
+            (_, PowerStep::PowerOff)
+                | (Off, PowerStep::RailsUp)
+                | (RailsUp | Reset | Configured | Sleeping, PowerStep::Reset)
+                | (Reset, PowerStep::Configure)
+                | (Configured, PowerStep::Sleep)
+                | (Sleeping, PowerStep::Wake)
+        )
+    }
+
+    /// The state after `step` if it can be taken. Drivers should only move
+    /// to it once the step has actually worked.
+    pub fn step<E>(self, step: PowerStep) -> Result<PowerState, PowerError<E>> {
+        if self.can(step) {
+            Ok(step.target())
+        } else {
+            Err(PowerError::InvalidTransition { from: self, to: step.target() })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerError<E> {
+    /// The panel has to go through the states in order
+    InvalidTransition { from: PowerState, to: PowerState },
+    Panel(PanelError<E>),
+}
+
+impl<E> From<PanelError<E>> for PowerError<E> {
+    fn from(e: PanelError<E>) -> Self {
+        PowerError::Panel(e)
+    }
+}
+
+pub struct Panel<B> {
+    bus: B,
+    sleeping: bool,
+    display_on: bool,
+}
+
+impl<B: PanelBus> Panel<B> {
+    /// The panel is assumed to be fresh out of reset
+    pub fn new(bus: B) -> Self {
+        Self {
+            bus,
+            sleeping: true,
+            display_on: false,
+        }
+    }
+
+    /// Forget the panel state after pulling its reset line
+    pub fn mark_reset(&mut self) {
+        self.sleeping = true;
+        self.display_on = false;
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub async fn send(&mut self, command: Command) -> Result<(), PanelError<B::Error>> {
+        self.send_sequence(&[command]).await
+    }
+
+    /// Send a batch of commands in one go. The bus can stream these
+    /// without giving control back in between. If it fails partway, the
+    /// commands that did go out are still tracked.
+    pub async fn send_sequence(&mut self, commands: &[Command]) -> Result<(), PanelError<B::Error>> {
+        let result = self.bus.write_sequence(commands).await;
+        let sent = result.as_ref().map_or_else(|e| e.sent, |_| commands.len());
+        for command in &commands[..sent] {
+            self.track(*command);
+        }
+        result.map_err(|e| PanelError::Bus(e.error))
+    }
+
+    fn track(&mut self, command: Command) {
+        match command {
+            Command::SleepOut => self.sleeping = false,
+            Command::DisplayOn => self.display_on = true,
+            Command::DisplayOff => self.display_on = false,
+            _ => (),
+        }
+    }
+
+    /// Send the stock init sequence, this leaves the panel awake and on
+    pub async fn init(&mut self) -> Result<(), PanelError<B::Error>> {
+        self.send_sequence(&INIT_SEQUENCE).await
+    }
+
+    /// Blank the panel. It stays out of sleep, see the top of this file.
+    pub async fn sleep(&mut self) -> Result<(), PanelError<B::Error>> {
+        self.send(Command::DisplayOff).await
+    }
+
+    pub async fn wake(&mut self) -> Result<(), PanelError<B::Error>> {
+        self.send_sequence(&[Command::SleepOut, Command::DisplayOn]).await
+    }
+
+    pub async fn set_display_on(&mut self, on: bool) -> Result<(), PanelError<B::Error>> {
+        self.send(if on { Command::DisplayOn } else { Command::DisplayOff }).await
+    }
+
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    pub fn is_display_on(&self) -> bool {
+        self.display_on
+    }
+}
+
+/// Returned by [`MockPanelBus`] once its log is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogFull;
+
+/// A bus that records every transaction instead of sending it, for
+/// checking command sequences on the host
+pub struct MockPanelBus<const N: usize = 32> {
+    log: [[u8; 2]; N],
+    len: usize,
+}
+
+impl<const N: usize> MockPanelBus<N> {
+    pub const fn new() -> Self {
+        Self {
+            log: [[0; 2]; N],
+            len: 0,
+        }
+    }
+
+    /// Transactions so far, oldest first
+    pub fn transactions(&self) -> &[[u8; 2]] {
+        &self.log[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for MockPanelBus<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> PanelBus for MockPanelBus<N> {
+    type Error = LogFull;
+
+    async fn write(&mut self, bytes: [u8; 2]) -> Result<(), Self::Error> {
+        if self.len == N {
+            return Err(LogFull);
+        }
+        self.log[self.len] = bytes;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    const STATES: [PowerState; 5] =
+        [PowerState::Off, PowerState::RailsUp, PowerState::Reset, PowerState::Configured, PowerState::Sleeping];
+
+    fn step(from: PowerState, step: PowerStep) -> Result<PowerState, PowerError<()>> {
+        from.step(step)
+    }
+
+    #[test]
+    fn power_up_sleep_and_wake() {
+        let mut state = PowerState::Off;
+        for (s, expected) in [
+            (PowerStep::RailsUp, PowerState::RailsUp),
+            (PowerStep::Reset, PowerState::Reset),
+            (PowerStep::Configure, PowerState::Configured),
+            (PowerStep::Sleep, PowerState::Sleeping),
+            (PowerStep::Wake, PowerState::Configured),
+            (PowerStep::Reset, PowerState::Reset),
+            (PowerStep::PowerOff, PowerState::Off),
+        ] {
+            state = step(state, s).unwrap();
+            assert_eq!(state, expected);
+        }
+    }
+
+    #[test]
+    fn power_off_from_anywhere_reset_from_anywhere_powered() {
+        for state in STATES {
+            assert_eq!(step(state, PowerStep::PowerOff), Ok(PowerState::Off));
+            assert_eq!(step(state, PowerStep::Reset).is_ok(), state != PowerState::Off);
+        }
+    }
+
+    #[test]
+    fn invalid_steps_say_where_from_and_to() {
+        use PowerState::*;
+        for (from, s, to) in [
+            (Off, PowerStep::Reset, Reset),
+            (Off, PowerStep::Configure, Configured),
+            (Off, PowerStep::Wake, Configured),
+            (RailsUp, PowerStep::RailsUp, RailsUp),
+            (RailsUp, PowerStep::Configure, Configured),
+            (Reset, PowerStep::Wake, Configured),
+            (Reset, PowerStep::Sleep, Sleeping),
+            (Configured, PowerStep::Configure, Configured),
+            (Configured, PowerStep::Wake, Configured),
+            (Sleeping, PowerStep::Sleep, Sleeping),
+            (Sleeping, PowerStep::Configure, Configured),
+        ] {
+            assert_eq!(step(from, s), Err(PowerError::InvalidTransition { from, to }), "{from:?} {s:?}");
+        }
+    }
+
+    #[test]
+    fn init_sends_the_stock_sequence() {
+        let mut panel = Panel::new(MockPanelBus::<32>::new());
+        assert!(panel.is_sleeping() && !panel.is_display_on());
+
+        block_on(panel.init()).unwrap();
+        assert_eq!(
+            panel.bus().transactions(),
+            [
+                [0x08, 0x80],
+                [0x6e, 0x80],
+                [0x80, 0x80],
+                [0x68, 0x00],
+                [0xd0, 0x00],
+                [0x1b, 0x00],
+                [0xe0, 0x00],
+                [0x6a, 0x80],
+                [0x80, 0x00],
+                [0x14, 0x80],
+            ]
+        );
+        assert!(!panel.is_sleeping() && panel.is_display_on());
+    }
+
+    #[test]
+    fn sleep_only_blanks() {
+        let mut panel = Panel::new(MockPanelBus::<32>::new());
+        block_on(panel.init()).unwrap();
+        panel.bus_mut().clear();
+
+        block_on(panel.sleep()).unwrap();
+        assert_eq!(panel.bus().transactions(), [[0x14, 0x00]]);
+        assert!(!panel.is_sleeping() && !panel.is_display_on());
+
+        block_on(panel.wake()).unwrap();
+        assert_eq!(panel.bus().transactions(), [[0x14, 0x00], [0x08, 0x80], [0x14, 0x80]]);
+        assert!(panel.is_display_on());
+    }
+
+    #[test]
+    fn raw_writes_go_out_as_is() {
+        let mut panel = Panel::new(MockPanelBus::<32>::new());
+        block_on(panel.send(Command::Write { register: 0x12, value: 0x34 })).unwrap();
+        block_on(panel.set_display_on(true)).unwrap();
+
+        assert_eq!(panel.bus().transactions(), [[0x12, 0x34], [0x14, 0x80]]);
+        assert!(panel.is_display_on());
+    }
+
+    #[test]
+    fn reset_forgets_the_state() {
+        let mut panel = Panel::new(MockPanelBus::<32>::new());
+        block_on(panel.init()).unwrap();
+        panel.mark_reset();
+        assert!(panel.is_sleeping() && !panel.is_display_on());
+    }
+
+    #[test]
+    fn bus_errors_come_through() {
+        let mut panel = Panel::new(MockPanelBus::<4>::new());
+        assert_eq!(block_on(panel.init()), Err(PanelError::Bus(LogFull)));
+        assert_eq!(panel.bus().transactions().len(), 4);
+        // SleepOut was the first to go out, DisplayOn never did
+        assert!(!panel.is_sleeping() && !panel.is_display_on());
+
+        let mut panel = Panel::new(MockPanelBus::<2>::new());
+        block_on(panel.init()).unwrap_err();
+        block_on(panel.set_display_on(true)).unwrap_err();
+        assert!(!panel.is_sleeping() && !panel.is_display_on());
+    }
+
+    #[test]
+    fn sequences_say_how_far_they_got() {
+        let mut bus = MockPanelBus::<3>::new();
+        assert_eq!(block_on(bus.write_sequence(&INIT_SEQUENCE)), Err(SequenceError { sent: 3, error: LogFull }));
+        let mut bus = MockPanelBus::<3>::new();
+        assert_eq!(block_on(bus.write_sequence(&INIT_SEQUENCE[..3])), Ok(()));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::stream::{DecodeError, Decoder};
+
+// QOA, the "Quite OK Audio" format (https://qoaformat.org), 3.2 bits a
+// sample. After an 8 byte file header ("qoaf" and the length) come frames of
+// up to 256 slices, each frame with its own header and LMS predictor state.
+// A slice is a big endian 64 bit word: a 4 bit scale factor, then 20 3 bit
+// residuals. Every frame but the last holds 5120 samples, so a frame can be
+// found without reading the ones before it. Only mono files.
+
+pub const QOA_MAGIC: [u8; 4] = *b"qoaf";
+pub const FILE_HEADER_SIZE: usize = 8;
+pub const FRAME_HEADER_SIZE: usize = 8;
+/// History and weights, 4 x i16 each
+pub const LMS_STATE_SIZE: usize = 16;
+pub const SLICE_LEN: usize = 20;
+pub const SLICES_PER_FRAME: usize = 256;
+pub const FRAME_LEN: usize = SLICE_LEN * SLICES_PER_FRAME;
+
+/// round((s + 1) ^ 2.75)
+pub const SCALEFACTORS: [i32; 16] = [1, 7, 21, 45, 84, 138, 211, 304, 421, 562, 731, 928, 1157, 1419, 1715, 2048];
+
+/// Residual for each scale factor and 3 bit code: the scale factor times
+/// 0.75, 2.5, 4.5 and 7, rounded, alternating positive and negative
+pub const DEQUANT: [[i32; 8]; 16] = {
+    let mut table = [[0; 8]; 16];
+    let mut s = 0;
+    while s < 16 {
+        let sf = SCALEFACTORS[s];
+        let steps = [(sf * 3 + 2) / 4, (sf * 5 + 1) / 2, (sf * 9 + 1) / 2, sf * 7];
+        let mut q = 0;
+        while q < 4 {
+            table[s][q * 2] = steps[q];
+            table[s][q * 2 + 1] = -steps[q];
+            q += 1;
+        }
+        s += 1;
+    }
+    table
+};
+
+/// The frame size when it's full and mono
+const FULL_FRAME_SIZE: usize = FRAME_HEADER_SIZE + LMS_STATE_SIZE + SLICES_PER_FRAME * 8;
+
+/// Bytes in a mono frame of `samples`
+pub const fn frame_size(samples: usize) -> usize {
+    FRAME_HEADER_SIZE + LMS_STATE_SIZE + samples.div_ceil(SLICE_LEN) * 8
+}
+
+/// The 4 tap predictor, also used by the host side encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Lms {
+    pub history: [i32; 4],
+    pub weights: [i32; 4],
+}
+
+impl Lms {
+    pub fn predict(&self) -> i32 {
+        // wrapping, so made up weights give noise rather than a panic
+        let sum = (0..4).fold(0i32, |sum, i| sum.wrapping_add(self.weights[i].wrapping_mul(self.history[i])));
+        sum >> 13
+    }
+
+    pub fn update(&mut self, sample: i32, residual: i32) {
+        let delta = residual >> 4;
+        for (weight, &history) in self.weights.iter_mut().zip(&self.history) {
+            *weight = weight.wrapping_add(if history < 0 { -delta } else { delta });
+        }
+        self.history.copy_within(1.., 0);
+        self.history[3] = sample;
+    }
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+/// Mono QOA decoder, reading the file in place
+pub struct Qoa<'a> {
+    data: &'a [u8],
+    len: u32,
+    sample_rate: u32,
+    frame: usize,
+    /// Next sample within the frame
+    in_frame: usize,
+    lms: Lms,
+    /// What's left of the current slice, the next code in the top bits
+    slice: u64,
+    scalefactor: usize,
+}
+
+impl<'a> Qoa<'a> {
+    /// Open a QOA file, e.g. one written by the host `encode` tool. All
+    /// frame headers are checked here so decoding can't fail later.
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, DecodeError> {
+        if data.len() < FILE_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        if data[0..4] != QOA_MAGIC {
+            return Err(DecodeError::BadHeader);
+        }
+        let len = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        if len == 0 {
+            // streaming files leave the length out
+            return Err(DecodeError::Unsupported);
+        }
+
+        let mut sample_rate = 0;
+        let mut left = len as usize;
+        let mut at = FILE_HEADER_SIZE;
+        while left > 0 {
+            if data.len() < at + FRAME_HEADER_SIZE {
+                return Err(DecodeError::Truncated);
+            }
+            let header = u64_at(data, at);
+            let channels = (header >> 56) as u8;
+            let rate = (header >> 32) as u32 & 0xff_ffff;
+            let samples = (header >> 16) as u16 as usize;
+            let size = header as u16 as usize;
+            if channels != 1 {
+                return Err(DecodeError::Unsupported);
+            }
+            if samples != left.min(FRAME_LEN) || size != frame_size(samples) {
+                return Err(DecodeError::BadHeader);
+            }
+            if data.len() < at + size {
+                return Err(DecodeError::Truncated);
+            }
+            sample_rate = rate;
+            left -= samples;
+            at += size;
+        }
+
+        let mut qoa = Self {
+            data,
+            len,
+            sample_rate,
+            frame: 0,
+            in_frame: 0,
+            lms: Lms { history: [0; 4], weights: [0; 4] },
+            slice: 0,
+            scalefactor: 0,
+        };
+        qoa.seek(0);
+        Ok(qoa)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn frame_start(&self) -> usize {
+        FILE_HEADER_SIZE + self.frame * FULL_FRAME_SIZE
+    }
+
+    fn start_frame(&mut self) {
+        let at = self.frame_start() + FRAME_HEADER_SIZE;
+        let mut history = u64_at(self.data, at);
+        let mut weights = u64_at(self.data, at + 8);
+        for i in 0..4 {
+            self.lms.history[i] = (history >> 48) as i16 as i32;
+            self.lms.weights[i] = (weights >> 48) as i16 as i32;
+            history <<= 16;
+            weights <<= 16;
+        }
+    }
+
+    fn next(&mut self) -> i16 {
+        if self.in_frame.is_multiple_of(SLICE_LEN) {
+            if self.in_frame == FRAME_LEN {
+                self.frame += 1;
+                self.in_frame = 0;
+                self.start_frame();
+            }
+            let at = self.frame_start() + FRAME_HEADER_SIZE + LMS_STATE_SIZE + self.in_frame / SLICE_LEN * 8;
+            let slice = u64_at(self.data, at);
+            self.scalefactor = (slice >> 60) as usize;
+            self.slice = slice << 4;
+        }
+
+        let residual = DEQUANT[self.scalefactor][(self.slice >> 61) as usize];
+        self.slice <<= 3;
+        let sample = (self.lms.predict() + residual).clamp(i16::MIN as i32, i16::MAX as i32);
+        self.lms.update(sample, residual);
+        self.in_frame += 1;
+        sample as i16
+    }
+}
+
+impl Decoder for Qoa<'_> {
+    fn len(&self) -> u32 {
+        self.len
+    }
+
+    fn seek(&mut self, sample: u32) {
+        self.frame = sample as usize / FRAME_LEN;
+        self.in_frame = 0;
+        self.start_frame();
+        for _ in 0..sample as usize % FRAME_LEN {
+            self.next();
+        }
+    }
+
+    fn decode(&mut self, out: &mut [i16]) {
+        for sample in out {
+            *sample = self.next();
+        }
+    }
+}
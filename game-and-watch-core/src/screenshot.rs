@@ -0,0 +1,177 @@
+use embedded_graphics::pixelcolor::{raw::RawU16, Rgb565, Rgb888};
+use embedded_graphics::prelude::*;
+
+use crate::framebuffer::Frame;
+
+// Screenshot encoders. They stream their output in small pieces through a
+// callback, so a frame can go straight out over RTT or into flash without
+// a second framebuffer sized buffer.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScreenshotFormat {
+    /// 16 bit RGB565 BMP, lossless and what most tools open directly
+    Bmp,
+    /// QOI, lossless and usually a fraction of the size
+    Qoi,
+}
+
+impl ScreenshotFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ScreenshotFormat::Bmp => "bmp",
+            ScreenshotFormat::Qoi => "qoi",
+        }
+    }
+}
+
+/// Encode `frame` in `format`, handing the output to `out` piece by piece
+pub fn encode<F: FnMut(&[u8])>(frame: &Frame<'_>, format: ScreenshotFormat, out: F) {
+    match format {
+        ScreenshotFormat::Bmp => encode_bmp(frame, out),
+        ScreenshotFormat::Qoi => encode_qoi(frame, out),
+    }
+}
+
+const BMP_HEADER_SIZE: u32 = 14;
+// BITMAPV4HEADER, the oldest one with the channel masks inside the header
+// that everything reads
+const BMP_INFO_SIZE: u32 = 108;
+const BMP_BI_BITFIELDS: u32 = 3;
+const BMP_LCS_SRGB: u32 = u32::from_be_bytes(*b"sRGB");
+const BMP_MASKS: [u32; 4] = [0xf800, 0x07e0, 0x001f, 0];
+
+/// Size in bytes of the BMP `encode_bmp` produces for `size`
+pub fn bmp_len(size: Size) -> u32 {
+    BMP_HEADER_SIZE + BMP_INFO_SIZE + bmp_stride(size.width) * size.height
+}
+
+fn bmp_stride(width: u32) -> u32 {
+    (width * 2).next_multiple_of(4)
+}
+
+/// 16 bit BMP with RGB565 bitfields, the framebuffer format as is
+pub fn encode_bmp<F: FnMut(&[u8])>(frame: &Frame<'_>, mut out: F) {
+    let size = frame.size;
+    let data_offset = BMP_HEADER_SIZE + BMP_INFO_SIZE;
+
+    let mut header = [0u8; (BMP_HEADER_SIZE + BMP_INFO_SIZE) as usize];
+    header[0..2].copy_from_slice(b"BM");
+    header[2..6].copy_from_slice(&bmp_len(size).to_le_bytes());
+    header[10..14].copy_from_slice(&data_offset.to_le_bytes());
+
+    let info = &mut header[BMP_HEADER_SIZE as usize..];
+    info[0..4].copy_from_slice(&BMP_INFO_SIZE.to_le_bytes());
+    info[4..8].copy_from_slice(&size.width.to_le_bytes());
+    // positive height means the rows are stored bottom up
+    info[8..12].copy_from_slice(&size.height.to_le_bytes());
+    info[12..14].copy_from_slice(&1u16.to_le_bytes());
+    info[14..16].copy_from_slice(&16u16.to_le_bytes());
+    info[16..20].copy_from_slice(&BMP_BI_BITFIELDS.to_le_bytes());
+    info[20..24].copy_from_slice(&(bmp_stride(size.width) * size.height).to_le_bytes());
+    for (i, mask) in BMP_MASKS.iter().enumerate() {
+        let at = 40 + i * 4;
+        info[at..at + 4].copy_from_slice(&mask.to_le_bytes());
+    }
+    info[56..60].copy_from_slice(&BMP_LCS_SRGB.to_le_bytes());
+    out(&header);
+
+    let padding = (bmp_stride(size.width) - size.width * 2) as usize;
+    let rows = (0..size.height as usize).rev().map(|y| &frame.pixels[y * frame.stride..y * frame.stride + size.width as usize]);
+    for row in rows {
+        let mut chunk = [0u8; 64];
+        for pixels in row.chunks(chunk.len() / 2) {
+            for (bytes, pixel) in chunk.chunks_exact_mut(2).zip(pixels) {
+                bytes.copy_from_slice(&pixel.to_le_bytes());
+            }
+            out(&chunk[..pixels.len() * 2]);
+        }
+        if padding > 0 {
+            out(&[0; 3][..padding]);
+        }
+    }
+}
+
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RUN: u8 = 0xc0;
+const QOI_OP_RGB: u8 = 0xfe;
+const QOI_END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+fn qoi_hash([r, g, b, a]: [u8; 4]) -> usize {
+    (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64
+}
+
+/// QOI, see https://qoiformat.org/qoi-specification.pdf. The pixels are
+/// expanded to 8 bits per channel the same way the LTDC does it.
+pub fn encode_qoi<F: FnMut(&[u8])>(frame: &Frame<'_>, mut out: F) {
+    let mut header = [0u8; 14];
+    header[0..4].copy_from_slice(b"qoif");
+    header[4..8].copy_from_slice(&frame.size.width.to_be_bytes());
+    header[8..12].copy_from_slice(&frame.size.height.to_be_bytes());
+    header[12] = 3; // RGB
+    header[13] = 1; // all channels linear
+    out(&header);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0, 0, 0, 255];
+    let mut run = 0u8;
+
+    let mut buf = [0u8; 64];
+    let mut len = 0;
+    let mut emit = |bytes: &[u8], out: &mut F| {
+        if len + bytes.len() > buf.len() {
+            out(&buf[..len]);
+            len = 0;
+        }
+        buf[len..len + bytes.len()].copy_from_slice(bytes);
+        len += bytes.len();
+    };
+
+    for &raw in frame.rows().flatten() {
+        let color: Rgb888 = Rgb565::from(RawU16::new(raw)).into();
+        let px = [color.r(), color.g(), color.b(), 255];
+
+        if px == prev {
+            run += 1;
+            if run == 62 {
+                emit(&[QOI_OP_RUN | (run - 1)], &mut out);
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            emit(&[QOI_OP_RUN | (run - 1)], &mut out);
+            run = 0;
+        }
+
+        let hash = qoi_hash(px);
+        if index[hash] == px {
+            emit(&[QOI_OP_INDEX | hash as u8], &mut out);
+        } else {
+            index[hash] = px;
+
+            let dr = px[0].wrapping_sub(prev[0]) as i8;
+            let dg = px[1].wrapping_sub(prev[1]) as i8;
+            let db = px[2].wrapping_sub(prev[2]) as i8;
+            let dr_dg = dr.wrapping_sub(dg);
+            let db_dg = db.wrapping_sub(dg);
+
+            if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                emit(&[QOI_OP_DIFF | (((dr + 2) as u8) << 4) | (((dg + 2) as u8) << 2) | (db + 2) as u8], &mut out);
+            } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                emit(&[QOI_OP_LUMA | (dg + 32) as u8, (((dr_dg + 8) as u8) << 4) | (db_dg + 8) as u8], &mut out);
+            } else {
+                emit(&[QOI_OP_RGB, px[0], px[1], px[2]], &mut out);
+            }
+        }
+        prev = px;
+    }
+
+    if run > 0 {
+        emit(&[QOI_OP_RUN | (run - 1)], &mut out);
+    }
+    emit(&QOI_END, &mut out);
+    out(&buf[..len]);
+}
@@ -0,0 +1,527 @@
+use embedded_graphics::{
+    image::{Image, ImageDrawable, ImageDrawableExt},
+    prelude::*,
+    primitives::Rectangle,
+};
+
+// Sprites cut from an atlas image, e.g. a tinybmp::Bmp or an ImageRaw in
+// flash. Each frame is drawn through a small adapter that does the flips
+// and the transparent colour, so the atlas itself is never copied.
+
+/// An atlas of equally sized frames, numbered left to right and top to
+/// bottom
+pub struct SpriteSheet<'a, I: ImageDrawable> {
+    image: &'a I,
+    frame_size: Size,
+    columns: u32,
+    frames: u32,
+    transparent: Option<I::Color>,
+}
+
+impl<'a, I: ImageDrawable> SpriteSheet<'a, I> {
+    /// Cut `image` into a grid of `frame_size` frames, any partial frames
+    /// at the right and bottom edges are ignored. Panics if not even one
+    /// frame fits.
+    pub fn new(image: &'a I, frame_size: Size) -> Self {
+        let size = image.size();
+        assert!(frame_size.width > 0 && frame_size.height > 0, "empty sprite frame");
+        assert!(frame_size.width <= size.width && frame_size.height <= size.height, "sprite frame bigger than the image");
+        let columns = size.width / frame_size.width;
+        let rows = size.height / frame_size.height;
+
+        Self {
+            image,
+            frame_size,
+            columns,
+            frames: columns * rows,
+            transparent: None,
+        }
+    }
+
+    /// The whole image as a single frame
+    pub fn single(image: &'a I) -> Self {
+        Self::new(image, image.size())
+    }
+
+    /// Pixels of this colour aren't drawn
+    pub fn with_transparent(mut self, color: I::Color) -> Self {
+        self.transparent = Some(color);
+        self
+    }
+
+    pub fn frame_size(&self) -> Size {
+        self.frame_size
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Where frame `index` is in the atlas
+    pub fn frame_area(&self, index: u32) -> Rectangle {
+        let index = index % self.frames.max(1);
+        let x = (index % self.columns) * self.frame_size.width;
+        let y = (index / self.columns) * self.frame_size.height;
+        Rectangle::new(Point::new(x as i32, y as i32), self.frame_size)
+    }
+
+    /// Draw frame `index` with its top left corner at `pos`
+    pub fn draw_frame<D>(&self, index: u32, pos: Point, flip_x: bool, flip_y: bool, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = I::Color>,
+    {
+        let frame = self.image.sub_image(&self.frame_area(index));
+        let mut target = Placed {
+            target,
+            pos,
+            size: self.frame_size,
+            flip_x,
+            flip_y,
+            transparent: self.transparent,
+        };
+        Image::new(&frame, Point::zero()).draw(&mut target)
+    }
+}
+
+/// Moves, flips and keys out the pixels of one frame on their way to the
+/// real target
+struct Placed<'t, D: DrawTarget> {
+    target: &'t mut D,
+    pos: Point,
+    size: Size,
+    flip_x: bool,
+    flip_y: bool,
+    transparent: Option<D::Color>,
+}
+
+impl<'t, D: DrawTarget> DrawTarget for Placed<'t, D> {
+    type Color = D::Color;
+    type Error = D::Error;
+
+    fn draw_iter<P>(&mut self, pixels: P) -> Result<(), Self::Error>
+    where
+        P: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let (w, h) = (self.size.width as i32, self.size.height as i32);
+        let (pos, flip_x, flip_y, transparent) = (self.pos, self.flip_x, self.flip_y, self.transparent);
+
+        self.target.draw_iter(
+            pixels
+                .into_iter()
+                .filter(|Pixel(_, color)| Some(*color) != transparent)
+                .map(|Pixel(p, color)| {
+                    let x = if flip_x { w - 1 - p.x } else { p.x };
+                    let y = if flip_y { h - 1 - p.y } else { p.y };
+                    Pixel(pos + Point::new(x, y), color)
+                }),
+        )
+    }
+
+    fn fill_contiguous<P>(&mut self, area: &Rectangle, colors: P) -> Result<(), Self::Error>
+    where
+        P: IntoIterator<Item = Self::Color>,
+    {
+        if self.flip_x || self.flip_y || self.transparent.is_some() {
+            self.draw_iter(area.points().zip(colors).map(|(p, c)| Pixel(p, c)))
+        } else {
+            // nothing to do per pixel, keep the target's fast path
+            self.target.fill_contiguous(&area.translate(self.pos), colors)
+        }
+    }
+}
+
+impl<'t, D: DrawTarget> OriginDimensions for Placed<'t, D> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+/// One frame of an animation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationFrame {
+    /// Frame in the sprite sheet
+    pub index: u16,
+    pub duration_ms: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Animation<'a> {
+    pub frames: &'a [AnimationFrame],
+    pub looping: bool,
+}
+
+/// Plays an [`Animation`], driven by elapsed time
+#[derive(Debug, Clone, Copy)]
+pub struct AnimationPlayer<'a> {
+    animation: Animation<'a>,
+    current: usize,
+    elapsed_us: u64,
+    finished: bool,
+}
+
+impl<'a> AnimationPlayer<'a> {
+    pub fn new(animation: Animation<'a>) -> Self {
+        Self {
+            animation,
+            current: 0,
+            elapsed_us: 0,
+            finished: animation.frames.is_empty(),
+        }
+    }
+
+    /// Switch to another animation from its start, unless it's already
+    /// the one playing
+    pub fn play(&mut self, animation: Animation<'a>) {
+        if !core::ptr::eq(self.animation.frames, animation.frames) {
+            *self = Self::new(animation);
+        }
+    }
+
+    pub fn restart(&mut self) {
+        *self = Self::new(self.animation);
+    }
+
+    /// A non looping animation stops on its last frame
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Sprite sheet index of the frame to show
+    pub fn frame(&self) -> u32 {
+        self.animation.frames.get(self.current).map_or(0, |f| f.index as u32)
+    }
+
+    pub fn update(&mut self, elapsed_us: u64) {
+        if self.finished {
+            return;
+        }
+        self.elapsed_us += elapsed_us;
+
+        let frames = self.animation.frames;
+        loop {
+            let duration = frames[self.current].duration_ms as u64 * 1000;
+            if self.elapsed_us < duration || duration == 0 {
+                break;
+            }
+            self.elapsed_us -= duration;
+
+            if self.current + 1 < frames.len() {
+                self.current += 1;
+            } else if self.animation.looping {
+                self.current = 0;
+            } else {
+                self.finished = true;
+                break;
+            }
+        }
+    }
+}
+
+pub struct Sprite<'a, I: ImageDrawable> {
+    pub sheet: &'a SpriteSheet<'a, I>,
+    pub frame: u32,
+    pub pos: Point,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// Higher is drawn later, on top
+    pub z: i16,
+}
+
+impl<'a, I: ImageDrawable> Sprite<'a, I> {
+    pub fn new(sheet: &'a SpriteSheet<'a, I>, pos: Point) -> Self {
+        Self {
+            sheet,
+            frame: 0,
+            pos,
+            flip_x: false,
+            flip_y: false,
+            z: 0,
+        }
+    }
+
+    pub fn bounding_box(&self) -> Rectangle {
+        Rectangle::new(self.pos, self.sheet.frame_size())
+    }
+
+    pub fn draw<D: DrawTarget<Color = I::Color>>(&self, target: &mut D) -> Result<(), D::Error> {
+        self.sheet.draw_frame(self.frame, self.pos, self.flip_x, self.flip_y, target)
+    }
+}
+
+/// Returned when a [`SpriteList`] has no room left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteListFull;
+
+/// The sprites for one frame, drawn back to front by z. Sprites with the
+/// same z are drawn in the order they were added.
+pub struct SpriteList<'a, I: ImageDrawable, const N: usize> {
+    sprites: [Option<Sprite<'a, I>>; N],
+    len: usize,
+}
+
+impl<'a, I: ImageDrawable, const N: usize> SpriteList<'a, I, N> {
+    pub fn new() -> Self {
+        Self {
+            sprites: [const { None }; N],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, sprite: Sprite<'a, I>) -> Result<(), SpriteListFull> {
+        if self.len == N {
+            return Err(SpriteListFull);
+        }
+        self.sprites[self.len] = Some(sprite);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.iter_mut().for_each(|s| *s = None);
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Sprite<'a, I>> {
+        self.sprites[..self.len].iter().flatten()
+    }
+
+    /// Mark every sprite's area dirty, e.g. with
+    /// [`SwapChain::mark_dirty`](crate::framebuffer::SwapChain::mark_dirty)
+    pub fn for_each_area(&self, mut f: impl FnMut(&Rectangle)) {
+        self.iter().for_each(|s| f(&s.bounding_box()));
+    }
+
+    pub fn draw<D: DrawTarget<Color = I::Color>>(&self, target: &mut D) -> Result<(), D::Error> {
+        let mut order = [0usize; N];
+        for (i, o) in order[..self.len].iter_mut().enumerate() {
+            *o = i;
+        }
+        let z = |i: usize| self.sprites[i].as_ref().map_or(0, |s| s.z);
+        order[..self.len].sort_unstable_by_key(|&i| (z(i), i));
+
+        for &i in &order[..self.len] {
+            if let Some(sprite) = &self.sprites[i] {
+                sprite.draw(target)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, I: ImageDrawable, const N: usize> Default for SpriteList<'a, I, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::{image::ImageRawLE, mock_display::MockDisplay, pixelcolor::Rgb565};
+
+    use super::*;
+
+    /// Two 2x2 frames side by side, RG/BY and WK/CM
+    fn colours() -> [u8; 16] {
+        let pixels = [
+            Rgb565::RED, Rgb565::GREEN, Rgb565::WHITE, Rgb565::BLACK,
+            Rgb565::BLUE, Rgb565::YELLOW, Rgb565::CYAN, Rgb565::MAGENTA,
+        ];
+        let mut bytes = [0; 16];
+        for (out, pixel) in bytes.chunks_exact_mut(2).zip(pixels) {
+            out.copy_from_slice(&pixel.into_storage().to_le_bytes());
+        }
+        bytes
+    }
+
+    fn draw(sheet: &SpriteSheet<'_, ImageRawLE<'_, Rgb565>>, frame: u32, flip_x: bool, flip_y: bool) -> MockDisplay<Rgb565> {
+        let mut display = MockDisplay::new();
+        sheet.draw_frame(frame, Point::new(1, 1), flip_x, flip_y, &mut display).unwrap();
+        display
+    }
+
+    const fn frame(index: u16, duration_ms: u16) -> AnimationFrame {
+        AnimationFrame { index, duration_ms }
+    }
+
+    const WALK: [AnimationFrame; 3] = [frame(0, 100), frame(1, 50), frame(2, 200)];
+
+    /// 3x2 frames of 2x2 pixels with a column and a row to spare
+    const ATLAS: [u8; 7 * 5 * 2] = [0; 7 * 5 * 2];
+
+    #[test]
+    fn frames_are_numbered_across_then_down() {
+        let image = ImageRawLE::<Rgb565>::new(&ATLAS, 7);
+        let sheet = SpriteSheet::new(&image, Size::new(2, 2));
+
+        assert_eq!(sheet.frames(), 6);
+        assert_eq!(sheet.frame_area(1), Rectangle::new(Point::new(2, 0), Size::new(2, 2)));
+        assert_eq!(sheet.frame_area(4), Rectangle::new(Point::new(2, 2), Size::new(2, 2)));
+        // wraps around
+        assert_eq!(sheet.frame_area(7), sheet.frame_area(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "sprite frame bigger than the image")]
+    fn frame_wider_than_the_image() {
+        let image = ImageRawLE::<Rgb565>::new(&ATLAS, 7);
+        SpriteSheet::new(&image, Size::new(8, 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "sprite frame bigger than the image")]
+    fn frame_taller_than_the_image() {
+        let image = ImageRawLE::<Rgb565>::new(&ATLAS, 7);
+        SpriteSheet::new(&image, Size::new(2, 6));
+    }
+
+    #[test]
+    fn frames_flip_in_place() {
+        let data = colours();
+        let image = ImageRawLE::<Rgb565>::new(&data, 4);
+        let sheet = SpriteSheet::new(&image, Size::new(2, 2));
+
+        draw(&sheet, 0, false, false).assert_pattern(&["   ", " RG", " BY"]);
+        draw(&sheet, 1, false, false).assert_pattern(&["   ", " WK", " CM"]);
+        draw(&sheet, 0, true, false).assert_pattern(&["   ", " GR", " YB"]);
+        draw(&sheet, 0, false, true).assert_pattern(&["   ", " BY", " RG"]);
+        draw(&sheet, 0, true, true).assert_pattern(&["   ", " YB", " GR"]);
+    }
+
+    #[test]
+    fn transparent_pixels_are_left_alone() {
+        let data = colours();
+        let image = ImageRawLE::<Rgb565>::new(&data, 4);
+        let sheet = SpriteSheet::new(&image, Size::new(2, 2)).with_transparent(Rgb565::GREEN);
+
+        draw(&sheet, 0, false, false).assert_pattern(&["   ", " R ", " BY"]);
+        // the hole moves with the flip
+        draw(&sheet, 0, true, false).assert_pattern(&["   ", "  R", " YB"]);
+        draw(&sheet, 0, true, true).assert_pattern(&["   ", " YB", "  R"]);
+        // frames without the colour are drawn whole
+        draw(&sheet, 1, false, false).assert_pattern(&["   ", " WK", " CM"]);
+    }
+
+    #[test]
+    fn animations_hold_each_frame_for_its_duration() {
+        let mut player = AnimationPlayer::new(Animation { frames: &WALK, looping: true });
+        assert_eq!(player.frame(), 0);
+        player.update(99_999);
+        assert_eq!(player.frame(), 0);
+        player.update(1);
+        assert_eq!(player.frame(), 1);
+        player.update(50_000);
+        assert_eq!(player.frame(), 2);
+        // the time left over carries into the next frames
+        player.update(310_000);
+        assert_eq!(player.frame(), 1);
+        assert!(!player.is_finished());
+    }
+
+    #[test]
+    fn looping_animations_wrap_around() {
+        let mut player = AnimationPlayer::new(Animation { frames: &WALK, looping: true });
+        // three times round and 120ms into the fourth
+        player.update(3 * 350_000 + 120_000);
+        assert_eq!(player.frame(), 1);
+        player.update(30_000);
+        assert_eq!(player.frame(), 2);
+        player.update(200_000);
+        assert_eq!(player.frame(), 0);
+        assert!(!player.is_finished());
+    }
+
+    #[test]
+    fn one_shot_animations_stop_on_the_last_frame() {
+        let mut player = AnimationPlayer::new(Animation { frames: &WALK, looping: false });
+        player.update(349_999);
+        assert_eq!(player.frame(), 2);
+        assert!(!player.is_finished());
+        player.update(1);
+        assert_eq!(player.frame(), 2);
+        assert!(player.is_finished());
+        player.update(1_000_000);
+        assert_eq!(player.frame(), 2);
+
+        player.restart();
+        assert_eq!(player.frame(), 0);
+        assert!(!player.is_finished());
+    }
+
+    #[test]
+    fn zero_length_frames_hold_forever() {
+        let frames = [frame(3, 10), frame(4, 0), frame(5, 10)];
+        let mut player = AnimationPlayer::new(Animation { frames: &frames, looping: true });
+        player.update(1_000_000);
+        assert_eq!(player.frame(), 4);
+
+        let mut empty = AnimationPlayer::new(Animation { frames: &[], looping: true });
+        empty.update(1_000);
+        assert_eq!(empty.frame(), 0);
+        assert!(empty.is_finished());
+    }
+
+    #[test]
+    fn playing_the_same_animation_keeps_going() {
+        let walk = Animation { frames: &WALK, looping: true };
+        let mut player = AnimationPlayer::new(walk);
+        player.update(120_000);
+        player.play(walk);
+        assert_eq!(player.frame(), 1);
+
+        let idle = [frame(7, 100)];
+        player.play(Animation { frames: &idle, looping: true });
+        assert_eq!(player.frame(), 7);
+        player.play(walk);
+        assert_eq!(player.frame(), 0);
+    }
+
+    #[test]
+    fn sprites_are_drawn_back_to_front() {
+        let data = colours();
+        let image = ImageRawLE::<Rgb565>::new(&data, 4);
+        let sheet = SpriteSheet::new(&image, Size::new(2, 2));
+        let sprite = |frame, x, z| Sprite { frame, z, ..Sprite::new(&sheet, Point::new(x, 0)) };
+
+        let draw_list = |sprites: &[Sprite<'_, _>]| {
+            let mut list = SpriteList::<_, 3>::new();
+            for s in sprites {
+                list.push(Sprite { ..*s }).unwrap();
+            }
+            let mut display = MockDisplay::new();
+            display.set_allow_overdraw(true);
+            list.draw(&mut display).unwrap();
+            display
+        };
+
+        // higher z on top whatever the order they were added in
+        draw_list(&[sprite(0, 0, 1), sprite(1, 1, 0)]).assert_pattern(&["RGK", "BYM"]);
+        draw_list(&[sprite(0, 0, -1), sprite(1, 1, 0)]).assert_pattern(&["RWK", "BCM"]);
+        draw_list(&[sprite(1, 1, 2), sprite(0, 0, 1), sprite(1, 2, -3)]).assert_pattern(&["RWKK", "BCMM"]);
+        // the same z goes by the order they were added in
+        draw_list(&[sprite(0, 0, 0), sprite(1, 1, 0)]).assert_pattern(&["RWK", "BCM"]);
+        draw_list(&[sprite(1, 1, 0), sprite(0, 0, 0)]).assert_pattern(&["RGK", "BYM"]);
+    }
+
+    #[test]
+    fn sprite_lists_fill_up() {
+        let data = colours();
+        let image = ImageRawLE::<Rgb565>::new(&data, 4);
+        let sheet = SpriteSheet::new(&image, Size::new(2, 2));
+        let mut list = SpriteList::<_, 2>::new();
+        assert!(list.is_empty());
+        list.push(Sprite::new(&sheet, Point::zero())).unwrap();
+        list.push(Sprite::new(&sheet, Point::zero())).unwrap();
+        assert_eq!(list.push(Sprite::new(&sheet, Point::zero())), Err(SpriteListFull));
+        assert_eq!(list.len(), 2);
+        list.clear();
+        assert!(list.is_empty());
+    }
+}
@@ -0,0 +1,437 @@
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use crate::adpcm::ImaAdpcm;
+use crate::audio::AudioSource;
+use crate::qoa::Qoa;
+
+// Playing long audio straight out of (memory mapped) flash, as raw PCM or
+// compressed with one of the decoders below. The stream is handed to the
+// mixer, which lives with the audio task, so the game talks to it through
+// a StreamControl: seeking goes in, the position and loop/end events come
+// out.
+
+const NO_SEEK: u32 = u32::MAX;
+const EVENT_LOOPED: u8 = 1 << 0;
+const EVENT_ENDED: u8 = 1 << 1;
+
+/// What happened to a stream since the last [`StreamControl::take_events`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StreamEvents {
+    /// Went back to the loop start at least once
+    pub looped: bool,
+    /// Played to the end, the mixer has let go of it
+    pub ended: bool,
+}
+
+/// Shared between a [`Stream`] and whoever controls it, usually as a
+/// `static`
+#[derive(Debug)]
+pub struct StreamControl {
+    seek: AtomicU32,
+    position: AtomicU32,
+    events: AtomicU8,
+}
+
+impl StreamControl {
+    pub const fn new() -> Self {
+        Self {
+            seek: AtomicU32::new(NO_SEEK),
+            position: AtomicU32::new(0),
+            events: AtomicU8::new(0),
+        }
+    }
+
+    /// Jump to `sample`, from the next block the stream plays
+    pub fn seek(&self, sample: u32) {
+        self.seek.store(sample, Ordering::Relaxed);
+    }
+
+    /// Sample the stream had reached after its last block
+    pub fn position(&self) -> u32 {
+        self.position.load(Ordering::Relaxed)
+    }
+
+    /// Events since the last call
+    pub fn take_events(&self) -> StreamEvents {
+        let events = self.events.swap(0, Ordering::Relaxed);
+        StreamEvents {
+            looped: events & EVENT_LOOPED != 0,
+            ended: events & EVENT_ENDED != 0,
+        }
+    }
+
+    fn post(&self, event: u8) {
+        self.events.fetch_or(event, Ordering::Relaxed);
+    }
+}
+
+impl Default for StreamControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Repeat part of a stream, in samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LoopPoints {
+    pub start: u32,
+    /// One past the last sample of the loop, clamped to the stream length
+    pub end: u32,
+    /// Times to go round, None for ever
+    pub count: Option<u32>,
+}
+
+/// Why encoded audio was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecodeError {
+    /// Not the format it was opened as
+    BadHeader,
+    /// A valid file, but e.g. stereo
+    Unsupported,
+    /// The data ends before the header says it does
+    Truncated,
+}
+
+/// Turns some encoding back into mono samples for a [`Stream`]
+pub trait Decoder {
+    /// Length in samples
+    fn len(&self) -> u32;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Carry on from `sample`, which is below `len()`
+    fn seek(&mut self, sample: u32);
+
+    /// Decode the next `out.len()` samples. The caller doesn't ask for
+    /// more than are left.
+    fn decode(&mut self, out: &mut [i16]);
+}
+
+/// Signed 16 bit little endian mono PCM, played in place
+pub struct Pcm<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Pcm<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+}
+
+impl Decoder for Pcm<'_> {
+    fn len(&self) -> u32 {
+        (self.data.len() / 2) as u32
+    }
+
+    fn seek(&mut self, sample: u32) {
+        self.pos = sample as usize;
+    }
+
+    fn decode(&mut self, out: &mut [i16]) {
+        let start = self.pos * 2;
+        let bytes = &self.data[start..start + out.len() * 2];
+        for (sample, b) in out.iter_mut().zip(bytes.chunks_exact(2)) {
+            *sample = i16::from_le_bytes([b[0], b[1]]);
+        }
+        self.pos += out.len();
+    }
+}
+
+/// The formats a [`Stream`] can play
+pub enum Codec<'a> {
+    Pcm(Pcm<'a>),
+    ImaAdpcm(ImaAdpcm<'a>),
+    Qoa(Qoa<'a>),
+}
+
+impl<'a> From<Pcm<'a>> for Codec<'a> {
+    fn from(pcm: Pcm<'a>) -> Self {
+        Codec::Pcm(pcm)
+    }
+}
+
+impl<'a> From<ImaAdpcm<'a>> for Codec<'a> {
+    fn from(adpcm: ImaAdpcm<'a>) -> Self {
+        Codec::ImaAdpcm(adpcm)
+    }
+}
+
+impl<'a> From<Qoa<'a>> for Codec<'a> {
+    fn from(qoa: Qoa<'a>) -> Self {
+        Codec::Qoa(qoa)
+    }
+}
+
+impl Decoder for Codec<'_> {
+    fn len(&self) -> u32 {
+        match self {
+            Codec::Pcm(d) => d.len(),
+            Codec::ImaAdpcm(d) => d.len(),
+            Codec::Qoa(d) => d.len(),
+        }
+    }
+
+    fn seek(&mut self, sample: u32) {
+        match self {
+            Codec::Pcm(d) => d.seek(sample),
+            Codec::ImaAdpcm(d) => d.seek(sample),
+            Codec::Qoa(d) => d.seek(sample),
+        }
+    }
+
+    fn decode(&mut self, out: &mut [i16]) {
+        match self {
+            Codec::Pcm(d) => d.decode(out),
+            Codec::ImaAdpcm(d) => d.decode(out),
+            Codec::Qoa(d) => d.decode(out),
+        }
+    }
+}
+
+/// Audio played from memory, with optional loop points and control
+pub struct Stream<'a> {
+    codec: Codec<'a>,
+    len: u32,
+    pos: u32,
+    loop_points: Option<LoopPoints>,
+    loops_done: u32,
+    control: Option<&'a StreamControl>,
+}
+
+impl<'a> Stream<'a> {
+    pub fn new(codec: impl Into<Codec<'a>>) -> Self {
+        let codec = codec.into();
+        Self {
+            len: codec.len(),
+            codec,
+            pos: 0,
+            loop_points: None,
+            loops_done: 0,
+            control: None,
+        }
+    }
+
+    pub fn with_loop(mut self, loop_points: LoopPoints) -> Self {
+        self.loop_points = Some(loop_points);
+        self
+    }
+
+    pub fn with_control(mut self, control: &'a StreamControl) -> Self {
+        self.control = Some(control);
+        control.position.store(self.pos, Ordering::Relaxed);
+        self
+    }
+
+    /// Length in samples
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn position(&self) -> u32 {
+        self.pos
+    }
+
+    /// Jump to `sample`, past the end means the stream ends
+    pub fn seek(&mut self, sample: u32) {
+        self.pos = sample.min(self.len);
+        if self.pos < self.len {
+            self.codec.seek(self.pos);
+        }
+    }
+
+    /// The loop the stream is in, if it still has rounds to go
+    fn active_loop(&self) -> Option<LoopPoints> {
+        self.loop_points
+            .filter(|l| l.count.is_none_or(|count| self.loops_done < count))
+            .filter(|l| l.start < l.end.min(self.len()) && self.pos <= l.end.min(self.len()))
+    }
+
+    fn post(&self, event: u8) {
+        if let Some(control) = self.control {
+            control.post(event);
+        }
+    }
+}
+
+impl<'a> AudioSource for Stream<'a> {
+    fn fill(&mut self, out: &mut [i16]) -> usize {
+        if let Some(control) = self.control {
+            let seek = control.seek.swap(NO_SEEK, Ordering::Relaxed);
+            if seek != NO_SEEK {
+                self.seek(seek);
+            }
+        }
+
+        let mut written = 0;
+        while written < out.len() {
+            let active = self.active_loop();
+            let end = active.map_or(self.len(), |l| l.end.min(self.len()));
+            if self.pos >= end {
+                match active {
+                    Some(l) => {
+                        self.seek(l.start);
+                        self.loops_done += 1;
+                        self.post(EVENT_LOOPED);
+                        continue;
+                    }
+                    None => {
+                        self.post(EVENT_ENDED);
+                        break;
+                    }
+                }
+            }
+
+            let n = (out.len() - written).min((end - self.pos) as usize);
+            self.codec.decode(&mut out[written..written + n]);
+            self.pos += n as u32;
+            written += n;
+        }
+
+        if let Some(control) = self.control {
+            control.position.store(self.pos, Ordering::Relaxed);
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16 samples, each its own index
+    const DATA: [u8; 32] = {
+        let mut data = [0; 32];
+        let mut i = 0;
+        while i < 16 {
+            data[i * 2] = i as u8;
+            i += 1;
+        }
+        data
+    };
+
+    fn stream(len: usize) -> Stream<'static> {
+        Stream::new(Pcm::new(&DATA[..len * 2]))
+    }
+
+    fn looped(start: u32, end: u32, count: Option<u32>) -> Stream<'static> {
+        stream(8).with_loop(LoopPoints { start, end, count })
+    }
+
+    #[test]
+    fn seeks_apply_on_the_next_fill() {
+        let control = StreamControl::new();
+        let mut s = stream(16).with_control(&control);
+        let mut out = [0; 3];
+        s.fill(&mut out);
+        control.seek(10);
+        assert_eq!((s.position(), control.position()), (3, 3));
+
+        assert_eq!(s.fill(&mut out), 3);
+        assert_eq!(out, [10, 11, 12]);
+        assert_eq!(control.position(), 13);
+        // only once
+        s.fill(&mut out);
+        assert_eq!(out, [13, 14, 15]);
+
+        control.seek(1);
+        s.fill(&mut out);
+        assert_eq!(out, [1, 2, 3]);
+        assert_eq!(control.take_events(), StreamEvents::default());
+    }
+
+    #[test]
+    fn seeking_past_the_end_ends_the_stream() {
+        let control = StreamControl::new();
+        let mut s = stream(8).with_control(&control);
+        control.seek(100);
+        let mut out = [0; 4];
+        assert_eq!(s.fill(&mut out), 0);
+        assert_eq!(control.position(), 8);
+        assert_eq!(control.take_events(), StreamEvents { looped: false, ended: true });
+
+        // and back in from there
+        s.seek(6);
+        assert_eq!(s.fill(&mut out), 2);
+        assert_eq!(out[..2], [6, 7]);
+    }
+
+    #[test]
+    fn streams_end_once_played() {
+        let control = StreamControl::new();
+        let mut s = stream(8).with_control(&control);
+        let mut out = [0; 5];
+        assert_eq!(s.fill(&mut out), 5);
+        assert_eq!(control.take_events(), StreamEvents::default());
+        assert_eq!(s.fill(&mut out), 3);
+        assert_eq!(out[..3], [5, 6, 7]);
+        assert_eq!(control.take_events(), StreamEvents { looped: false, ended: true });
+        assert_eq!(control.take_events(), StreamEvents::default());
+    }
+
+    #[test]
+    fn counted_loops_go_round_then_play_on() {
+        let control = StreamControl::new();
+        let mut s = looped(2, 6, Some(2)).with_control(&control);
+        let mut out = [-1; 20];
+        assert_eq!(s.fill(&mut out), 16);
+        assert_eq!(out[..16], [0, 1, 2, 3, 4, 5, 2, 3, 4, 5, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(control.take_events(), StreamEvents { looped: true, ended: true });
+    }
+
+    #[test]
+    fn loops_say_when_they_wrap() {
+        let control = StreamControl::new();
+        let mut s = looped(2, 6, None).with_control(&control);
+        let mut out = [0; 4];
+        s.fill(&mut out);
+        assert_eq!(out, [0, 1, 2, 3]);
+        assert!(!control.take_events().looped);
+
+        // every fill from here wraps once, forever
+        for _ in 0..100 {
+            assert_eq!(s.fill(&mut out), 4);
+            assert_eq!(out, [4, 5, 2, 3]);
+            assert_eq!(control.take_events(), StreamEvents { looped: true, ended: false });
+        }
+
+        // landing right on the loop end wraps at the start of the next fill
+        let mut out = [0; 2];
+        assert_eq!(s.fill(&mut out), 2);
+        assert_eq!(out, [4, 5]);
+        assert!(!control.take_events().looped);
+        s.fill(&mut out);
+        assert_eq!(out, [2, 3]);
+        assert!(control.take_events().looped);
+    }
+
+    #[test]
+    fn loop_ends_past_the_end_are_clamped() {
+        let mut s = looped(5, 100, None);
+        let mut out = [0; 12];
+        assert_eq!(s.fill(&mut out), 12);
+        assert_eq!(out, [0, 1, 2, 3, 4, 5, 6, 7, 5, 6, 7, 5]);
+    }
+
+    #[test]
+    fn empty_loops_play_through() {
+        let mut s = looped(6, 6, None);
+        let mut out = [0; 12];
+        assert_eq!(s.fill(&mut out), 8);
+
+        // as does one the stream has been seeked past
+        let mut s = looped(1, 3, None);
+        s.seek(5);
+        assert_eq!(s.fill(&mut out), 3);
+        assert_eq!(out[..3], [5, 6, 7]);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::audio::SampleRate;
+
+// Procedural chip sounds: a few channels, each a pulse, triangle, noise or
+// wavetable oscillator shaped by an ADSR envelope. Everything is integer
+// maths on phase accumulators and the noise comes from an LFSR, so the same
+// notes give the same samples on the device and on the host.
+
+/// Channels a Synth plays at once
+pub const CHANNELS: usize = 4;
+
+/// Peak of one channel, so that all of them together can't clip
+const CHANNEL_PEAK: i32 = i16::MAX as i32 / CHANNELS as i32;
+
+/// Envelope level at full volume, 24 bits of fraction
+const FULL: u32 = 1 << 24;
+
+/// Highest note, B8
+pub const MAX_NOTE: u8 = 119;
+
+/// C8 to B8 in mHz, lower octaves are halvings
+const TOP_OCTAVE_MHZ: [u32; 12] = [
+    4_186_009, 4_434_922, 4_698_636, 4_978_032, 5_274_041, 5_587_652, 5_919_911, 6_271_927, 6_644_875, 7_040_000,
+    7_458_620, 7_902_133,
+];
+
+/// Frequency of a MIDI note number in mHz, A4 (69) is 440 Hz
+pub const fn note_mhz(note: u8) -> u32 {
+    let note = if note > MAX_NOTE { MAX_NOTE } else { note };
+    TOP_OCTAVE_MHZ[note as usize % 12] >> (9 - note / 12)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Waveform<'a> {
+    /// Square wave, high for `duty` 256ths of the period. 128 is even.
+    Pulse { duty: u8 },
+    Triangle,
+    /// Pseudo random, clocked 16 times a period of the note. `short` gives
+    /// the metallic 93 step sequence instead of the 32767 step one.
+    Noise { short: bool },
+    /// One period of any shape, played at the note's pitch
+    Wavetable(&'a [i8]),
+}
+
+/// Times in milliseconds, sustain out of 255. A sustain of 0 makes a
+/// plucked sound that is over after the decay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Adsr {
+    pub attack_ms: u16,
+    pub decay_ms: u16,
+    pub sustain: u8,
+    pub release_ms: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Instrument<'a> {
+    pub waveform: Waveform<'a>,
+    pub envelope: Adsr,
+    pub volume: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+struct Channel<'a> {
+    instrument: Option<&'a Instrument<'a>>,
+    phase: u32,
+    step: u32,
+    lfsr: u16,
+    stage: Stage,
+    level: u32,
+    sustain: u32,
+    /// Level change a sample in the current stage
+    delta: u32,
+    decay_delta: u32,
+}
+
+impl<'a> Channel<'a> {
+    const IDLE: Self = Self {
+        instrument: None,
+        phase: 0,
+        step: 0,
+        lfsr: 1,
+        stage: Stage::Off,
+        level: 0,
+        sustain: 0,
+        delta: 0,
+        decay_delta: 0,
+    };
+
+    fn oscillator(&mut self, waveform: Waveform) -> i32 {
+        let phase = self.phase;
+        self.phase = phase.wrapping_add(self.step);
+        match waveform {
+            Waveform::Pulse { duty } => {
+                if (phase >> 24) < duty as u32 {
+                    CHANNEL_PEAK
+                } else {
+                    -CHANNEL_PEAK
+                }
+            }
+            Waveform::Triangle => {
+                let p = (phase >> 16) as i32;
+                let triangle = if p < 0x8000 { p * 2 - 0x8000 } else { 0x17fff - p * 2 };
+                triangle * CHANNEL_PEAK / 0x8000
+            }
+            Waveform::Noise { short } => {
+                let clocks = (phase as u64 + self.step as u64 * 16) >> 32;
+                self.phase = phase.wrapping_add(self.step.wrapping_mul(16));
+                let tap = if short { 6 } else { 1 };
+                for _ in 0..clocks {
+                    let feedback = (self.lfsr ^ (self.lfsr >> tap)) & 1;
+                    self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+                }
+                if self.lfsr & 1 == 0 {
+                    CHANNEL_PEAK
+                } else {
+                    -CHANNEL_PEAK
+                }
+            }
+            Waveform::Wavetable(table) => {
+                let index = (phase as u64 * table.len() as u64) >> 32;
+                table.get(index as usize).map_or(0, |&s| s as i32 * CHANNEL_PEAK / 128)
+            }
+        }
+    }
+
+    /// Step the envelope by a sample, returns the level before it
+    fn envelope(&mut self) -> u32 {
+        let level = self.level;
+        match self.stage {
+            Stage::Attack => {
+                self.level = (self.level + self.delta).min(FULL);
+                if self.level == FULL {
+                    self.stage = Stage::Decay;
+                    self.delta = self.decay_delta;
+                }
+            }
+            Stage::Decay => {
+                if self.level <= self.sustain + self.delta {
+                    self.level = self.sustain;
+                    self.stage = if self.sustain == 0 { Stage::Off } else { Stage::Sustain };
+                } else {
+                    self.level -= self.delta;
+                }
+            }
+            Stage::Release => {
+                if self.level <= self.delta {
+                    self.level = 0;
+                    self.stage = Stage::Off;
+                } else {
+                    self.level -= self.delta;
+                }
+            }
+            Stage::Sustain | Stage::Off => {}
+        }
+        level
+    }
+}
+
+/// Plays notes on [`CHANNELS`] channels, rendered at a fixed rate
+pub struct Synth<'a> {
+    channels: [Channel<'a>; CHANNELS],
+    rate: SampleRate,
+}
+
+impl<'a> Synth<'a> {
+    pub const fn new(rate: SampleRate) -> Self {
+        Self {
+            channels: [Channel::IDLE; CHANNELS],
+            rate,
+        }
+    }
+
+    /// Samples in `ms`, at least 1 so a zero time still moves on
+    fn samples(&self, ms: u16) -> u32 {
+        (self.rate.samples(ms as u32) as u32).max(1)
+    }
+
+    /// Start `note` (a MIDI note number) on `channel`. The envelope starts
+    /// over from wherever it was and the oscillator keeps its phase, so
+    /// there's no click.
+    pub fn note_on(&mut self, channel: usize, note: u8, instrument: &'a Instrument<'a>) {
+        let envelope = instrument.envelope;
+        let sustain = (envelope.sustain as u64 * FULL as u64 / 255) as u32;
+        let attack = FULL / self.samples(envelope.attack_ms);
+        let decay = ((FULL - sustain) / self.samples(envelope.decay_ms)).max(1);
+        let step = ((note_mhz(note) as u64) << 32) / (self.rate.hz() as u64 * 1000);
+
+        let Some(c) = self.channels.get_mut(channel) else {
+            return;
+        };
+        c.instrument = Some(instrument);
+        c.step = step as u32;
+        c.stage = Stage::Attack;
+        c.sustain = sustain;
+        c.delta = attack;
+        c.decay_delta = decay;
+    }
+
+    /// Let the note on `channel` fade out over its release time
+    pub fn note_off(&mut self, channel: usize) {
+        let Some(release_ms) = self
+            .channels
+            .get(channel)
+            .and_then(|c| c.instrument)
+            .map(|i| i.envelope.release_ms)
+        else {
+            return;
+        };
+        let samples = self.samples(release_ms);
+        let c = &mut self.channels[channel];
+        if c.stage != Stage::Off {
+            c.stage = Stage::Release;
+            c.delta = (c.level / samples).max(1);
+        }
+    }
+
+    /// Cut every channel off straight away
+    pub fn stop_all(&mut self) {
+        for c in &mut self.channels {
+            c.stage = Stage::Off;
+            c.level = 0;
+        }
+    }
+
+    /// Nothing is sounding, every envelope has run out
+    pub fn is_silent(&self) -> bool {
+        self.channels.iter().all(|c| c.stage == Stage::Off)
+    }
+
+    /// Write the next `out.len()` samples of all channels
+    pub fn render(&mut self, out: &mut [i16]) {
+        out.fill(0);
+        for c in &mut self.channels {
+            let Some(instrument) = c.instrument else {
+                continue;
+            };
+            if c.stage == Stage::Off {
+                continue;
+            }
+            let volume = instrument.volume as i32;
+            for sample in out.iter_mut() {
+                let level = (c.envelope() >> 8) as i32; // 0..=65536
+                let value = (c.oscillator(instrument.waveform) * level) >> 16;
+                *sample += (value * volume / 255) as i16;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: SampleRate = SampleRate::Hz32000;
+
+    const TONE: Instrument = Instrument {
+        waveform: Waveform::Pulse { duty: 128 },
+        envelope: Adsr { attack_ms: 10, decay_ms: 20, sustain: 128, release_ms: 30 },
+        volume: 255,
+    };
+
+    /// Samples the channel stays in `stage`
+    fn stage_len(c: &mut Channel, stage: Stage) -> usize {
+        let mut n = 0;
+        while c.stage == stage && n < 100_000 {
+            c.envelope();
+            n += 1;
+        }
+        n
+    }
+
+    fn assert_about(len: usize, ms: u32) {
+        let expected = RATE.samples(ms);
+        assert!(len.abs_diff(expected) <= 1, "{len} samples instead of {expected}");
+    }
+
+    #[test]
+    fn notes_have_the_right_pitch() {
+        assert_eq!(note_mhz(69), 440_000);
+        assert_eq!(note_mhz(57), 220_000);
+        assert_eq!(note_mhz(60), 261_625);
+        assert_eq!(note_mhz(MAX_NOTE), 7_902_133);
+        assert_eq!(note_mhz(MAX_NOTE + 1), note_mhz(MAX_NOTE));
+    }
+
+    #[test]
+    fn envelope_stages_take_their_time() {
+        let mut synth = Synth::new(RATE);
+        synth.note_on(0, 69, &TONE);
+        let c = &mut synth.channels[0];
+        assert_about(stage_len(c, Stage::Attack), 10);
+        assert_eq!(c.level, FULL);
+        assert_about(stage_len(c, Stage::Decay), 20);
+        assert_eq!(c.stage, Stage::Sustain);
+        assert_eq!(c.level, 128 * FULL / 255);
+        assert_eq!(stage_len(c, Stage::Sustain), 100_000);
+
+        synth.note_off(0);
+        let c = &mut synth.channels[0];
+        assert_about(stage_len(c, Stage::Release), 30);
+        assert_eq!((c.stage, c.level), (Stage::Off, 0));
+        assert!(synth.is_silent());
+    }
+
+    #[test]
+    fn no_sustain_ends_after_the_decay() {
+        let pluck = Instrument {
+            envelope: Adsr { attack_ms: 0, decay_ms: 5, sustain: 0, release_ms: 100 },
+            ..TONE
+        };
+        let mut synth = Synth::new(RATE);
+        synth.note_on(1, 60, &pluck);
+        let c = &mut synth.channels[1];
+        assert_eq!(stage_len(c, Stage::Attack), 1);
+        assert_about(stage_len(c, Stage::Decay), 5);
+        assert_eq!((c.stage, c.level), (Stage::Off, 0));
+    }
+
+    #[test]
+    fn retriggering_keeps_the_phase() {
+        let mut synth = Synth::new(RATE);
+        let mut out = [0; 100];
+        synth.note_on(0, 69, &TONE);
+        synth.render(&mut out);
+        let (phase, level) = (synth.channels[0].phase, synth.channels[0].level);
+        assert_ne!(phase, 0);
+
+        synth.note_on(0, 72, &TONE);
+        let c = &synth.channels[0];
+        assert_eq!((c.phase, c.level, c.stage), (phase, level, Stage::Attack));
+    }
+
+    #[test]
+    fn renders_the_same_every_time() {
+        let render = || {
+            let mut synth = Synth::new(RATE);
+            let noise = Instrument { waveform: Waveform::Noise { short: true }, ..TONE };
+            let mut out = [0; 2000];
+            synth.note_on(0, 69, &TONE);
+            synth.note_on(3, 40, &noise);
+            synth.render(&mut out[..1000]);
+            synth.note_off(0);
+            synth.render(&mut out[1000..]);
+            out
+        };
+        let out = render();
+        assert!(out.iter().any(|&s| s != 0));
+        assert_eq!(out, render());
+    }
+}
@@ -0,0 +1,409 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::Rectangle,
+    text::{renderer::TextRenderer, Alignment, Baseline},
+};
+
+use crate::font::{BitmapFont, BitmapTextStyle};
+
+// Laying out text from a BitmapFont inside a rectangle: word wrap,
+// alignment and a text box that types its text out.
+
+/// Kana, kanji and full width forms, which can be broken before or after
+/// without a space
+fn is_wide(c: char) -> bool {
+    matches!(c as u32, 0x3000..=0x30ff | 0x3400..=0x4dbf | 0x4e00..=0x9fff | 0xff00..=0xffef)
+}
+
+/// Splits text into lines no wider than `width`. Lines break at spaces,
+/// around wide (Japanese) characters and at `\n`, and words that don't fit
+/// on a line of their own are broken anywhere.
+pub struct Lines<'t, 'f> {
+    font: &'f BitmapFont<'f>,
+    width: u32,
+    text: &'t str,
+    pos: usize,
+}
+
+impl<'t, 'f> Lines<'t, 'f> {
+    pub fn new(font: &'f BitmapFont<'f>, text: &'t str, width: u32) -> Self {
+        Self { font, width, text, pos: 0 }
+    }
+
+    /// Byte offset where the next line starts
+    pub fn offset(&self) -> usize {
+        self.pos
+    }
+}
+
+impl<'t, 'f> Iterator for Lines<'t, 'f> {
+    /// The line and its byte offset in the text
+    type Item = (usize, &'t str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.pos;
+        let rest = &self.text[start..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut width = 0;
+        // where the line could end, and where the next one would start
+        let mut brk = None;
+
+        for (i, c) in rest.char_indices() {
+            if c == '\n' {
+                self.pos = start + i + 1;
+                return Some((start, rest[..i].trim_end_matches(' ')));
+            }
+            if c == ' ' {
+                brk = Some((i, i + 1));
+                width += self.font.advance(c);
+                continue;
+            }
+            if is_wide(c) && i > 0 {
+                brk = Some((i, i));
+            }
+
+            let advance = self.font.advance(c);
+            if width + advance > self.width && i > 0 {
+                let (end, next) = brk.filter(|&(end, _)| end > 0).unwrap_or((i, i));
+                let skipped = rest[next..].len() - rest[next..].trim_start_matches(' ').len();
+                self.pos = start + next + skipped;
+                return Some((start, rest[..end].trim_end_matches(' ')));
+            }
+            width += advance;
+
+            if is_wide(c) {
+                brk = Some((i + c.len_utf8(), i + c.len_utf8()));
+            }
+        }
+
+        self.pos = self.text.len();
+        Some((start, rest))
+    }
+}
+
+/// How many bytes of `text` fit in `bounds`, the start of the next page
+pub fn fit(font: &BitmapFont<'_>, text: &str, bounds: Size) -> usize {
+    let lines = (bounds.height / font.line_height().max(1)) as usize;
+    let mut layout = Lines::new(font, text, bounds.width);
+    for _ in layout.by_ref().take(lines) {}
+    layout.offset()
+}
+
+/// Draw `text` wrapped inside `bounds`, stopping at the bottom. Only the
+/// first `visible` bytes are drawn, while the layout is that of the whole
+/// text, so words don't jump between lines as they are revealed. Returns
+/// how many bytes fit.
+pub fn draw_wrapped<C, D>(
+    text: &str,
+    visible: usize,
+    bounds: &Rectangle,
+    style: &BitmapTextStyle<'_, C>,
+    alignment: Alignment,
+    target: &mut D,
+) -> Result<usize, D::Error>
+where
+    C: PixelColor,
+    D: DrawTarget<Color = C>,
+{
+    let font = style.font;
+    let lines = (bounds.size.height / font.line_height().max(1)) as usize;
+    let mut layout = Lines::new(font, text, bounds.size.width);
+
+    for (n, (offset, line)) in layout.by_ref().take(lines).enumerate() {
+        if offset >= visible {
+            continue;
+        }
+        let shown = &line[..line.len().min(visible - offset)];
+
+        // aligned on the full line so it doesn't slide while typing
+        let spare = bounds.size.width.saturating_sub(font.text_width(line)) as i32;
+        let x = match alignment {
+            Alignment::Left => 0,
+            Alignment::Center => spare / 2,
+            Alignment::Right => spare,
+        };
+        let pos = bounds.top_left + Point::new(x, (n as u32 * font.line_height()) as i32);
+        style.draw_string(shown, pos, Baseline::Top, target)?;
+    }
+
+    Ok(layout.offset())
+}
+
+/// A dialogue box that reveals its text a character at a time. Text that
+/// doesn't fit is split into pages.
+pub struct TypewriterBox<'a, C> {
+    text: &'a str,
+    bounds: Rectangle,
+    style: BitmapTextStyle<'a, C>,
+    alignment: Alignment,
+    /// Time to reveal one character
+    char_us: u64,
+    elapsed_us: u64,
+    /// Byte offsets of the page and of the end of the revealed text
+    page: usize,
+    shown: usize,
+}
+
+impl<'a, C: PixelColor> TypewriterBox<'a, C> {
+    pub fn new(text: &'a str, bounds: Rectangle, style: BitmapTextStyle<'a, C>) -> Self {
+        Self {
+            text,
+            bounds,
+            style,
+            alignment: Alignment::Left,
+            char_us: 30_000,
+            elapsed_us: 0,
+            page: 0,
+            shown: 0,
+        }
+    }
+
+    pub fn with_alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Characters revealed per second, 0 shows the whole page at once
+    pub fn with_speed(mut self, chars_per_second: u32) -> Self {
+        self.char_us = match chars_per_second {
+            0 => 0,
+            cps => 1_000_000 / cps as u64,
+        };
+        self
+    }
+
+    pub fn bounds(&self) -> Rectangle {
+        self.bounds
+    }
+
+    fn page_text(&self) -> &'a str {
+        &self.text[self.page..]
+    }
+
+    fn page_len(&self) -> usize {
+        fit(self.style.font, self.page_text(), self.bounds.size)
+    }
+
+    /// The whole page is shown
+    pub fn is_page_done(&self) -> bool {
+        self.shown >= self.page_len()
+    }
+
+    /// The last page is shown in full
+    pub fn is_done(&self) -> bool {
+        self.is_page_done() && self.page + self.page_len() >= self.text.len()
+    }
+
+    /// Reveal characters for `elapsed_us` of time. Returns whether more
+    /// text became visible.
+    pub fn update(&mut self, elapsed_us: u64) -> bool {
+        let end = self.page_len();
+        if self.shown >= end {
+            return false;
+        }
+        if self.char_us == 0 {
+            self.shown = end;
+            return true;
+        }
+
+        self.elapsed_us += elapsed_us;
+        let before = self.shown;
+        let page = self.page_text();
+        while self.elapsed_us >= self.char_us && self.shown < end {
+            self.elapsed_us -= self.char_us;
+            let c = page[self.shown..].chars().next().unwrap();
+            self.shown += c.len_utf8();
+        }
+        self.shown != before
+    }
+
+    /// What pressing A does: finish the page, then go to the next one.
+    /// Returns false once there's nothing more to show.
+    pub fn confirm(&mut self) -> bool {
+        let end = self.page_len();
+        if self.shown < end {
+            self.shown = end;
+            return true;
+        }
+        // end == 0 when not even a line fits in the box
+        if end == 0 || self.page + end >= self.text.len() {
+            return false;
+        }
+        self.page += end;
+        self.shown = 0;
+        self.elapsed_us = 0;
+        true
+    }
+
+    pub fn draw<D: DrawTarget<Color = C>>(&self, target: &mut D) -> Result<(), D::Error> {
+        if let Some(background) = self.style.background_color {
+            target.fill_solid(&self.bounds, background)?;
+        }
+        let style = BitmapTextStyle {
+            background_color: None,
+            ..self.style
+        };
+        draw_wrapped(self.page_text(), self.shown, &self.bounds, &style, self.alignment, target)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use embedded_graphics::{mock_display::MockDisplay, pixelcolor::BinaryColor};
+
+    use super::*;
+    use crate::font::tests::test_font;
+
+    // letters are 6 wide with their spacing, kana 10 and a space 4
+
+    fn lines(text: &str, width: u32) -> Vec<(usize, &str)> {
+        let data = test_font();
+        let font = BitmapFont::from_bytes(&data).unwrap();
+        Lines::new(&font, text, width).collect()
+    }
+
+    /// Where drawing `text` left pixels
+    fn drawn(text: &str, visible: usize, size: Size, alignment: Alignment) -> (Rectangle, usize) {
+        let data = test_font();
+        let font = BitmapFont::from_bytes(&data).unwrap();
+        let style = BitmapTextStyle::new(&font, BinaryColor::On);
+        let mut display = MockDisplay::new();
+        let bounds = Rectangle::new(Point::new(2, 1), size);
+        let fitted = draw_wrapped(text, visible, &bounds, &style, alignment, &mut display).unwrap();
+        (display.affected_area(), fitted)
+    }
+
+    #[test]
+    fn lines_break_at_spaces() {
+        assert_eq!(lines("ab cd ef", 30), [(0, "ab cd"), (6, "ef")]);
+        assert_eq!(lines("ab   cd", 14), [(0, "ab"), (5, "cd")]);
+        assert_eq!(lines("ab cd", 100), [(0, "ab cd")]);
+        assert_eq!(lines("", 100), []);
+    }
+
+    #[test]
+    fn long_words_break_anywhere() {
+        assert_eq!(lines("abcdefgh", 20), [(0, "abc"), (3, "def"), (6, "gh")]);
+        assert_eq!(lines("ab cdefghij", 30), [(0, "ab"), (3, "cdefg"), (8, "hij")]);
+        // even a box too narrow for one character makes progress
+        assert_eq!(lines("ab", 1), [(0, "a"), (1, "b")]);
+    }
+
+    #[test]
+    fn newlines_end_lines() {
+        assert_eq!(lines("ab \ncd\n\nef", 100), [(0, "ab"), (4, "cd"), (7, ""), (8, "ef")]);
+    }
+
+    #[test]
+    fn latin_1_wraps_like_ascii() {
+        assert_eq!(lines("caf\u{e9} m\u{fc}de", 30), [(0, "caf\u{e9}"), (6, "m\u{fc}de")]);
+    }
+
+    #[test]
+    fn kana_break_without_spaces() {
+        assert_eq!(lines("\u{3042}\u{3044}\u{3046}\u{3048}", 25), [(0, "\u{3042}\u{3044}"), (6, "\u{3046}\u{3048}")]);
+        // before kana that follows latin text too
+        assert_eq!(lines("ab\u{3042}\u{3044}", 25), [(0, "ab\u{3042}"), (5, "\u{3044}")]);
+    }
+
+    #[test]
+    fn fit_stops_at_the_bottom() {
+        let data = test_font();
+        let font = BitmapFont::from_bytes(&data).unwrap();
+        let text = "ab cd ef gh ij";
+        assert_eq!(fit(&font, text, Size::new(30, 20)), 12);
+        assert_eq!(fit(&font, text, Size::new(30, 29)), 12);
+        assert_eq!(fit(&font, text, Size::new(30, 30)), text.len());
+        assert_eq!(fit(&font, text, Size::new(30, 9)), 0);
+    }
+
+    #[test]
+    fn lines_are_aligned_in_the_box() {
+        // "ab" is 11 pixels, the box 30 wide from x 2
+        let size = Size::new(30, 20);
+        let ink = |x| Rectangle::new(Point::new(x, 1), Size::new(11, 8));
+        assert_eq!(drawn("ab", 2, size, Alignment::Left), (ink(2), 2));
+        assert_eq!(drawn("ab", 2, size, Alignment::Center), (ink(2 + 9), 2));
+        assert_eq!(drawn("ab", 2, size, Alignment::Right), (ink(2 + 18), 2));
+    }
+
+    #[test]
+    fn partly_revealed_text_keeps_its_layout() {
+        let size = Size::new(30, 20);
+        // "ab cd" then "ef", only "ab c" of it is visible
+        let (area, fitted) = drawn("ab cd ef", 4, size, Alignment::Right);
+        assert_eq!(fitted, 8);
+        assert_eq!(area, Rectangle::new(Point::new(2 + 2, 1), Size::new(21, 8)));
+
+        // the second line shows up once reached, and what doesn't fit isn't drawn
+        let (area, fitted) = drawn("ab cd ef gh ij", 14, size, Alignment::Left);
+        assert_eq!(fitted, 12);
+        assert_eq!(area, Rectangle::new(Point::new(2, 1), Size::new(27, 18)));
+    }
+
+    #[test]
+    fn typewriter_reveals_and_pages() {
+        let data = test_font();
+        let font = BitmapFont::from_bytes(&data).unwrap();
+        let style = BitmapTextStyle::new(&font, BinaryColor::On);
+        // one line a page: "ab cd" then "ef"
+        let bounds = Rectangle::new(Point::zero(), Size::new(30, 10));
+        let mut tb = TypewriterBox::new("ab cd ef", bounds, style).with_speed(10);
+
+        assert!(!tb.update(50_000));
+        assert!(tb.update(200_000));
+        assert_eq!(tb.shown, 2);
+        assert!(tb.update(50_000));
+        assert_eq!(tb.shown, 3);
+        assert!(!tb.is_page_done());
+
+        // A finishes the page, then turns it
+        assert!(tb.confirm());
+        assert!(tb.is_page_done() && !tb.is_done());
+        let mut display = MockDisplay::new();
+        tb.draw(&mut display).unwrap();
+        assert_eq!(display.affected_area(), Rectangle::new(Point::zero(), Size::new(27, 8)));
+
+        assert!(tb.confirm());
+        assert_eq!((tb.page, tb.shown, tb.page_text()), (6, 0, "ef"));
+        let mut display = MockDisplay::new();
+        tb.draw(&mut display).unwrap();
+        assert!(display.affected_area().is_zero_sized());
+
+        assert!(tb.update(1_000_000));
+        assert!(tb.is_done());
+        let mut display = MockDisplay::new();
+        tb.draw(&mut display).unwrap();
+        assert_eq!(display.affected_area(), Rectangle::new(Point::zero(), Size::new(11, 8)));
+        assert!(!tb.confirm());
+    }
+
+    #[test]
+    fn typewriter_pages_kana() {
+        let data = test_font();
+        let font = BitmapFont::from_bytes(&data).unwrap();
+        let style = BitmapTextStyle::new(&font, BinaryColor::On);
+        let bounds = Rectangle::new(Point::zero(), Size::new(25, 10));
+        let text = "\u{3042}\u{3044}\u{3046}\u{3048}\u{30ab}";
+        let mut tb = TypewriterBox::new(text, bounds, style).with_speed(0);
+
+        let mut pages = Vec::new();
+        loop {
+            assert!(tb.update(0));
+            pages.push(&text[tb.page..tb.page + tb.shown]);
+            if !tb.confirm() {
+                break;
+            }
+        }
+        assert_eq!(pages, ["\u{3042}\u{3044}", "\u{3046}\u{3048}", "\u{30ab}"]);
+        assert!(tb.is_done());
+    }
+}
@@ -0,0 +1,162 @@
+use embedded_graphics::{image::ImageDrawable, prelude::*, primitives::Rectangle};
+
+use crate::sprite::SpriteSheet;
+
+// Backgrounds bigger than the screen, built from a tileset and a map of
+// tile indices. The map is a plain slice so it can be a `static` in flash.
+
+/// Tiles are square and one of these sizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TileSize {
+    Px8,
+    Px16,
+}
+
+impl TileSize {
+    pub const fn pixels(self) -> u32 {
+        match self {
+            TileSize::Px8 => 8,
+            TileSize::Px16 => 16,
+        }
+    }
+
+    pub const fn size(self) -> Size {
+        Size::new_equal(self.pixels())
+    }
+}
+
+/// Map entry for a cell that draws nothing
+pub const EMPTY_TILE: u16 = u16::MAX;
+
+pub struct Tilemap<'a, I: ImageDrawable> {
+    tileset: SpriteSheet<'a, I>,
+    tile_size: TileSize,
+    /// Row major, `columns` tiles per row
+    tiles: &'a [u16],
+    columns: u32,
+    rows: u32,
+}
+
+impl<'a, I: ImageDrawable> Tilemap<'a, I> {
+    /// `tiles` holds tileset indices row by row, `columns` to a row
+    pub fn new(tileset: &'a I, tile_size: TileSize, tiles: &'a [u16], columns: u32) -> Self {
+        assert!(columns > 0 && tiles.len().is_multiple_of(columns as usize), "tilemap isn't a whole number of rows");
+
+        Self {
+            tileset: SpriteSheet::new(tileset, tile_size.size()),
+            tile_size,
+            tiles,
+            columns,
+            rows: tiles.len() as u32 / columns,
+        }
+    }
+
+    /// Skip pixels of this colour in the tiles, e.g. for a layer over
+    /// another one
+    pub fn with_transparent(mut self, color: I::Color) -> Self {
+        self.tileset = self.tileset.with_transparent(color);
+        self
+    }
+
+    pub fn tile_size(&self) -> TileSize {
+        self.tile_size
+    }
+
+    /// Size of the whole map in pixels
+    pub fn size(&self) -> Size {
+        Size::new(self.columns, self.rows) * self.tile_size.pixels()
+    }
+
+    /// Tile at a cell, `None` outside the map
+    pub fn tile(&self, column: i32, row: i32) -> Option<u16> {
+        if column < 0 || row < 0 || column as u32 >= self.columns || row as u32 >= self.rows {
+            return None;
+        }
+        Some(self.tiles[row as usize * self.columns as usize + column as usize])
+    }
+
+    /// Tile under a point in map pixels, for collisions
+    pub fn tile_at(&self, point: Point) -> Option<u16> {
+        let px = self.tile_size.pixels() as i32;
+        self.tile(point.x.div_euclid(px), point.y.div_euclid(px))
+    }
+
+    /// Draw the part of the map the camera sees. Only tiles overlapping the
+    /// target's bounding box are drawn, so drawing through a clipped target
+    /// only touches the tiles under the clip area.
+    pub fn draw<D>(&self, camera: &Camera, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = I::Color>,
+    {
+        let screen = target.bounding_box();
+        let Some(bottom_right) = screen.bottom_right() else {
+            return Ok(());
+        };
+        let px = self.tile_size.pixels() as i32;
+
+        // visible cells, in map coordinates
+        let cell = |p: Point| Point::new(p.x.div_euclid(px), p.y.div_euclid(px));
+        let first = cell(camera.to_world(screen.top_left));
+        let last = cell(camera.to_world(bottom_right));
+        let first = Point::new(first.x.max(0), first.y.max(0));
+        let last = Point::new(last.x.min(self.columns as i32 - 1), last.y.min(self.rows as i32 - 1));
+
+        for row in first.y..=last.y {
+            for column in first.x..=last.x {
+                let tile = self.tiles[row as usize * self.columns as usize + column as usize];
+                if tile == EMPTY_TILE {
+                    continue;
+                }
+                let pos = Point::new(column, row) * px - camera.pos;
+                self.tileset.draw_frame(tile as u32, pos, false, false, target)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Top left corner of the screen in world (map) pixels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Camera {
+    pub pos: Point,
+}
+
+impl Camera {
+    pub const fn new(pos: Point) -> Self {
+        Self { pos }
+    }
+
+    pub fn scroll_by(&mut self, delta: Point) {
+        self.pos += delta;
+    }
+
+    /// Keep the view inside a world of `world` size, seen through a screen
+    /// of `screen` size. A world smaller than the screen stays at the top
+    /// left.
+    pub fn clamp(&mut self, world: Size, screen: Size) {
+        let max_x = world.width.saturating_sub(screen.width) as i32;
+        let max_y = world.height.saturating_sub(screen.height) as i32;
+        self.pos = Point::new(self.pos.x.clamp(0, max_x), self.pos.y.clamp(0, max_y));
+    }
+
+    /// Center the view on `target`, clamped to the world
+    pub fn follow(&mut self, target: Point, world: Size, screen: Size) {
+        self.pos = target - Point::new(screen.width as i32 / 2, screen.height as i32 / 2);
+        self.clamp(world, screen);
+    }
+
+    pub fn to_screen(&self, world: Point) -> Point {
+        world - self.pos
+    }
+
+    pub fn to_world(&self, screen: Point) -> Point {
+        screen + self.pos
+    }
+
+    /// Part of the world on screen
+    pub fn view(&self, screen: Size) -> Rectangle {
+        Rectangle::new(self.pos, screen)
+    }
+}
@@ -0,0 +1,296 @@
+use crate::audio::{AudioSource, SampleRate};
+use crate::synth::{Instrument, Synth, CHANNELS};
+
+// Songs for the synth, laid out like a tracker: patterns are rows with one
+// cell per channel, and a sequence says which pattern plays when. Cells are
+// written as tracker text and turned into data at compile time:
+//
+//   "C-4 0"  C4 on instrument 0, "F#5 A" is F#5 on instrument 10
+//   "==="    note off, the channel fades out over its release
+//   "---"    nothing new, whatever is playing carries on
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Cell {
+    Empty,
+    Off,
+    Note { note: u8, instrument: u8 },
+}
+
+impl Cell {
+    /// Parse a cell, panics on anything that isn't one (at compile time
+    /// when used in a const)
+    pub const fn parse(text: &str) -> Self {
+        match text.as_bytes() {
+            b"---" => Cell::Empty,
+            b"===" => Cell::Off,
+            &[name, accidental, octave, b' ', instrument] => {
+                let semitone = match name {
+                    b'C' => 0,
+                    b'D' => 2,
+                    b'E' => 4,
+                    b'F' => 5,
+                    b'G' => 7,
+                    b'A' => 9,
+                    b'B' => 11,
+                    _ => panic!("note names are C to B"),
+                };
+                let sharp = match accidental {
+                    b'-' => 0,
+                    b'#' => 1,
+                    _ => panic!("notes are like C-4 or C#4"),
+                };
+                if !matches!(octave, b'0'..=b'8') {
+                    panic!("octaves are 0 to 8");
+                }
+                let note = (octave - b'0' + 1) * 12 + semitone + sharp;
+                let instrument = match instrument {
+                    b'0'..=b'9' => instrument - b'0',
+                    b'A'..=b'F' => instrument - b'A' + 10,
+                    _ => panic!("instruments are a hex digit"),
+                };
+                Cell::Note { note, instrument }
+            }
+            _ => panic!("cells look like \"C-4 0\", \"===\" or \"---\""),
+        }
+    }
+}
+
+pub type Row = [Cell; CHANNELS];
+
+/// Rows from tracker text
+pub const fn pattern<const N: usize>(rows: [[&str; CHANNELS]; N]) -> [Row; N] {
+    let mut out = [[Cell::Empty; CHANNELS]; N];
+    let mut r = 0;
+    while r < N {
+        let mut c = 0;
+        while c < CHANNELS {
+            out[r][c] = Cell::parse(rows[r][c]);
+            c += 1;
+        }
+        r += 1;
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Song<'a> {
+    pub instruments: &'a [Instrument<'a>],
+    pub patterns: &'a [&'a [Row]],
+    /// Pattern indices in the order they play
+    pub sequence: &'a [u8],
+    /// Where in the sequence to go on from after the last pattern, None
+    /// to stop
+    pub loop_to: Option<usize>,
+    /// How long a row lasts, i.e. the tempo
+    pub row_ms: u16,
+}
+
+/// Plays a [`Song`] on its own [`Synth`]. Ends once the song has and the
+/// last notes have faded out.
+pub struct Player<'a> {
+    song: &'a Song<'a>,
+    synth: Synth<'a>,
+    /// Index into the sequence
+    position: usize,
+    row: usize,
+    row_samples: usize,
+    /// Samples left of the current row, 0 to start the next one
+    until_row: usize,
+    ended: bool,
+}
+
+impl<'a> Player<'a> {
+    pub fn new(song: &'a Song<'a>, rate: SampleRate) -> Self {
+        Self {
+            song,
+            synth: Synth::new(rate),
+            position: 0,
+            row: 0,
+            row_samples: rate.samples(song.row_ms as u32).max(1),
+            until_row: 0,
+            ended: false,
+        }
+    }
+
+    /// Sequence index and row that plays next
+    pub fn position(&self) -> (usize, usize) {
+        (self.position, self.row)
+    }
+
+    /// Past the last row, the synth may still be fading out
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    fn play_row(&mut self, row: &Row) {
+        for (channel, cell) in row.iter().enumerate() {
+            match *cell {
+                Cell::Empty => {}
+                Cell::Off => self.synth.note_off(channel),
+                Cell::Note { note, instrument } => match self.song.instruments.get(instrument as usize) {
+                    Some(instrument) => self.synth.note_on(channel, note, instrument),
+                    None => self.synth.note_off(channel),
+                },
+            }
+        }
+    }
+
+    fn next_row(&mut self) {
+        let song = self.song;
+        // at most one pass over the sequence, in case it's all empty
+        for _ in 0..=song.sequence.len() {
+            if self.position >= song.sequence.len() {
+                match song.loop_to {
+                    Some(to) if to < song.sequence.len() => self.position = to,
+                    _ => break,
+                }
+            }
+            let pattern = song.patterns.get(song.sequence[self.position] as usize).copied().unwrap_or(&[]);
+            if let Some(row) = pattern.get(self.row) {
+                self.play_row(row);
+                self.row += 1;
+                return;
+            }
+            self.position += 1;
+            self.row = 0;
+        }
+
+        self.ended = true;
+        for channel in 0..CHANNELS {
+            self.synth.note_off(channel);
+        }
+    }
+}
+
+impl AudioSource for Player<'_> {
+    fn fill(&mut self, out: &mut [i16]) -> usize {
+        let mut written = 0;
+        while written < out.len() {
+            if self.until_row == 0 {
+                if !self.ended {
+                    self.next_row();
+                }
+                self.until_row = self.row_samples;
+            }
+            if self.ended && self.synth.is_silent() {
+                break;
+            }
+
+            let n = (out.len() - written).min(self.until_row);
+            self.synth.render(&mut out[written..written + n]);
+            self.until_row -= n;
+            written += n;
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::{Adsr, Waveform};
+
+    const RATE: SampleRate = SampleRate::Hz32000;
+
+    const INSTRUMENTS: [Instrument; 2] = [
+        Instrument {
+            waveform: Waveform::Pulse { duty: 64 },
+            envelope: Adsr { attack_ms: 2, decay_ms: 20, sustain: 160, release_ms: 40 },
+            volume: 200,
+        },
+        Instrument {
+            waveform: Waveform::Noise { short: false },
+            envelope: Adsr { attack_ms: 0, decay_ms: 15, sustain: 0, release_ms: 0 },
+            volume: 120,
+        },
+    ];
+
+    const TUNE: [Row; 4] = pattern([
+        ["C-4 0", "---", "C-8 1", "---"],
+        ["---", "E-4 0", "---", "---"],
+        ["G-4 0", "===", "C-8 1", "---"],
+        ["===", "---", "---", "---"],
+    ]);
+
+    const SONG: Song = Song {
+        instruments: &INSTRUMENTS,
+        patterns: &[&TUNE],
+        sequence: &[0, 0],
+        loop_to: None,
+        row_ms: 10,
+    };
+
+    /// Renders `song` until it ends, returns the length
+    fn render(song: &Song, out: &mut [i16]) -> usize {
+        let mut player = Player::new(song, RATE);
+        let len = player.fill(out);
+        assert!(player.is_ended());
+        len
+    }
+
+    #[test]
+    fn cells_parse() {
+        assert_eq!(Cell::parse("---"), Cell::Empty);
+        assert_eq!(Cell::parse("==="), Cell::Off);
+        assert_eq!(Cell::parse("A-4 0"), Cell::Note { note: 69, instrument: 0 });
+        assert_eq!(Cell::parse("F#5 A"), Cell::Note { note: 78, instrument: 10 });
+        assert_eq!(Cell::parse("C-0 F"), Cell::Note { note: 12, instrument: 15 });
+        assert_eq!(Cell::parse("B-8 9"), Cell::Note { note: crate::synth::MAX_NOTE, instrument: 9 });
+    }
+
+    #[test]
+    #[should_panic(expected = "octaves are 0 to 8")]
+    fn cells_stop_at_octave_8() {
+        Cell::parse("C-9 0");
+    }
+
+    #[test]
+    #[should_panic(expected = "note names are C to B")]
+    fn cells_need_a_note_name() {
+        Cell::parse("H-4 0");
+    }
+
+    #[test]
+    #[should_panic(expected = "instruments are a hex digit")]
+    fn cells_need_a_hex_instrument() {
+        Cell::parse("C-4 G");
+    }
+
+    #[test]
+    fn songs_render_the_same_every_time() {
+        let mut first = [0; 8000];
+        let mut second = [1; 8000];
+        let len = render(&SONG, &mut first);
+        assert_eq!(render(&SONG, &mut second), len);
+        assert_eq!(first[..len], second[..len]);
+        assert!(first[..len].iter().any(|&s| s != 0));
+    }
+
+    #[test]
+    fn players_end_after_the_release() {
+        let mut out = [0; 8000];
+        let len = render(&SONG, &mut out);
+        // 8 rows, then the last note fades out over the release
+        let rows = 8 * RATE.samples(10);
+        assert!(len > rows, "{len} samples");
+        assert!(len <= rows + RATE.samples(10) + RATE.samples(40), "{len} samples");
+        assert!(out[rows..len].iter().any(|&s| s != 0));
+
+        let mut player = Player::new(&SONG, RATE);
+        let mut rest = [0; 100];
+        player.fill(&mut out);
+        assert_eq!(player.fill(&mut rest), 0);
+    }
+
+    #[test]
+    fn looping_songs_play_on() {
+        let song = Song { loop_to: Some(1), ..SONG };
+        let mut player = Player::new(&song, RATE);
+        let mut out = [0; 8000];
+        assert_eq!(player.fill(&mut out), out.len());
+        assert!(!player.is_ended());
+        // 25 rows in: 8 before the loop, 4 times round it and one more
+        assert_eq!(player.position(), (1, 1));
+    }
+}
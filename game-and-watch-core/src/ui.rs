@@ -0,0 +1,450 @@
+use core::fmt::Write;
+use core::ops::RangeInclusive;
+
+use embedded_graphics::{
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{
+        renderer::{CharacterStyle, TextRenderer},
+        Alignment, Baseline, Text, TextStyleBuilder,
+    },
+};
+
+use crate::input::ButtonClick;
+
+// An immediate mode UI: the app calls the widget functions every frame,
+// in order, and they draw themselves and return what the user did. Only
+// the focus, the open dialog and the toast live across frames, in
+// UiState. Up/down move the focus, left/right change values, A activates
+// and B goes back.
+//
+//     let mut ui = Ui::begin(&mut gs.ui, clicks, now_us, area, &theme, &mut display);
+//     if ui.button("Start") { ... }
+//     ui.toggle("Sound", &mut settings.sound);
+//     ui.slider("Volume", &mut settings.volume, 0..=10);
+//     ui.end()?;
+//
+// Nothing here depends on the hardware, so the logic can be driven from a
+// script on the host.
+
+const PADDING: u32 = 2;
+const TOAST_LEN: usize = 40;
+
+/// Colours and font for the widgets, `S` is any text style that can change
+/// colour, e.g. `MonoTextStyle` or `BitmapTextStyle`
+#[derive(Debug, Clone, Copy)]
+pub struct Theme<S, C> {
+    pub text_style: S,
+    pub text: C,
+    pub background: C,
+    pub focus_text: C,
+    pub focus_background: C,
+    /// Filled part of sliders, the dialog border
+    pub accent: C,
+}
+
+/// What the UI remembers between frames
+#[derive(Debug, Clone)]
+pub struct UiState {
+    focus: usize,
+    /// Focusable widgets seen last frame
+    widgets: usize,
+    /// A dialog was open last frame, so it gets the input
+    modal: bool,
+    dialog_yes: bool,
+    toast: [u8; TOAST_LEN],
+    toast_len: usize,
+    toast_until_us: u64,
+}
+
+impl UiState {
+    pub const fn new() -> Self {
+        Self {
+            focus: 0,
+            widgets: 0,
+            modal: false,
+            dialog_yes: false,
+            toast: [0; TOAST_LEN],
+            toast_len: 0,
+            toast_until_us: 0,
+        }
+    }
+
+    pub fn focus(&self) -> usize {
+        self.focus
+    }
+
+    pub fn set_focus(&mut self, focus: usize) {
+        self.focus = focus;
+    }
+
+    /// Show `message` at the bottom for `duration_us`, anything past 40
+    /// bytes is cut off
+    pub fn toast(&mut self, message: &str, now_us: u64, duration_us: u64) {
+        let mut len = message.len().min(TOAST_LEN);
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.toast[..len].copy_from_slice(&message.as_bytes()[..len]);
+        self.toast_len = len;
+        self.toast_until_us = now_us + duration_us;
+    }
+
+    /// The toast that's showing at `now_us`
+    pub fn current_toast(&self, now_us: u64) -> Option<&str> {
+        if self.toast_len == 0 || now_us >= self.toast_until_us {
+            return None;
+        }
+        core::str::from_utf8(&self.toast[..self.toast_len]).ok()
+    }
+
+    /// A dialog is taking the input
+    pub fn is_modal(&self) -> bool {
+        self.modal
+    }
+}
+
+impl Default for UiState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One frame of UI, see the module comment
+pub struct Ui<'a, S, C, D: DrawTarget<Color = C>> {
+    state: &'a mut UiState,
+    clicks: ButtonClick,
+    now_us: u64,
+    area: Rectangle,
+    theme: &'a Theme<S, C>,
+    target: &'a mut D,
+    /// Top of the next row
+    y: i32,
+    /// Index the next focusable widget gets
+    index: usize,
+    dialog_open: bool,
+    error: Option<D::Error>,
+}
+
+impl<'a, S, C, D> Ui<'a, S, C, D>
+where
+    S: TextRenderer<Color = C> + CharacterStyle<Color = C> + Clone,
+    C: PixelColor,
+    D: DrawTarget<Color = C>,
+{
+    /// Start a frame, laying the widgets out top to bottom in `area`
+    pub fn begin(
+        state: &'a mut UiState,
+        clicks: ButtonClick,
+        now_us: u64,
+        area: Rectangle,
+        theme: &'a Theme<S, C>,
+        target: &'a mut D,
+    ) -> Self {
+        if !state.modal && state.widgets > 0 {
+            if clicks.up {
+                state.focus = state.focus.checked_sub(1).unwrap_or(state.widgets - 1);
+            }
+            if clicks.down {
+                state.focus = (state.focus + 1) % state.widgets;
+            }
+        }
+
+        let mut ui = Self {
+            state,
+            clicks,
+            now_us,
+            area,
+            theme,
+            target,
+            y: area.top_left.y,
+            index: 0,
+            dialog_open: false,
+            error: None,
+        };
+        let background = ui.theme.background;
+        ui.fill(&area, background);
+        ui
+    }
+
+    /// Finish the frame, drawing the toast on top. Returns the first
+    /// drawing error, if any.
+    pub fn end(self) -> Result<(), D::Error> {
+        let Self { state, theme, target, area, now_us, index, dialog_open, mut error, .. } = self;
+        state.widgets = index;
+        state.focus = state.focus.min(index.saturating_sub(1));
+        state.modal = dialog_open;
+
+        if let Some(message) = state.current_toast(now_us) {
+            let height = theme.text_style.line_height() + 2 * PADDING;
+            let width = theme.text_style.measure_string(message, Point::zero(), Baseline::Top).bounding_box.size.width + 4 * PADDING;
+            let bottom = area.bottom_right().unwrap_or(area.top_left);
+            let pos = Point::new(area.center().x - width as i32 / 2, bottom.y - height as i32 - PADDING as i32);
+            let rect = Rectangle::new(pos, Size::new(width, height));
+
+            let result = rect
+                .into_styled(PrimitiveStyle::with_fill(theme.focus_background))
+                .draw(target)
+                .and_then(|_| {
+                    let mut style = theme.text_style.clone();
+                    style.set_text_color(Some(theme.focus_text));
+                    style.set_background_color(None);
+                    Text::with_baseline(message, pos + Point::new(2 * PADDING as i32, PADDING as i32), style, Baseline::Top).draw(target)
+                });
+            if let Err(e) = result {
+                error.get_or_insert(e);
+            }
+        }
+
+        error.map_or(Ok(()), Err)
+    }
+
+    /// B was clicked outside a dialog, e.g. to leave a menu
+    pub fn back(&self) -> bool {
+        !self.state.modal && self.clicks.b
+    }
+
+    fn record<T>(&mut self, result: Result<T, D::Error>) {
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    fn fill(&mut self, rect: &Rectangle, color: C) {
+        let result = self.target.fill_solid(rect, color);
+        self.record(result);
+    }
+
+    fn text(&mut self, text: &str, pos: Point, alignment: Alignment, color: C) {
+        let mut style = self.theme.text_style.clone();
+        style.set_text_color(Some(color));
+        style.set_background_color(None);
+        let text_style = TextStyleBuilder::new().alignment(alignment).baseline(Baseline::Top).build();
+        let result = Text::with_text_style(text, pos, style, text_style).draw(self.target);
+        self.record(result);
+    }
+
+    /// Lay out the next row, returns it, whether it has the focus and the
+    /// clicks it should react to
+    fn row(&mut self) -> (Rectangle, bool, ButtonClick) {
+        let height = self.theme.text_style.line_height() + 2 * PADDING;
+        let rect = Rectangle::new(Point::new(self.area.top_left.x, self.y), Size::new(self.area.size.width, height));
+        self.y += height as i32;
+
+        let focused = self.index == self.state.focus;
+        self.index += 1;
+        let clicks = if focused && !self.state.modal { self.clicks } else { ButtonClick::default() };
+
+        let background = if focused { self.theme.focus_background } else { self.theme.background };
+        self.fill(&rect, background);
+        (rect, focused, clicks)
+    }
+
+    fn row_text_color(&self, focused: bool) -> C {
+        if focused {
+            self.theme.focus_text
+        } else {
+            self.theme.text
+        }
+    }
+
+    fn label(&mut self, rect: &Rectangle, focused: bool, label: &str) {
+        let color = self.row_text_color(focused);
+        self.text(label, rect.top_left + Point::new(PADDING as i32 * 2, PADDING as i32), Alignment::Left, color);
+    }
+
+    fn value(&mut self, rect: &Rectangle, focused: bool, value: &str) {
+        let color = self.row_text_color(focused);
+        let right = rect.top_left + Point::new(rect.size.width as i32 - PADDING as i32 * 2, PADDING as i32);
+        self.text(value, right, Alignment::Right, color);
+    }
+
+    /// Non focusable text
+    pub fn label_row(&mut self, text: &str) {
+        let height = self.theme.text_style.line_height() + 2 * PADDING;
+        let rect = Rectangle::new(Point::new(self.area.top_left.x, self.y), Size::new(self.area.size.width, height));
+        self.y += height as i32;
+        self.label(&rect, false, text);
+    }
+
+    /// Returns true when activated with A
+    pub fn button(&mut self, label: &str) -> bool {
+        let (rect, focused, clicks) = self.row();
+        self.label(&rect, focused, label);
+        clicks.a
+    }
+
+    /// A vertical list of buttons, returns the index of the one activated
+    pub fn menu(&mut self, items: &[&str]) -> Option<usize> {
+        let mut chosen = None;
+        for (i, item) in items.iter().enumerate() {
+            if self.button(item) {
+                chosen = Some(i);
+            }
+        }
+        chosen
+    }
+
+    /// Flips with A, left or right. Returns true when it changed.
+    pub fn toggle(&mut self, label: &str, value: &mut bool) -> bool {
+        let (rect, focused, clicks) = self.row();
+        let changed = clicks.a || clicks.left || clicks.right;
+        if changed {
+            *value = !*value;
+        }
+        self.label(&rect, focused, label);
+        self.value(&rect, focused, if *value { "ON" } else { "OFF" });
+        changed
+    }
+
+    /// Left/right move by `step` within `range`. Returns true when it
+    /// changed.
+    pub fn slider(&mut self, label: &str, value: &mut i32, range: RangeInclusive<i32>, step: i32) -> bool {
+        let (rect, focused, clicks) = self.row();
+        let changed = adjust(value, &range, step, clicks, false);
+        self.label(&rect, focused, label);
+
+        // the bar takes the right half of the row
+        let bar = Rectangle::new(
+            rect.top_left + Point::new(rect.size.width as i32 / 2, PADDING as i32 + 1),
+            Size::new(rect.size.width / 2 - PADDING * 2, rect.size.height.saturating_sub(PADDING * 2 + 2)),
+        );
+        let span = (range.end() - range.start()).max(1) as u32;
+        let filled = bar.size.width * (*value - range.start()) as u32 / span;
+        let text = self.row_text_color(focused);
+        let result = bar.into_styled(PrimitiveStyle::with_stroke(text, 1)).draw(self.target);
+        self.record(result);
+        let accent = self.theme.accent;
+        self.fill(&Rectangle::new(bar.top_left, Size::new(filled, bar.size.height)), accent);
+
+        changed
+    }
+
+    /// Left/right step by one through `range`, wrapping around. Returns
+    /// true when it changed.
+    pub fn spinner(&mut self, label: &str, value: &mut i32, range: RangeInclusive<i32>) -> bool {
+        let (rect, focused, clicks) = self.row();
+        let changed = adjust(value, &range, 1, clicks, true);
+        self.label(&rect, focused, label);
+
+        let mut text = TextBuf::new();
+        let _ = write!(text, "< {} >", value);
+        self.value(&rect, focused, text.as_str());
+        changed
+    }
+
+    /// A yes/no dialog over everything drawn so far, so call it after the
+    /// other widgets. Call it every frame for as long as it should stay
+    /// open; it returns the answer once, when A or B is pressed. While it's
+    /// open the other widgets get no input.
+    pub fn confirm(&mut self, message: &str) -> Option<bool> {
+        let first_frame = !self.state.modal;
+        self.dialog_open = true;
+        if first_frame {
+            self.state.dialog_yes = false;
+        }
+
+        let mut answer = None;
+        if !first_frame {
+            if self.clicks.left || self.clicks.right {
+                self.state.dialog_yes = !self.state.dialog_yes;
+            }
+            if self.clicks.a {
+                answer = Some(self.state.dialog_yes);
+            } else if self.clicks.b {
+                answer = Some(false);
+            }
+        }
+        if answer.is_some() {
+            // closed, give the input back next frame
+            self.dialog_open = false;
+        }
+
+        let line = self.theme.text_style.line_height() as i32;
+        let size = Size::new(self.area.size.width * 3 / 4, (line as u32 + PADDING * 2) * 2 + PADDING * 4);
+        let rect = Rectangle::with_center(self.area.center(), size);
+        let (background, accent, text, focus_bg, focus_text) = (
+            self.theme.background,
+            self.theme.accent,
+            self.theme.text,
+            self.theme.focus_background,
+            self.theme.focus_text,
+        );
+        let result = rect
+            .into_styled(PrimitiveStyle::with_fill(background))
+            .draw(self.target)
+            .and_then(|_| rect.into_styled(PrimitiveStyle::with_stroke(accent, 1)).draw(self.target));
+        self.record(result);
+
+        let top = rect.top_left.y + PADDING as i32 * 2;
+        self.text(message, Point::new(rect.center().x, top), Alignment::Center, text);
+
+        let y = top + line + PADDING as i32 * 2;
+        let quarter = rect.size.width as i32 / 4;
+        for (label, yes) in [("Yes", true), ("No", false)] {
+            let x = rect.top_left.x + if yes { quarter } else { quarter * 3 };
+            let selected = yes == self.state.dialog_yes;
+            if selected {
+                let width = self.theme.text_style.measure_string(label, Point::zero(), Baseline::Top).bounding_box.size.width;
+                let highlight = Rectangle::with_center(Point::new(x, y + line / 2), Size::new(width + PADDING * 4, line as u32 + PADDING));
+                self.fill(&highlight, focus_bg);
+            }
+            self.text(label, Point::new(x, y), Alignment::Center, if selected { focus_text } else { text });
+        }
+
+        answer
+    }
+
+    pub fn now_us(&self) -> u64 {
+        self.now_us
+    }
+}
+
+/// Apply left/right to `value`, returns whether it changed
+fn adjust(value: &mut i32, range: &RangeInclusive<i32>, step: i32, clicks: ButtonClick, wrap: bool) -> bool {
+    let old = *value;
+    let delta = match (clicks.left, clicks.right) {
+        (true, false) => -step,
+        (false, true) => step,
+        _ => 0,
+    };
+    let new = value.saturating_add(delta);
+    *value = if !wrap {
+        new.clamp(*range.start(), *range.end())
+    } else if new > *range.end() {
+        *range.start()
+    } else if new < *range.start() {
+        *range.end()
+    } else {
+        new
+    };
+    *value != old
+}
+
+/// Enough room for a formatted number and some decoration
+struct TextBuf {
+    buf: [u8; 24],
+    len: usize,
+}
+
+impl TextBuf {
+    fn new() -> Self {
+        Self { buf: [0; 24], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for TextBuf {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(core::fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+// Compresses audio for the external flash: IMA ADPCM (4 bits a sample) when
+// the output ends in .wav, QOA (3.2 bits a sample) when it ends in .qoa.
+// The result is decoded again with the firmware's decoder to check it
+// loads and to report how close it came.
+//
+// usage: encode <in.wav|in.raw_s16le_pcm> <out.wav|out.qoa> [--rate <hz>]
+//
+// Raw input is taken to be mono at --rate, 44100 unless given.
+
+use std::process::ExitCode;
+
+use game_and_watch_core::{
+    adpcm::ImaAdpcm,
+    qoa::Qoa,
+    stream::{Codec, Decoder, DecodeError},
+};
+use game_and_watch_host::encode::{encode_ima_adpcm, encode_qoa, read_samples, ADPCM_BLOCK_ALIGN};
+
+/// Signal to noise ratio in dB
+fn snr(original: &[i16], decoded: &[i16]) -> f64 {
+    let (signal, noise) = original.iter().zip(decoded).fold((0f64, 0f64), |(s, n), (&a, &b)| {
+        let error = a as f64 - b as f64;
+        (s + a as f64 * a as f64, n + error * error)
+    });
+    10.0 * (signal / noise.max(1.0)).log10()
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let mut rate = 44_100;
+    let mut paths = Vec::new();
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        if arg == "--rate" {
+            match rest.next().and_then(|r| r.parse().ok()) {
+                Some(r) => rate = r,
+                None => paths.clear(),
+            }
+        } else {
+            paths.push(arg);
+        }
+    }
+    if paths.len() != 2 {
+        eprintln!("usage: {} <in.wav|in.raw_s16le_pcm> <out.wav|out.qoa> [--rate <hz>]", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    let input = match std::fs::read(paths[0]) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("can't read {}: {e}", paths[0]);
+            return ExitCode::FAILURE;
+        }
+    };
+    let (samples, rate) = match read_samples(&input, rate) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {e}", paths[0]);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let encoded = if paths[1].ends_with(".qoa") {
+        encode_qoa(&samples, rate)
+    } else if paths[1].ends_with(".wav") {
+        encode_ima_adpcm(&samples, rate, ADPCM_BLOCK_ALIGN)
+    } else {
+        eprintln!("{}: the output has to be .wav (IMA ADPCM) or .qoa", paths[1]);
+        return ExitCode::FAILURE;
+    };
+
+    // make sure the firmware side will take it
+    let codec: Result<Codec, DecodeError> = if paths[1].ends_with(".qoa") {
+        Qoa::from_bytes(&encoded).map(Codec::from)
+    } else {
+        ImaAdpcm::from_wav(&encoded).map(Codec::from)
+    };
+    let mut codec = match codec {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("encoded audio doesn't load: {e:?}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut decoded = vec![0; codec.len() as usize];
+    codec.decode(&mut decoded);
+
+    if let Err(e) = std::fs::write(paths[1], &encoded) {
+        eprintln!("can't write {}: {e}", paths[1]);
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "wrote {} samples at {rate} Hz, {} bytes ({:.1}:1), to {}, SNR {:.1} dB",
+        samples.len(),
+        encoded.len(),
+        (samples.len() * 2) as f64 / encoded.len() as f64,
+        paths[1],
+        snr(&samples, &decoded),
+    );
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,51 @@
+// Converts a Unicode BDF font to the GWF1 format the firmware draws text
+// with, ready to go into the external flash.
+//
+// usage: font <in.bdf> <out.gwf> [--all]
+//
+// Only Latin-1 and the kana blocks are kept unless --all is given.
+
+use std::process::ExitCode;
+
+use game_and_watch_core::font::BitmapFont;
+use game_and_watch_host::font::{bdf_to_gwf, default_charset};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let all = args.iter().any(|a| a == "--all");
+    let paths: Vec<&String> = args.iter().skip(1).filter(|a| *a != "--all").collect();
+    if paths.len() != 2 {
+        eprintln!("usage: {} <in.bdf> <out.gwf> [--all]", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    let bdf = match std::fs::read(paths[0]) {
+        Ok(d) => String::from_utf8_lossy(&d).into_owned(),
+        Err(e) => {
+            eprintln!("can't read {}: {e}", paths[0]);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let gwf = match bdf_to_gwf(&bdf, |c| all || default_charset(c)) {
+        Ok(gwf) => gwf,
+        Err(e) => {
+            eprintln!("{}: {e}", paths[0]);
+            return ExitCode::FAILURE;
+        }
+    };
+    // make sure the firmware side will take it
+    if let Err(e) = BitmapFont::from_bytes(&gwf) {
+        eprintln!("converted font doesn't load: {e:?}");
+        return ExitCode::FAILURE;
+    }
+
+    if let Err(e) = std::fs::write(paths[1], &gwf) {
+        eprintln!("can't write {}: {e}", paths[1]);
+        return ExitCode::FAILURE;
+    }
+    let glyphs = u16::from_le_bytes([gwf[6], gwf[7]]);
+    println!("wrote {glyphs} glyphs, {} bytes, to {}", gwf.len(), paths[1]);
+
+    ExitCode::SUCCESS
+}
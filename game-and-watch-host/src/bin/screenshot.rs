@@ -0,0 +1,74 @@
+// Turns screenshots from the firmware into PNGs. The input is either a
+// defmt log with `screenshot begin/data/end` lines, as saved from
+// defmt-print or probe-rs, or a raw .bmp/.qoi dump.
+//
+// usage: screenshot <log or dump> <out.png>
+//
+// A log with several screenshots gives out_0.png, out_1.png, ...
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use game_and_watch_core::screenshot::ScreenshotFormat;
+use game_and_watch_host::{
+    png_display::write_png_rgb888,
+    screenshot::{decode, detect_format, extract_from_log},
+};
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().map(|e| e.to_string_lossy()).unwrap_or("png".into());
+    path.with_file_name(format!("{stem}_{n}.{ext}"))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <log or dump> <out.png>", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    let data = match std::fs::read(&args[1]) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("can't read {}: {e}", args[1]);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let shots: Vec<(ScreenshotFormat, Vec<u8>)> = match detect_format(&data) {
+        Some(format) => vec![(format, data)],
+        None => match extract_from_log(&String::from_utf8_lossy(&data)) {
+            Ok(shots) => shots,
+            Err(e) => {
+                eprintln!("{}: {e}", args[1]);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    if shots.is_empty() {
+        eprintln!("no screenshots in {}", args[1]);
+        return ExitCode::FAILURE;
+    }
+
+    let out = Path::new(&args[2]);
+    for (n, (format, bytes)) in shots.iter().enumerate() {
+        let path = if shots.len() == 1 { out.to_path_buf() } else { numbered(out, n) };
+
+        let (rgb, size) = match decode(*format, bytes) {
+            Ok(image) => image,
+            Err(e) => {
+                eprintln!("screenshot {n}: {e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        if let Err(e) = write_png_rgb888(&path, &rgb, size) {
+            eprintln!("can't write {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+        println!("wrote {}x{} {} to {}", size.width, size.height, format.extension(), path.display());
+    }
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,72 @@
+// Runs the game loop from game-and-watch-core headless and dumps every
+// frame as a PNG
+//
+// usage: sim <out-dir> [frames] [held buttons, e.g. right,down]
+
+use std::process::ExitCode;
+
+use embassy_futures::block_on;
+use embedded_graphics::prelude::*;
+use game_and_watch_core::{
+    frame_pacer::TargetFps,
+    framebuffer::{DoubleBuffer, TargetPixelType, WIDTH, HEIGHT},
+    game::{self, GameState},
+    input::ButtonClick,
+};
+use game_and_watch_host::{png_display::PngDisplay, script::parse_buttons};
+use tinybmp::Bmp;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: {} <out-dir> [frames] [held buttons]", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    let frames: usize = match args.get(2).map(|f| f.parse()) {
+        None => 60,
+        Some(Ok(f)) => f,
+        Some(Err(e)) => {
+            eprintln!("invalid frame count: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let reading = match parse_buttons(args.get(3).map(String::as_str).unwrap_or("")) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let size = Size::new(WIDTH as u32, HEIGHT as u32);
+    let mut display = match PngDisplay::new(&args[1]) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("can't create {}: {e}", args[1]);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut front: Vec<TargetPixelType> = vec![0; WIDTH * HEIGHT];
+    let mut back: Vec<TargetPixelType> = vec![0; WIDTH * HEIGHT];
+    let mut disp = DoubleBuffer::new([&mut front, &mut back], size);
+
+    let ferris = Bmp::from_slice(include_bytes!("../../../game-and-watch-stm32/assets/ferris.bmp")).unwrap();
+    let mut gs = GameState::new();
+
+    // simulated time, one 30fps frame per step
+    let period = TargetFps::Fps30.frame_period_us();
+    for frame in 0..frames {
+        game::update(&mut gs, &mut display, reading, ButtonClick::default(), frame as u64 * period);
+        game::render(&mut gs, &mut disp, Some(&ferris)).unwrap();
+        if let Err(e) = block_on(disp.swap(&mut display)) {
+            eprintln!("failed to write frame: {e}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    println!("wrote {} frames to {}", display.frame_count(), args[1]);
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,139 @@
+// Renders a tracker song through game_and_watch_core::synth to a WAV file,
+// exactly as the firmware would play it. The synth only does integer maths,
+// so the printed checksum is the same on every run and every machine.
+//
+// usage: synth <out.wav> [--rate 32000|44100|48000]
+
+use std::process::ExitCode;
+
+use game_and_watch_core::{
+    audio::{AudioSource, SampleRate},
+    synth::{Adsr, Instrument, Waveform},
+    tracker::{pattern, Player, Row, Song},
+};
+use game_and_watch_host::encode::pcm_wav;
+
+/// One period of a sine
+const SINE: [i8; 32] = [
+    0, 25, 49, 71, 90, 106, 117, 125, 127, 125, 117, 106, 90, 71, 49, 25, 0, -25, -49, -71, -90, -106, -117, -125,
+    -127, -125, -117, -106, -90, -71, -49, -25,
+];
+
+const INSTRUMENTS: [Instrument; 5] = [
+    // 0: lead
+    Instrument {
+        waveform: Waveform::Pulse { duty: 64 },
+        envelope: Adsr { attack_ms: 2, decay_ms: 120, sustain: 160, release_ms: 80 },
+        volume: 200,
+    },
+    // 1: bass
+    Instrument {
+        waveform: Waveform::Triangle,
+        envelope: Adsr { attack_ms: 1, decay_ms: 0, sustain: 255, release_ms: 30 },
+        volume: 255,
+    },
+    // 2: hi-hat
+    Instrument {
+        waveform: Waveform::Noise { short: false },
+        envelope: Adsr { attack_ms: 0, decay_ms: 40, sustain: 0, release_ms: 0 },
+        volume: 120,
+    },
+    // 3: snare
+    Instrument {
+        waveform: Waveform::Noise { short: false },
+        envelope: Adsr { attack_ms: 0, decay_ms: 150, sustain: 0, release_ms: 0 },
+        volume: 220,
+    },
+    // 4: bell
+    Instrument {
+        waveform: Waveform::Wavetable(&SINE),
+        envelope: Adsr { attack_ms: 5, decay_ms: 400, sustain: 0, release_ms: 100 },
+        volume: 180,
+    },
+];
+
+const VERSE: [Row; 16] = pattern([
+    ["C-5 0", "C-3 1", "C-8 2", "---"],
+    ["---", "---", "C-8 2", "---"],
+    ["E-5 0", "===", "C-7 3", "G-5 4"],
+    ["---", "---", "C-8 2", "---"],
+    ["G-5 0", "G-2 1", "C-8 2", "---"],
+    ["===", "---", "C-8 2", "---"],
+    ["E-5 0", "===", "C-7 3", "C-6 4"],
+    ["---", "---", "C-8 2", "---"],
+    ["F-5 0", "F-2 1", "C-8 2", "---"],
+    ["---", "---", "C-8 2", "---"],
+    ["A-5 0", "===", "C-7 3", "A-5 4"],
+    ["---", "---", "C-8 2", "---"],
+    ["G-5 0", "G-2 1", "C-8 2", "---"],
+    ["---", "---", "C-8 2", "---"],
+    ["F-5 0", "===", "C-7 3", "B-5 4"],
+    ["E-5 0", "---", "C-8 2", "---"],
+]);
+
+const ENDING: [Row; 8] = pattern([
+    ["C-5 0", "C-3 1", "C-8 2", "C-6 4"],
+    ["---", "---", "---", "---"],
+    ["G-4 0", "G-2 1", "C-7 3", "---"],
+    ["---", "---", "---", "---"],
+    ["C-5 0", "C-3 1", "C-7 3", "E-6 4"],
+    ["---", "---", "---", "---"],
+    ["===", "===", "---", "---"],
+    ["---", "---", "---", "---"],
+]);
+
+static SONG: Song = Song {
+    instruments: &INSTRUMENTS,
+    patterns: &[&VERSE, &ENDING],
+    sequence: &[0, 0, 1],
+    loop_to: None,
+    row_ms: 125,
+};
+
+/// FNV-1a over the samples
+fn checksum(samples: &[i16]) -> u64 {
+    samples.iter().flat_map(|s| s.to_le_bytes()).fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let rate = match args.get(2..).unwrap_or_default() {
+        [] => Some(SampleRate::Hz48000),
+        [flag, hz] if flag == "--rate" => match hz.as_str() {
+            "32000" => Some(SampleRate::Hz32000),
+            "44100" => Some(SampleRate::Hz44100),
+            "48000" => Some(SampleRate::Hz48000),
+            _ => None,
+        },
+        _ => None,
+    };
+    let (Some(path), Some(rate)) = (args.get(1), rate) else {
+        eprintln!("usage: {} <out.wav> [--rate 32000|44100|48000]", args[0]);
+        return ExitCode::FAILURE;
+    };
+
+    let mut player = Player::new(&SONG, rate);
+    let mut samples = Vec::new();
+    let mut block = [0i16; 1024];
+    loop {
+        let n = player.fill(&mut block);
+        samples.extend_from_slice(&block[..n]);
+        if n < block.len() {
+            break;
+        }
+    }
+
+    if let Err(e) = std::fs::write(path, pcm_wav(&samples, rate.hz())) {
+        eprintln!("can't write {path}: {e}");
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "wrote {} samples at {} Hz to {path}, checksum {:016x}",
+        samples.len(),
+        rate.hz(),
+        checksum(&samples)
+    );
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,122 @@
+// Drives a settings screen built with game_and_watch_core::ui from a click
+// script, printing what the widgets report and dumping every frame as a
+// PNG. Handy for trying out the widget logic without the hardware.
+//
+// usage: ui <out-dir> <clicks, e.g. down,right,right,down,a,-,left,a>
+
+use std::process::ExitCode;
+
+use embassy_futures::block_on;
+use embedded_graphics::{mono_font::{ascii, MonoTextStyle}, pixelcolor::Rgb565, prelude::*};
+use game_and_watch_core::{
+    frame_pacer::TargetFps,
+    framebuffer::{DoubleBuffer, TargetPixelType, HEIGHT, WIDTH},
+    ui::{Theme, Ui, UiState},
+};
+use game_and_watch_host::{png_display::PngDisplay, script::parse_clicks};
+
+struct Settings {
+    sound: bool,
+    volume: i32,
+    lives: i32,
+    confirm_reset: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            sound: true,
+            volume: 5,
+            lives: 3,
+            confirm_reset: false,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <out-dir> <clicks>", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    let script = match parse_clicks(&args[2]) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut display = match PngDisplay::new(&args[1]) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("can't create {}: {e}", args[1]);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let size = Size::new(WIDTH as u32, HEIGHT as u32);
+    let mut front: Vec<TargetPixelType> = vec![0; WIDTH * HEIGHT];
+    let mut back: Vec<TargetPixelType> = vec![0; WIDTH * HEIGHT];
+    let mut disp = DoubleBuffer::new([&mut front, &mut back], size);
+
+    let theme = Theme {
+        text_style: MonoTextStyle::new(&ascii::FONT_9X18, Rgb565::WHITE),
+        text: Rgb565::WHITE,
+        background: Rgb565::new(2, 4, 8),
+        focus_text: Rgb565::BLACK,
+        focus_background: Rgb565::YELLOW,
+        accent: Rgb565::GREEN,
+    };
+    let mut state = UiState::new();
+    let mut settings = Settings::default();
+
+    let period = TargetFps::Fps30.frame_period_us();
+    for (frame, clicks) in script.into_iter().enumerate() {
+        let now_us = frame as u64 * period;
+        disp.mark_all_dirty();
+
+        let area = disp.bounding_box();
+        let mut ui = Ui::begin(&mut state, clicks, now_us, area, &theme, &mut disp);
+        ui.label_row("Settings");
+        if ui.toggle("Sound", &mut settings.sound) {
+            println!("{frame}: sound {}", settings.sound);
+        }
+        if ui.slider("Volume", &mut settings.volume, 0..=10, 1) {
+            println!("{frame}: volume {}", settings.volume);
+        }
+        if ui.spinner("Lives", &mut settings.lives, 1..=5) {
+            println!("{frame}: lives {}", settings.lives);
+        }
+        if ui.button("Reset") {
+            settings.confirm_reset = true;
+        }
+        if ui.back() {
+            println!("{frame}: back");
+        }
+        let mut reset = false;
+        if settings.confirm_reset {
+            if let Some(yes) = ui.confirm("Reset settings?") {
+                println!("{frame}: reset {}", if yes { "confirmed" } else { "cancelled" });
+                settings.confirm_reset = false;
+                reset = yes;
+            }
+        }
+        ui.end().unwrap();
+
+        if reset {
+            settings = Settings::default();
+            // shows from the next frame
+            state.toast("Settings reset", now_us, 1_000_000);
+        }
+
+        if let Err(e) = block_on(disp.swap(&mut display)) {
+            eprintln!("failed to write frame: {e}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    println!("wrote {} frames to {}", display.frame_count(), args[1]);
+    ExitCode::SUCCESS
+}
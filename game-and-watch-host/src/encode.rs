@@ -0,0 +1,184 @@
+use game_and_watch_core::{
+    adpcm::{samples_per_block, AdpcmState, BLOCK_HEADER_SIZE, WAVE_FORMAT_IMA_ADPCM},
+    qoa::{frame_size, Lms, DEQUANT, FRAME_LEN, QOA_MAGIC, SCALEFACTORS, SLICE_LEN},
+};
+
+// Encoders for the compressed formats the firmware streams from flash, see
+// game_and_watch_core::adpcm and game_and_watch_core::qoa. Input is mono, as
+// 16 bit PCM WAV (channels are mixed down) or raw s16le.
+
+/// Bytes per IMA ADPCM block, 1017 samples
+pub const ADPCM_BLOCK_ALIGN: usize = 512;
+
+fn le16(bytes: &[u8]) -> Vec<i16> {
+    bytes.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect()
+}
+
+fn chunks(wav: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut at = 12;
+    std::iter::from_fn(move || {
+        let id = wav.get(at..at + 4)?;
+        let size = u32::from_le_bytes(wav.get(at + 4..at + 8)?.try_into().unwrap()) as usize;
+        let body = wav.get(at + 8..(at + 8 + size).min(wav.len()))?;
+        at += 8 + size + (size & 1);
+        Some((id, body))
+    })
+}
+
+/// Samples and rate from a 16 bit PCM WAV, or raw s16le at `raw_rate`
+pub fn read_samples(input: &[u8], raw_rate: u32) -> Result<(Vec<i16>, u32), String> {
+    if !input.starts_with(b"RIFF") {
+        return Ok((le16(input), raw_rate));
+    }
+    if input.get(8..12) != Some(b"WAVE") {
+        return Err("not a WAV file".into());
+    }
+
+    let fmt = chunks(input).find(|(id, _)| *id == b"fmt ").map(|(_, b)| b).ok_or("no fmt chunk")?;
+    let data = chunks(input).find(|(id, _)| *id == b"data").map(|(_, b)| b).ok_or("no data chunk")?;
+    if fmt.len() < 16 {
+        return Err("fmt chunk too short".into());
+    }
+    let field = |at: usize| u16::from_le_bytes([fmt[at], fmt[at + 1]]);
+    let (format, channels, bits) = (field(0), field(2) as usize, field(14));
+    let rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+    if format != 1 || bits != 16 || channels == 0 {
+        return Err(format!("only 16 bit PCM is supported, not format {format} with {bits} bits"));
+    }
+
+    let samples = le16(data)
+        .chunks_exact(channels)
+        .map(|frame| (frame.iter().map(|&s| s as i32).sum::<i32>() / channels as i32) as i16)
+        .collect();
+    Ok((samples, rate))
+}
+
+/// A mono IMA ADPCM WAV file
+pub fn encode_ima_adpcm(samples: &[i16], rate: u32, block_align: usize) -> Vec<u8> {
+    let per_block = samples_per_block(block_align);
+    let mut data = Vec::new();
+    let mut state = AdpcmState::default();
+    for block in samples.chunks(per_block) {
+        state.predictor = block[0];
+        data.extend_from_slice(&block[0].to_le_bytes());
+        data.extend_from_slice(&[state.index, 0]);
+
+        let mut codes = vec![0u8; block_align - BLOCK_HEADER_SIZE];
+        for (k, &sample) in block[1..].iter().enumerate() {
+            codes[k / 2] |= state.encode(sample) << (k % 2 * 4);
+        }
+        if block.len() < per_block {
+            // the last block only goes as far as it needs to
+            codes.truncate(block.len() / 2);
+        }
+        data.extend_from_slice(&codes);
+    }
+
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&WAVE_FORMAT_IMA_ADPCM.to_le_bytes());
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&rate.to_le_bytes());
+    let byte_rate = rate as u64 * block_align as u64 / per_block as u64;
+    fmt.extend_from_slice(&(byte_rate as u32).to_le_bytes());
+    fmt.extend_from_slice(&(block_align as u16).to_le_bytes());
+    fmt.extend_from_slice(&4u16.to_le_bytes());
+    // extra format bytes: samples per block
+    fmt.extend_from_slice(&2u16.to_le_bytes());
+    fmt.extend_from_slice(&(per_block as u16).to_le_bytes());
+
+    riff_wave(&[(b"fmt ", &fmt), (b"fact", &(samples.len() as u32).to_le_bytes()), (b"data", &data)])
+}
+
+fn riff_wave(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut body = b"WAVE".to_vec();
+    for (id, chunk) in chunks {
+        body.extend_from_slice(*id);
+        body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        body.extend_from_slice(chunk);
+        if chunk.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    let mut wav = b"RIFF".to_vec();
+    wav.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&body);
+    wav
+}
+
+/// A mono 16 bit PCM WAV file, for listening to what the firmware would play
+pub fn pcm_wav(samples: &[i16], rate: u32) -> Vec<u8> {
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&rate.to_le_bytes());
+    fmt.extend_from_slice(&(rate * 2).to_le_bytes());
+    fmt.extend_from_slice(&2u16.to_le_bytes());
+    fmt.extend_from_slice(&16u16.to_le_bytes());
+
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    riff_wave(&[(b"fmt ", &fmt), (b"data", &data)])
+}
+
+/// 1 / scale factor in 16.16, for dividing the way the reference encoder
+/// does
+fn qoa_div(v: i32, scalefactor: usize) -> i32 {
+    let reciprocal = ((1 << 16) + SCALEFACTORS[scalefactor] - 1) / SCALEFACTORS[scalefactor];
+    // residuals go up to 17 bits, too many for 32 bit maths at scale 1
+    let n = ((v as i64 * reciprocal as i64 + (1 << 15)) >> 16) as i32;
+    // round away from zero
+    n + v.signum() - n.signum()
+}
+
+/// 3 bit code for a scaled residual of -8..=8
+const QUANT: [u64; 17] = [7, 7, 7, 5, 5, 3, 3, 1, 0, 0, 2, 2, 4, 4, 6, 6, 6];
+
+/// Encode one slice with `scalefactor`, returns the slice word, how bad it
+/// is and the predictor after it
+fn qoa_slice(samples: &[i16], scalefactor: usize, mut lms: Lms) -> (u64, u64, Lms) {
+    let mut slice = scalefactor as u64;
+    let mut rank = 0u64;
+    for &sample in samples {
+        let predicted = lms.predict();
+        let scaled = qoa_div(sample as i32 - predicted, scalefactor).clamp(-8, 8);
+        let code = QUANT[(scaled + 8) as usize];
+        let residual = DEQUANT[scalefactor][code as usize];
+        let decoded = (predicted + residual).clamp(i16::MIN as i32, i16::MAX as i32);
+
+        // big weights make the predictor go off on quiet passages
+        let weights: i64 = lms.weights.iter().map(|&w| w as i64 * w as i64).sum();
+        let penalty = ((weights >> 18) - 0x8ff).max(0) as u64;
+        let error = (sample as i32 - decoded) as i64;
+        rank += (error * error) as u64 + penalty * penalty;
+
+        lms.update(decoded, residual);
+        slice = slice << 3 | code;
+    }
+    slice <<= (SLICE_LEN - samples.len()) * 3;
+    (slice, rank, lms)
+}
+
+/// A mono QOA file
+pub fn encode_qoa(samples: &[i16], rate: u32) -> Vec<u8> {
+    let mut qoa = QOA_MAGIC.to_vec();
+    qoa.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+
+    let mut lms = Lms { history: [0; 4], weights: [0, 0, -(1 << 13), 1 << 14] };
+    for frame in samples.chunks(FRAME_LEN) {
+        let header = 1u64 << 56 | (rate as u64) << 32 | (frame.len() as u64) << 16 | frame_size(frame.len()) as u64;
+        qoa.extend_from_slice(&header.to_be_bytes());
+        for values in [lms.history, lms.weights] {
+            let packed = values.iter().fold(0u64, |p, &v| p << 16 | v as u16 as u64);
+            qoa.extend_from_slice(&packed.to_be_bytes());
+        }
+
+        for slice in frame.chunks(SLICE_LEN) {
+            let (word, _, after) = (0..SCALEFACTORS.len())
+                .map(|sf| qoa_slice(slice, sf, lms))
+                .min_by_key(|&(_, rank, _)| rank)
+                .unwrap();
+            lms = after;
+            qoa.extend_from_slice(&word.to_be_bytes());
+        }
+    }
+    qoa
+}
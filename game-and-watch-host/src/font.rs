@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+use game_and_watch_core::font::{FONT_HEADER_SIZE, FONT_MAGIC, GLYPH_ENTRY_SIZE};
+
+// Converting BDF bitmap fonts to the firmware's GWF1 format (see
+// game_and_watch_core::font). The BDF has to be Unicode encoded, i.e.
+// CHARSET_REGISTRY "ISO10646", so ENCODING is the code point.
+
+/// Latin-1, then the CJK punctuation, hiragana and katakana blocks and the
+/// full width forms
+pub fn default_charset(c: u32) -> bool {
+    matches!(c, 0x20..=0x7e | 0xa0..=0xff | 0x3000..=0x30ff | 0xff00..=0xffef | 0xfffd)
+}
+
+struct BdfGlyph {
+    width: u8,
+    height: u8,
+    x_offset: i8,
+    y_offset: i8,
+    advance: u8,
+    bitmap: Vec<u8>,
+}
+
+fn numbers<const N: usize>(line: &str, n: usize) -> Result<[i32; N], String> {
+    let values: Vec<i32> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|v| v.parse().map_err(|e| format!("line {}: {e}", n + 1)))
+        .collect::<Result<_, _>>()?;
+    values
+        .get(..N)
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| format!("line {}: expected {N} numbers", n + 1))
+}
+
+fn narrow<T: TryFrom<i32>>(value: i32, what: &str, c: u32) -> Result<T, String> {
+    T::try_from(value).map_err(|_| format!("U+{c:04X}: {what} {value} out of range"))
+}
+
+/// Convert a BDF font, keeping the characters `keep` accepts
+pub fn bdf_to_gwf(bdf: &str, keep: impl Fn(u32) -> bool) -> Result<Vec<u8>, String> {
+    let mut ascent = None;
+    let mut descent = None;
+    let mut glyphs = BTreeMap::new();
+
+    let mut encoding: Option<i32> = None;
+    let mut dwidth = 0;
+    let mut bbx = [0; 4];
+    let mut bitmap: Option<Vec<u8>> = None;
+
+    for (n, line) in bdf.lines().enumerate() {
+        let keyword = line.split_whitespace().next().unwrap_or("");
+        match keyword {
+            "FONT_ASCENT" => ascent = Some(numbers::<1>(line, n)?[0]),
+            "FONT_DESCENT" => descent = Some(numbers::<1>(line, n)?[0]),
+            "STARTCHAR" => {
+                encoding = None;
+                dwidth = 0;
+                bbx = [0; 4];
+            }
+            "ENCODING" => encoding = Some(numbers::<1>(line, n)?[0]),
+            "DWIDTH" => dwidth = numbers::<2>(line, n)?[0],
+            "BBX" => bbx = numbers::<4>(line, n)?,
+            "BITMAP" => bitmap = Some(Vec::new()),
+            "ENDCHAR" => {
+                let data = bitmap.take().ok_or_else(|| format!("line {}: ENDCHAR without BITMAP", n + 1))?;
+                // negative encodings are glyphs without a code point
+                let Some(c) = encoding.and_then(|e| u32::try_from(e).ok()).filter(|&c| keep(c)) else {
+                    continue;
+                };
+                let [w, h, x, y] = bbx;
+                let expected = (w as usize).div_ceil(8) * h as usize;
+                if data.len() != expected {
+                    return Err(format!("U+{c:04X}: {} bitmap bytes, expected {expected}", data.len()));
+                }
+                glyphs.insert(
+                    c,
+                    BdfGlyph {
+                        width: narrow(w, "width", c)?,
+                        height: narrow(h, "height", c)?,
+                        x_offset: narrow(x, "x offset", c)?,
+                        // BDF has the bottom of the box relative to the baseline, up is positive
+                        y_offset: narrow(-(y + h), "y offset", c)?,
+                        advance: narrow(dwidth, "advance", c)?,
+                        bitmap: data,
+                    },
+                );
+            }
+            _ => {
+                if let Some(data) = bitmap.as_mut() {
+                    let row = line.trim();
+                    for i in (0..row.len()).step_by(2) {
+                        let byte = row.get(i..i + 2).ok_or_else(|| format!("line {}: odd hex row", n + 1))?;
+                        data.push(u8::from_str_radix(byte, 16).map_err(|e| format!("line {}: {e}", n + 1))?);
+                    }
+                }
+            }
+        }
+    }
+
+    let ascent = ascent.ok_or("no FONT_ASCENT")?;
+    let descent = descent.ok_or("no FONT_DESCENT")?;
+    if glyphs.is_empty() {
+        return Err("no glyphs left".into());
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&FONT_MAGIC);
+    out.push(u8::try_from(ascent + descent).map_err(|_| "line height out of range")?);
+    out.push(u8::try_from(ascent).map_err(|_| "ascent out of range")?);
+    out.extend_from_slice(&u16::try_from(glyphs.len()).map_err(|_| "too many glyphs")?.to_le_bytes());
+    debug_assert_eq!(out.len(), FONT_HEADER_SIZE);
+
+    let mut offset = 0u32;
+    for (&c, g) in &glyphs {
+        let mut entry = [0u8; GLYPH_ENTRY_SIZE];
+        entry[0..4].copy_from_slice(&c.to_le_bytes());
+        entry[4..8].copy_from_slice(&offset.to_le_bytes());
+        entry[8] = g.width;
+        entry[9] = g.height;
+        entry[10] = g.x_offset as u8;
+        entry[11] = g.y_offset as u8;
+        entry[12] = g.advance;
+        out.extend_from_slice(&entry);
+        offset += g.bitmap.len() as u32;
+    }
+    for g in glyphs.values() {
+        out.extend_from_slice(&g.bitmap);
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,7 @@
+// Helpers shared by the host tools
+
+pub mod encode;
+pub mod font;
+pub mod png_display;
+pub mod screenshot;
+pub mod script;
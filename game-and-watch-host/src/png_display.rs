@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use embedded_graphics::{pixelcolor::{raw::RawU16, Rgb565, Rgb888}, prelude::*};
+use game_and_watch_core::{
+    backlight::MAX_BRIGHTNESS,
+    display::DisplayBackend,
+    framebuffer::{Frame, FrameSink, TargetPixelType},
+};
+
+/// Write an RGB565 frame as an 8 bit RGB PNG
+pub fn write_png(path: &Path, frame: &[TargetPixelType], size: Size) -> io::Result<()> {
+    let mut rgb = Vec::with_capacity(frame.len() * 3);
+    for &raw in frame {
+        let color = Rgb565::from(RawU16::new(raw));
+        let rgb888: Rgb888 = color.into();
+        rgb.extend_from_slice(&[rgb888.r(), rgb888.g(), rgb888.b()]);
+    }
+    write_png_rgb888(path, &rgb, size)
+}
+
+/// Write packed 8 bit RGB as a PNG
+pub fn write_png_rgb888(path: &Path, rgb: &[u8], size: Size) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), size.width, size.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(rgb).map_err(io::Error::other)?;
+    Ok(())
+}
+
+/// Scale an RGB565 pixel by `level` / 255
+fn dim(pixel: TargetPixelType, level: u8) -> TargetPixelType {
+    let color = Rgb565::from(RawU16::new(pixel));
+    let scale = |c: u8| (c as u32 * level as u32 / 255) as u8;
+    RawU16::from(Rgb565::new(scale(color.r()), scale(color.g()), scale(color.b()))).into_inner()
+}
+
+/// Dumps every presented frame to `<dir>/frame_NNNN.png`. Frames come out
+/// black while the simulated backlight is off and darker when it's dimmed,
+/// like on the real panel.
+pub struct PngDisplay {
+    dir: PathBuf,
+    frame_count: usize,
+    backlight: bool,
+    brightness: u8,
+    powered: bool,
+}
+
+impl PngDisplay {
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            frame_count: 0,
+            backlight: true,
+            brightness: MAX_BRIGHTNESS,
+            powered: true,
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+}
+
+impl FrameSink for PngDisplay {
+    type Error = io::Error;
+
+    fn queue(&mut self, frame: Frame<'_>) -> Result<(), Self::Error> {
+        let path = self.dir.join(format!("frame_{:04}.png", self.frame_count));
+        let mut pixels: Vec<TargetPixelType> = frame.rows().flatten().copied().collect();
+        if !(self.backlight && self.powered) {
+            pixels.fill(0);
+        } else if self.brightness < MAX_BRIGHTNESS {
+            for pixel in &mut pixels {
+                *pixel = dim(*pixel, self.brightness);
+            }
+        }
+        write_png(&path, &pixels, frame.size)?;
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    async fn wait_for_vblank(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl DisplayBackend for PngDisplay {
+    fn set_backlight(&mut self, on: bool) {
+        self.backlight = on;
+    }
+
+    fn backlight(&self) -> bool {
+        self.backlight
+    }
+
+    fn set_brightness(&mut self, level: u8) {
+        self.brightness = level;
+    }
+
+    fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    async fn power_on(&mut self) -> Result<(), Self::Error> {
+        self.powered = true;
+        self.backlight = true;
+        Ok(())
+    }
+
+    async fn power_off(&mut self) -> Result<(), Self::Error> {
+        self.powered = false;
+        self.backlight = false;
+        Ok(())
+    }
+
+    fn is_powered(&self) -> bool {
+        self.powered
+    }
+}
@@ -0,0 +1,154 @@
+use embedded_graphics::{pixelcolor::{Rgb565, Rgb888}, prelude::*};
+use game_and_watch_core::screenshot::ScreenshotFormat;
+use tinybmp::Bmp;
+
+// Reading back the screenshots the firmware sends over defmt-RTT. The log
+// looks like
+//
+//   screenshot begin qoi
+//   screenshot data [113, 111, 105, 102, ...]
+//   ...
+//   screenshot end 12345
+//
+// with whatever timestamps and levels defmt-print adds around it.
+
+/// Pull every complete screenshot out of a defmt log
+pub fn extract_from_log(log: &str) -> Result<Vec<(ScreenshotFormat, Vec<u8>)>, String> {
+    let mut shots = Vec::new();
+    let mut current: Option<(ScreenshotFormat, Vec<u8>)> = None;
+
+    for (n, line) in log.lines().enumerate() {
+        let Some(at) = line.find("screenshot ") else {
+            continue;
+        };
+        let rest = &line[at + "screenshot ".len()..];
+
+        if let Some(format) = rest.strip_prefix("begin ") {
+            let format = match format.trim() {
+                "bmp" => ScreenshotFormat::Bmp,
+                "qoi" => ScreenshotFormat::Qoi,
+                other => return Err(format!("line {}: unknown format {other}", n + 1)),
+            };
+            current = Some((format, Vec::new()));
+        } else if let Some(data) = rest.strip_prefix("data ") {
+            let Some((_, bytes)) = current.as_mut() else {
+                return Err(format!("line {}: data outside a screenshot", n + 1));
+            };
+            parse_bytes(data, bytes).map_err(|e| format!("line {}: {e}", n + 1))?;
+        } else if let Some(len) = rest.strip_prefix("end ") {
+            let Some((format, bytes)) = current.take() else {
+                return Err(format!("line {}: end without begin", n + 1));
+            };
+            let len: usize = len.trim().parse().map_err(|e| format!("line {}: {e}", n + 1))?;
+            if len != bytes.len() {
+                return Err(format!("line {}: expected {len} bytes, got {} (lost log lines?)", n + 1, bytes.len()));
+            }
+            shots.push((format, bytes));
+        }
+    }
+
+    Ok(shots)
+}
+
+/// Parse a defmt byte slice, `[1, 2, 0xff]`
+fn parse_bytes(text: &str, out: &mut Vec<u8>) -> Result<(), String> {
+    let start = text.find('[').ok_or("missing [")?;
+    let end = text.rfind(']').ok_or("missing ]")?;
+
+    for item in text[start + 1..end].split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let value = match item.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => item.parse(),
+        };
+        out.push(value.map_err(|e| format!("bad byte {item}: {e}"))?);
+    }
+    Ok(())
+}
+
+/// Guess the format of a raw dump from its magic bytes
+pub fn detect_format(data: &[u8]) -> Option<ScreenshotFormat> {
+    if data.starts_with(b"BM") {
+        Some(ScreenshotFormat::Bmp)
+    } else if data.starts_with(b"qoif") {
+        Some(ScreenshotFormat::Qoi)
+    } else {
+        None
+    }
+}
+
+/// Decode a screenshot to 8 bit RGB
+pub fn decode(format: ScreenshotFormat, data: &[u8]) -> Result<(Vec<u8>, Size), String> {
+    match format {
+        ScreenshotFormat::Bmp => {
+            let bmp = Bmp::<Rgb565>::from_slice(data).map_err(|e| format!("bad BMP: {e:?}"))?;
+            let mut rgb = Vec::new();
+            for Pixel(_, color) in bmp.pixels() {
+                let color: Rgb888 = color.into();
+                rgb.extend_from_slice(&[color.r(), color.g(), color.b()]);
+            }
+            Ok((rgb, bmp.size()))
+        }
+        ScreenshotFormat::Qoi => decode_qoi(data),
+    }
+}
+
+fn decode_qoi(data: &[u8]) -> Result<(Vec<u8>, Size), String> {
+    if data.len() < 14 + 8 || !data.starts_with(b"qoif") {
+        return Err("not a QOI image".into());
+    }
+    let width = u32::from_be_bytes(data[4..8].try_into().unwrap());
+    let height = u32::from_be_bytes(data[8..12].try_into().unwrap());
+    let pixels = width as usize * height as usize;
+
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0, 0, 0, 255u8];
+    let mut rgb = Vec::with_capacity(pixels * 3);
+    let mut run = 0;
+    let mut pos = 14;
+    let body = &data[..data.len() - 8];
+
+    let next = |pos: &mut usize| -> Result<u8, String> {
+        let byte = *body.get(*pos).ok_or("QOI data ends early")?;
+        *pos += 1;
+        Ok(byte)
+    };
+
+    while rgb.len() < pixels * 3 {
+        if run > 0 {
+            run -= 1;
+        } else {
+            let op = next(&mut pos)?;
+            match op {
+                0xfe => {
+                    px[0] = next(&mut pos)?;
+                    px[1] = next(&mut pos)?;
+                    px[2] = next(&mut pos)?;
+                }
+                0xff => {
+                    px = [next(&mut pos)?, next(&mut pos)?, next(&mut pos)?, next(&mut pos)?];
+                }
+                _ => match op >> 6 {
+                    0 => px = index[op as usize],
+                    1 => {
+                        px[0] = px[0].wrapping_add((op >> 4) & 3).wrapping_sub(2);
+                        px[1] = px[1].wrapping_add((op >> 2) & 3).wrapping_sub(2);
+                        px[2] = px[2].wrapping_add(op & 3).wrapping_sub(2);
+                    }
+                    2 => {
+                        let dg = (op & 0x3f).wrapping_sub(32);
+                        let b2 = next(&mut pos)?;
+                        px[0] = px[0].wrapping_add(dg).wrapping_add(b2 >> 4).wrapping_sub(8);
+                        px[1] = px[1].wrapping_add(dg);
+                        px[2] = px[2].wrapping_add(dg).wrapping_add(b2 & 0xf).wrapping_sub(8);
+                    }
+                    _ => run = op & 0x3f,
+                },
+            }
+            let [r, g, b, a] = px;
+            index[(r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64] = px;
+        }
+        rgb.extend_from_slice(&px[..3]);
+    }
+
+    Ok((rgb, Size::new(width, height)))
+}
@@ -0,0 +1,49 @@
+use game_and_watch_core::input::{ButtonClick, ButtonReading};
+
+// Button input for the host tools, given on the command line
+
+/// Held buttons, e.g. `right,down`
+pub fn parse_buttons(list: &str) -> Result<ButtonReading, String> {
+    let mut reading = ButtonReading::default();
+    for name in list.split(',').filter(|n| !n.is_empty()) {
+        let button = match name {
+            "left" => &mut reading.left,
+            "right" => &mut reading.right,
+            "up" => &mut reading.up,
+            "down" => &mut reading.down,
+            "a" => &mut reading.a,
+            "b" => &mut reading.b,
+            "time" => &mut reading.time,
+            "game" => &mut reading.game,
+            "pause" => &mut reading.pause,
+            "power" => &mut reading.power,
+            _ => return Err(format!("unknown button {name}")),
+        };
+        *button = true;
+    }
+    Ok(reading)
+}
+
+/// One frame of clicks per step, e.g. `down,down,a,-,left+a` where `-` is
+/// a frame without clicks and `+` clicks several buttons at once
+pub fn parse_clicks(script: &str) -> Result<Vec<ButtonClick>, String> {
+    script
+        .split(',')
+        .filter(|step| !step.is_empty())
+        .map(|step| {
+            let held = parse_buttons(&step.replace('+', ",").replace('-', ""))?;
+            Ok(ButtonClick {
+                left: held.left,
+                right: held.right,
+                up: held.up,
+                down: held.down,
+                a: held.a,
+                b: held.b,
+                time: held.time,
+                game: held.game,
+                pause: held.pause,
+                power: held.power,
+            })
+        })
+        .collect()
+}
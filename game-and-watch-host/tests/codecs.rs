@@ -0,0 +1,137 @@
+// Decodes the reference files in tests/codecs with the firmware's decoders
+// and puts our encoders through them. The references come from other
+// implementations, see tests/codecs/reference.py, which also rewrites them.
+
+use game_and_watch_core::{
+    adpcm::ImaAdpcm,
+    qoa::{Qoa, FRAME_LEN},
+    stream::{DecodeError, Decoder},
+};
+use game_and_watch_host::encode::{encode_ima_adpcm, encode_qoa, pcm_wav, read_samples, ADPCM_BLOCK_ALIGN};
+
+const RATE: u32 = 22_050;
+const REFERENCE_BLOCK_ALIGN: usize = 256;
+
+fn samples(raw: &[u8]) -> Vec<i16> {
+    raw.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect()
+}
+
+fn source() -> Vec<i16> {
+    samples(include_bytes!("codecs/source.raw"))
+}
+
+fn decode_all(decoder: &mut impl Decoder) -> Vec<i16> {
+    let mut out = vec![0; decoder.len() as usize];
+    decoder.seek(0);
+    // in uneven pieces, the way a stream asks for them
+    for piece in out.chunks_mut(100) {
+        decoder.decode(piece);
+    }
+    out
+}
+
+/// Seeking lands on the same samples as decoding from the start
+fn check_seeks(decoder: &mut impl Decoder, reference: &[i16], to: &[usize]) {
+    for &at in to {
+        let mut out = [0; 30];
+        let n = out.len().min(reference.len() - at);
+        decoder.seek(at as u32);
+        decoder.decode(&mut out[..n]);
+        assert_eq!(out[..n], reference[at..at + n], "after seeking to {at}");
+    }
+}
+
+/// Signal to noise ratio in dB
+fn snr(original: &[i16], decoded: &[i16]) -> f64 {
+    let (signal, noise) = original.iter().zip(decoded).fold((0f64, 0f64), |(s, n), (&a, &b)| {
+        let error = a as f64 - b as f64;
+        (s + a as f64 * a as f64, n + error * error)
+    });
+    10.0 * (signal / noise.max(1.0)).log10()
+}
+
+#[test]
+fn ima_adpcm_decodes_like_the_reference() {
+    let reference = samples(include_bytes!("codecs/ima_adpcm.raw"));
+    let mut adpcm = ImaAdpcm::from_wav(include_bytes!("codecs/ima_adpcm.wav")).unwrap();
+    assert_eq!(adpcm.sample_rate(), RATE);
+    assert_eq!(adpcm.len() as usize, reference.len());
+    assert_eq!(decode_all(&mut adpcm), reference);
+    // into a block, a block start and the short last block
+    check_seeks(&mut adpcm, &reference, &[7, 505, 1010 + 300, 5555, 5990]);
+}
+
+#[test]
+fn qoa_decodes_like_the_reference() {
+    let reference = samples(include_bytes!("codecs/qoa.raw"));
+    let mut qoa = Qoa::from_bytes(include_bytes!("codecs/qoa.qoa")).unwrap();
+    assert_eq!(qoa.sample_rate(), RATE);
+    assert_eq!(qoa.len() as usize, reference.len());
+    assert_eq!(decode_all(&mut qoa), reference);
+    // into a slice, across the frame boundary and into the short last frame
+    check_seeks(&mut qoa, &reference, &[13, FRAME_LEN - 10, FRAME_LEN, FRAME_LEN + 21, 5990]);
+}
+
+#[test]
+fn ima_adpcm_encodes_like_the_reference() {
+    // the IMA encoder has no choices to make, so it matches bit for bit
+    let wav = encode_ima_adpcm(&source(), RATE, REFERENCE_BLOCK_ALIGN);
+    let mut adpcm = ImaAdpcm::from_wav(&wav).unwrap();
+    assert_eq!(decode_all(&mut adpcm), samples(include_bytes!("codecs/ima_adpcm.raw")));
+}
+
+#[test]
+fn qoa_encodes_as_well_as_the_reference() {
+    // the reference tries the scale factors in another order, so ties can
+    // go the other way, but it shouldn't sound worse
+    let source = source();
+    let qoa = encode_qoa(&source, RATE);
+    assert_eq!(qoa.len(), include_bytes!("codecs/qoa.qoa").len());
+    let decoded = decode_all(&mut Qoa::from_bytes(&qoa).unwrap());
+    let reference = samples(include_bytes!("codecs/qoa.raw"));
+    assert!(snr(&source, &decoded) >= snr(&source, &reference) - 0.1);
+}
+
+#[test]
+fn round_trips_at_any_length() {
+    let source = source();
+    let per_block = game_and_watch_core::adpcm::samples_per_block(ADPCM_BLOCK_ALIGN);
+    for len in [1, 2, 3, per_block - 1, per_block, per_block + 1, per_block + 2, source.len()] {
+        let input = &source[..len];
+        let wav = encode_ima_adpcm(input, RATE, ADPCM_BLOCK_ALIGN);
+        let mut adpcm = ImaAdpcm::from_wav(&wav).unwrap();
+        assert_eq!(adpcm.len() as usize, len);
+        let decoded = decode_all(&mut adpcm);
+        // every block starts on the exact sample
+        assert_eq!(decoded[0], input[0]);
+        assert!(len < 1000 || snr(input, &decoded) > 20.0, "{len} samples");
+    }
+
+    for len in [1, 19, 20, 21, FRAME_LEN - 1, FRAME_LEN, FRAME_LEN + 1, source.len()] {
+        let input = &source[..len];
+        let encoded = encode_qoa(input, RATE);
+        let mut qoa = Qoa::from_bytes(&encoded).unwrap();
+        assert_eq!(qoa.len() as usize, len);
+        let decoded = decode_all(&mut qoa);
+        assert!(len < 1000 || snr(input, &decoded) > 20.0, "{len} samples");
+    }
+}
+
+#[test]
+fn truncated_files_are_refused() {
+    let source = source();
+    let wav = encode_ima_adpcm(&source, RATE, ADPCM_BLOCK_ALIGN);
+    // the last byte is padding
+    assert_eq!(ImaAdpcm::from_wav(&wav[..wav.len() - 2]).err(), Some(DecodeError::Truncated));
+    let qoa = encode_qoa(&source, RATE);
+    assert_eq!(Qoa::from_bytes(&qoa[..qoa.len() - 1]).err(), Some(DecodeError::Truncated));
+}
+
+#[test]
+fn pcm_wav_reads_back() {
+    let source = source();
+    assert_eq!(read_samples(&pcm_wav(&source, RATE), 0), Ok((source.clone(), RATE)));
+    // raw input takes the given rate
+    let raw = include_bytes!("codecs/source.raw");
+    assert_eq!(read_samples(raw, 8_000), Ok((source, 8_000)));
+}
@@ -0,0 +1,201 @@
+#!/usr/bin/env python3
+# Writes the reference files the codecs test decodes, from a made up test
+# signal. IMA ADPCM comes from Python's audioop (so Python 3.12 or older),
+# QOA from a straight port of the encoder and decoder in the reference
+# qoa.h (https://github.com/phoboslab/qoa). Neither shares code with ours.
+#
+#   source.raw      the signal, mono s16le at 22050 Hz
+#   ima_adpcm.wav   encoded by audioop, 256 byte blocks
+#   ima_adpcm.raw   what audioop decodes it to
+#   qoa.qoa         encoded by the qoa.h port
+#   qoa.raw         what the qoa.h port decodes it to
+
+import audioop
+import math
+import struct
+from pathlib import Path
+
+RATE = 22050
+LEN = 6000
+BLOCK_ALIGN = 256
+OUT = Path(__file__).parent
+
+
+def signal():
+    # a sweep, a loud clipped tone, noise and silence, for every step size
+    seed = 1
+    samples = []
+    for i in range(LEN):
+        t = i / RATE
+        if i < 2000:
+            s = 12000 * math.sin(2 * math.pi * (100 + 2000 * t) * t)
+        elif i < 3000:
+            s = 60000 * math.sin(2 * math.pi * 330 * t)
+        elif i < 4500:
+            seed = (seed * 1103515245 + 12345) & 0x7FFFFFFF
+            s = (seed >> 15) % 16384 - 8192
+        else:
+            s = 0
+        samples.append(max(-32768, min(32767, round(s))))
+    return samples
+
+
+def pcm(samples):
+    return struct.pack(f"<{len(samples)}h", *samples)
+
+
+def riff(chunks):
+    body = b"WAVE"
+    for id, chunk in chunks:
+        body += id + struct.pack("<I", len(chunk)) + chunk + b"\0" * (len(chunk) & 1)
+    return b"RIFF" + struct.pack("<I", len(body)) + body
+
+
+def ima_adpcm(samples):
+    per_block = 1 + (BLOCK_ALIGN - 4) * 2
+    data = b""
+    decoded = []
+    index = 0
+    for at in range(0, len(samples), per_block):
+        block = samples[at : at + per_block]
+        header = struct.pack("<hBB", block[0], index, 0)
+        codes, (_, end_index) = audioop.lin2adpcm(pcm(block[1:]), 2, (block[0], index))
+        out, _ = audioop.adpcm2lin(codes, 2, (block[0], index))
+        decoded += [block[0]] + list(struct.unpack(f"<{len(block) - 1}h", out[: (len(block) - 1) * 2]))
+        # audioop puts the first sample in the high nibble, WAV in the low one
+        codes = bytes((b >> 4) | (b << 4 & 0xF0) for b in codes)
+        data += header + codes
+        index = end_index
+
+    fmt = struct.pack("<HHIIHHHH", 0x11, 1, RATE, RATE * BLOCK_ALIGN // per_block, BLOCK_ALIGN, 4, 2, per_block)
+    return riff([(b"fmt ", fmt), (b"fact", struct.pack("<I", len(samples))), (b"data", data)]), decoded
+
+
+# qoa.h
+SLICE_LEN = 20
+FRAME_LEN = SLICE_LEN * 256
+SCALEFACTORS = [1, 7, 21, 45, 84, 138, 211, 304, 421, 562, 731, 928, 1157, 1419, 1715, 2048]
+RECIPROCALS = [((1 << 16) + sf - 1) // sf for sf in SCALEFACTORS]
+QUANT = [7, 7, 7, 5, 5, 3, 3, 1, 0, 0, 2, 2, 4, 4, 6, 6, 6]
+DEQUANT = [[s * int(math.floor(sf * q + 0.5)) for q in (0.75, 2.5, 4.5, 7) for s in (1, -1)] for sf in SCALEFACTORS]
+
+
+def clamp(v, low, high):
+    return max(low, min(high, v))
+
+
+def sign(v):
+    return (v > 0) - (v < 0)
+
+
+def int32(v):
+    return (v + 2**31) % 2**32 - 2**31
+
+
+class Lms:
+    def __init__(self, history, weights):
+        self.history = list(history)
+        self.weights = list(weights)
+
+    def copy(self):
+        return Lms(self.history, self.weights)
+
+    def predict(self):
+        return int32(sum(w * h for w, h in zip(self.weights, self.history))) >> 13
+
+    def update(self, sample, residual):
+        delta = residual >> 4
+        self.weights = [w - delta if h < 0 else w + delta for w, h in zip(self.weights, self.history)]
+        self.history = self.history[1:] + [sample]
+
+
+def qoa_div(v, scalefactor):
+    n = (v * RECIPROCALS[scalefactor] + (1 << 15)) >> 16
+    return n + sign(v) - sign(n)
+
+
+def packed(values):
+    word = 0
+    for v in values:
+        word = word << 16 | (v & 0xFFFF)
+    return struct.pack(">Q", word)
+
+
+def qoa_encode(samples):
+    out = b"qoaf" + struct.pack(">I", len(samples))
+    lms = Lms([0, 0, 0, 0], [0, 0, -(1 << 13), 1 << 14])
+    prev_scalefactor = 0
+    for at in range(0, len(samples), FRAME_LEN):
+        frame = samples[at : at + FRAME_LEN]
+        size = 8 + 16 + (len(frame) + SLICE_LEN - 1) // SLICE_LEN * 8
+        out += struct.pack(">Q", 1 << 56 | RATE << 32 | len(frame) << 16 | size)
+        out += packed(lms.history) + packed(lms.weights)
+
+        for start in range(0, len(frame), SLICE_LEN):
+            part = frame[start : start + SLICE_LEN]
+            best_rank = 2**64 - 1
+            for sfi in range(16):
+                scalefactor = (sfi + prev_scalefactor) % 16
+                trial = lms.copy()
+                slice = scalefactor
+                rank = 0
+                for sample in part:
+                    predicted = trial.predict()
+                    scaled = clamp(qoa_div(sample - predicted, scalefactor), -8, 8)
+                    quantized = QUANT[scaled + 8]
+                    dequantized = DEQUANT[scalefactor][quantized]
+                    reconstructed = clamp(predicted + dequantized, -32768, 32767)
+                    penalty = max((sum(w * w for w in trial.weights) >> 18) - 0x8FF, 0)
+                    error = sample - reconstructed
+                    rank += error * error + penalty * penalty
+                    if rank > best_rank:
+                        break
+                    trial.update(reconstructed, dequantized)
+                    slice = slice << 3 | quantized
+                if rank < best_rank:
+                    best_rank, best_slice, best_lms, best_scalefactor = rank, slice, trial, scalefactor
+            prev_scalefactor = best_scalefactor
+            lms = best_lms
+            out += struct.pack(">Q", best_slice << (SLICE_LEN - len(part)) * 3)
+    return out
+
+
+def qoa_decode(data):
+    (total,) = struct.unpack(">I", data[4:8])
+    at = 8
+    decoded = []
+    while len(decoded) < total:
+        (header,) = struct.unpack(">Q", data[at : at + 8])
+        samples = header >> 16 & 0xFFFF
+        history = [v for v in struct.unpack(">4h", data[at + 8 : at + 16])]
+        weights = [v for v in struct.unpack(">4h", data[at + 16 : at + 24])]
+        lms = Lms(history, weights)
+        at += 24
+        for start in range(0, samples, SLICE_LEN):
+            (slice,) = struct.unpack(">Q", data[at : at + 8])
+            at += 8
+            scalefactor = slice >> 60
+            for i in range(min(SLICE_LEN, samples - start)):
+                quantized = slice >> (57 - 3 * i) & 7
+                dequantized = DEQUANT[scalefactor][quantized]
+                reconstructed = clamp(lms.predict() + dequantized, -32768, 32767)
+                lms.update(reconstructed, dequantized)
+                decoded.append(reconstructed)
+    return decoded
+
+
+def main():
+    samples = signal()
+    (OUT / "source.raw").write_bytes(pcm(samples))
+
+    wav, decoded = ima_adpcm(samples)
+    (OUT / "ima_adpcm.wav").write_bytes(wav)
+    (OUT / "ima_adpcm.raw").write_bytes(pcm(decoded))
+
+    qoa = qoa_encode(samples)
+    (OUT / "qoa.qoa").write_bytes(qoa)
+    (OUT / "qoa.raw").write_bytes(pcm(qoa_decode(qoa)))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,80 @@
+// Runs the game loop headless like the sim bin and compares frames with
+// the reference PNGs in tests/golden. After an intended change to what the
+// game draws, look at the new frames and update the references with
+//
+//   UPDATE_GOLDEN=1 cargo test --test golden
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use embassy_futures::block_on;
+use embedded_graphics::prelude::*;
+use game_and_watch_core::{
+    frame_pacer::TargetFps,
+    framebuffer::{DoubleBuffer, TargetPixelType, HEIGHT, WIDTH},
+    game::{self, GameState},
+    input::ButtonClick,
+};
+use game_and_watch_host::{png_display::PngDisplay, script::parse_buttons};
+use tinybmp::Bmp;
+
+const FRAMES: usize = 48;
+/// Frames that have a reference
+const CHECKED: [usize; 4] = [0, 1, 24, 47];
+
+/// Size and RGB pixels of a PNG
+fn read_png(path: &Path) -> (u32, u32, Vec<u8>) {
+    let decoder = png::Decoder::new(File::open(path).unwrap_or_else(|e| panic!("{}: {e}", path.display())));
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    pixels.truncate(info.buffer_size());
+    (info.width, info.height, pixels)
+}
+
+fn check(name: &str, held: &str) {
+    let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden").join(name);
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name);
+    let mut display = PngDisplay::new(&out).unwrap();
+
+    let size = Size::new(WIDTH as u32, HEIGHT as u32);
+    let mut front: Vec<TargetPixelType> = vec![0; WIDTH * HEIGHT];
+    let mut back: Vec<TargetPixelType> = vec![0; WIDTH * HEIGHT];
+    let mut disp = DoubleBuffer::new([&mut front, &mut back], size);
+
+    let ferris = Bmp::from_slice(include_bytes!("../../game-and-watch-stm32/assets/ferris.bmp")).unwrap();
+    let mut gs = GameState::new();
+    let reading = parse_buttons(held).unwrap();
+
+    let period = TargetFps::Fps30.frame_period_us();
+    for frame in 0..FRAMES {
+        game::update(&mut gs, &mut display, reading, ButtonClick::default(), frame as u64 * period);
+        game::render(&mut gs, &mut disp, Some(&ferris)).unwrap();
+        block_on(disp.swap(&mut display)).unwrap();
+    }
+    assert_eq!(display.frame_count(), FRAMES);
+
+    for frame in CHECKED {
+        let file = format!("frame_{frame:04}.png");
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(&golden).unwrap();
+            std::fs::copy(out.join(&file), golden.join(&file)).unwrap();
+            continue;
+        }
+        let (width, height, pixels) = read_png(&out.join(&file));
+        let (golden_width, golden_height, golden_pixels) = read_png(&golden.join(&file));
+        assert_eq!((width, height), (golden_width, golden_height), "{name}/{file} size");
+        let differ = pixels.chunks(3).zip(golden_pixels.chunks(3)).filter(|(a, b)| a != b).count();
+        assert!(differ == 0, "{name}/{file}: {differ} pixels differ from the reference, see {}", out.display());
+    }
+}
+
+#[test]
+fn idle() {
+    check("idle", "");
+}
+
+#[test]
+fn walking() {
+    check("walking", "right,down");
+}
@@ -0,0 +1,179 @@
+// Drives the settings screen from the ui bin with click scripts and checks
+// what the widgets did: focus, values, the confirm dialog and the toast.
+
+use embedded_graphics::{mono_font::{ascii, MonoTextStyle}, pixelcolor::Rgb565, prelude::*};
+use game_and_watch_core::{
+    frame_pacer::TargetFps,
+    framebuffer::{DoubleBuffer, TargetPixelType, HEIGHT, WIDTH},
+    ui::{Theme, Ui, UiState},
+};
+use game_and_watch_host::script::parse_clicks;
+
+const TOAST_US: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Settings {
+    sound: bool,
+    volume: i32,
+    lives: i32,
+}
+
+const DEFAULTS: Settings = Settings { sound: true, volume: 5, lives: 3 };
+
+/// Widget indices in the order the screen lays them out
+const SOUND: usize = 0;
+const VOLUME: usize = 1;
+const LIVES: usize = 2;
+const RESET: usize = 3;
+
+struct Screen {
+    state: UiState,
+    settings: Settings,
+    confirm_reset: bool,
+    /// Answers of the reset dialog
+    answers: Vec<bool>,
+    backs: usize,
+    /// Time of the last frame
+    now_us: u64,
+}
+
+fn run(script: &str) -> Screen {
+    let size = Size::new(WIDTH as u32, HEIGHT as u32);
+    let mut front: Vec<TargetPixelType> = vec![0; WIDTH * HEIGHT];
+    let mut back: Vec<TargetPixelType> = vec![0; WIDTH * HEIGHT];
+    let mut disp = DoubleBuffer::new([&mut front, &mut back], size);
+
+    let theme = Theme {
+        text_style: MonoTextStyle::new(&ascii::FONT_9X18, Rgb565::WHITE),
+        text: Rgb565::WHITE,
+        background: Rgb565::new(2, 4, 8),
+        focus_text: Rgb565::BLACK,
+        focus_background: Rgb565::YELLOW,
+        accent: Rgb565::GREEN,
+    };
+    let mut screen = Screen {
+        state: UiState::new(),
+        settings: DEFAULTS,
+        confirm_reset: false,
+        answers: Vec::new(),
+        backs: 0,
+        now_us: 0,
+    };
+
+    let period = TargetFps::Fps30.frame_period_us();
+    for (frame, clicks) in parse_clicks(script).unwrap().into_iter().enumerate() {
+        let now_us = frame as u64 * period;
+        screen.now_us = now_us;
+
+        let area = disp.bounding_box();
+        let settings = &mut screen.settings;
+        let mut ui = Ui::begin(&mut screen.state, clicks, now_us, area, &theme, &mut disp);
+        ui.label_row("Settings");
+        ui.toggle("Sound", &mut settings.sound);
+        ui.slider("Volume", &mut settings.volume, 0..=10, 1);
+        ui.spinner("Lives", &mut settings.lives, 1..=5);
+        if ui.button("Reset") {
+            screen.confirm_reset = true;
+        }
+        if ui.back() {
+            screen.backs += 1;
+        }
+        let mut reset = false;
+        if screen.confirm_reset {
+            if let Some(yes) = ui.confirm("Reset settings?") {
+                screen.answers.push(yes);
+                screen.confirm_reset = false;
+                reset = yes;
+            }
+        }
+        ui.end().unwrap();
+
+        if reset {
+            screen.settings = DEFAULTS;
+            screen.state.toast("Settings reset", now_us, TOAST_US);
+        }
+    }
+    screen
+}
+
+#[test]
+fn focus_wraps_around() {
+    // the first frame finds out how many widgets there are
+    assert_eq!(run("-,up").state.focus(), RESET);
+    assert_eq!(run("-,up,down").state.focus(), SOUND);
+    assert_eq!(run("-,down,down,down,down").state.focus(), SOUND);
+    assert_eq!(run("-,down,down").state.focus(), LIVES);
+}
+
+#[test]
+fn toggle_flips_with_a_left_and_right() {
+    assert!(!run("a").settings.sound);
+    assert!(run("a,right").settings.sound);
+    assert!(!run("a,right,left").settings.sound);
+    // only the focused widget reacts
+    assert_eq!(run("-,down,a").settings, DEFAULTS);
+}
+
+#[test]
+fn slider_steps_and_clamps() {
+    let screen = run("-,down,right,right");
+    assert_eq!(screen.state.focus(), VOLUME);
+    assert_eq!(screen.settings.volume, 7);
+    assert_eq!(run("-,down,right,right,right,right,right,right,right").settings.volume, 10);
+    assert_eq!(run("-,down,left,left,left,left,left,left,left").settings.volume, 0);
+    assert_eq!(run("-,down,left+right").settings.volume, 5);
+}
+
+#[test]
+fn spinner_wraps_around() {
+    assert_eq!(run("-,down,down,right").settings.lives, 4);
+    assert_eq!(run("-,down,down,right,right,right").settings.lives, 1);
+    assert_eq!(run("-,down,down,left,left,left").settings.lives, 5);
+}
+
+#[test]
+fn confirm_takes_the_input_until_answered() {
+    // open the dialog, then try to move and change things behind it
+    let screen = run("-,up,a,up,down,right,left");
+    assert!(screen.state.is_modal());
+    assert_eq!(screen.state.focus(), RESET);
+    assert_eq!(screen.settings, DEFAULTS);
+    assert!(screen.answers.is_empty());
+
+    // "No" is selected first, B cancels and isn't a back
+    let screen = run("-,down,right,up,up,a,b");
+    assert_eq!(screen.answers, [false]);
+    assert!(!screen.state.is_modal());
+    assert_eq!(screen.backs, 0);
+    assert_eq!(screen.settings.volume, 6);
+
+    let screen = run("-,down,right,up,up,a,a");
+    assert_eq!(screen.answers, [false]);
+    assert_eq!(screen.settings.volume, 6);
+
+    let screen = run("-,down,right,up,up,a,left,a");
+    assert_eq!(screen.answers, [true]);
+    assert_eq!(screen.settings, DEFAULTS);
+
+    // the input is back once it's closed
+    let screen = run("-,up,a,b,b,up");
+    assert_eq!(screen.backs, 1);
+    assert_eq!(screen.state.focus(), LIVES);
+}
+
+#[test]
+fn toast_expires() {
+    let period = TargetFps::Fps30.frame_period_us();
+    let screen = run("-,up,a,left,a");
+    let shown = screen.now_us;
+    assert_eq!(screen.state.current_toast(shown), Some("Settings reset"));
+    assert_eq!(screen.state.current_toast(shown + TOAST_US - 1), Some("Settings reset"));
+    assert_eq!(screen.state.current_toast(shown + TOAST_US), None);
+
+    // and it goes away on its own as the frames go by
+    let frames = (TOAST_US / period) as usize + 1;
+    let screen = run(&format!("-,up,a,left,a{}", ",-".repeat(frames)));
+    assert_eq!(screen.state.current_toast(screen.now_us), None);
+    let screen = run(&format!("-,up,a,left,a{}", ",-".repeat(frames - 2)));
+    assert_eq!(screen.state.current_toast(screen.now_us), Some("Settings reset"));
+}
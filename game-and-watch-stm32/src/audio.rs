@@ -0,0 +1,155 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::{info, warn};
+use embassy_stm32::{
+    gpio::Output,
+    pac,
+    peripherals::{DMA1_CH1, PE4, PE5, PE6, SAI1},
+    sai::{self, ClockStrobe, Config as SaiConfig, DataSize, FrameSyncOffset, MasterClockDivider, Mode, Sai, SlotSize, StereoMono, TxRx},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+
+use game_and_watch_core::{
+    audio::{apply_volume, AudioSource, SampleRate, MAX_VOLUME},
+    mixer::Mixer,
+};
+
+// Speaker output on SAI1 block A as a master transmitter: SCK on PE5, FS on
+// PE4, SD on PE6, with the amplifier enabled by PE3. The SAI reads a
+// circular DMA buffer, audio_task keeps refilling whichever half it has
+// finished with.
+
+/// Samples per half of the DMA buffer, 10ms at 48 kHz
+pub const CHUNK: usize = 480;
+pub const DMA_LEN: usize = CHUNK * 2;
+
+static MASTER_VOLUME: AtomicU8 = AtomicU8::new(MAX_VOLUME);
+
+/// Sounds playing at once
+pub const VOICES: usize = 8;
+
+/// What audio_task plays, start sounds here
+pub static MIXER: Mutex<CriticalSectionRawMutex, Mixer<'static, VOICES>> = Mutex::new(Mixer::new());
+
+pub fn set_volume(volume: u8) {
+    MASTER_VOLUME.store(volume, Ordering::Relaxed);
+}
+
+pub fn volume() -> u8 {
+    MASTER_VOLUME.load(Ordering::Relaxed)
+}
+
+// RCC registers for PLL2, which only clocks the SAI
+const RCC_CR_PLL2ON: u32 = 1 << 26;
+const RCC_CR_PLL2RDY: u32 = 1 << 27;
+const RCC_PLLCFGR_PLL2FRACEN: u32 = 1 << 4;
+const PLL2DIVR_N_MASK: u32 = 0x1ff;
+const PLL2FRACR_SHIFT: u32 = 3;
+
+/// Set up PLL2 for `rate` and return the SAI master clock divider that
+/// goes with it. The reference is HSI / 25 = 2.56 MHz and PLL2 P divides
+/// the VCO by 5, so
+///
+///   48 kHz and 32 kHz: N = 192, 98.304 MHz, MCKDIV 8 and 12
+///   44.1 kHz: N = 176 + 3277 / 8192, 90.3168 MHz, MCKDIV 8
+///
+/// with the SAI running at 256 * fs after MCKDIV.
+fn configure_clock(rate: SampleRate) -> MasterClockDivider {
+    let (n, fracn, divider) = match rate {
+        SampleRate::Hz48000 => (192, 0, MasterClockDivider::Div8),
+        SampleRate::Hz32000 => (192, 0, MasterClockDivider::Div12),
+        SampleRate::Hz44100 => (176, 3277, MasterClockDivider::Div8),
+    };
+
+    let rcc = pac::RCC;
+    rcc.cr().modify(|w| w.0 &= !RCC_CR_PLL2ON);
+    while rcc.cr().read().0 & RCC_CR_PLL2RDY != 0 {}
+
+    rcc.plldivr(1).modify(|w| w.0 = (w.0 & !PLL2DIVR_N_MASK) | (n - 1));
+    rcc.pllcfgr().modify(|w| w.0 &= !RCC_PLLCFGR_PLL2FRACEN);
+    rcc.pllfracr(1).write(|w| w.0 = fracn << PLL2FRACR_SHIFT);
+    if fracn != 0 {
+        rcc.pllcfgr().modify(|w| w.0 |= RCC_PLLCFGR_PLL2FRACEN);
+    }
+
+    rcc.cr().modify(|w| w.0 |= RCC_CR_PLL2ON);
+    while rcc.cr().read().0 & RCC_CR_PLL2RDY == 0 {}
+
+    divider
+}
+
+pub struct Audio<'d> {
+    sai: Sai<'d, SAI1, u16>,
+    amp: Output<'d>,
+    rate: SampleRate,
+}
+
+impl<'d> Audio<'d> {
+    pub fn new(
+        sai1: SAI1,
+        sck: PE5,
+        fs: PE4,
+        sd: PE6,
+        dma: DMA1_CH1,
+        dma_buf: &'d mut [u16],
+        amp: Output<'d>,
+        rate: SampleRate,
+    ) -> Self {
+        let mut config = SaiConfig::default();
+        config.mode = Mode::Master;
+        config.tx_rx = TxRx::Transmitter;
+        config.data_size = DataSize::Data16;
+        config.slot_size = SlotSize::DataSize;
+        // two 16 bit slots a frame, with the same sample in both
+        config.frame_length = 32;
+        config.frame_sync_active_level_length = sai::word::U7(16);
+        config.frame_sync_offset = FrameSyncOffset::BeforeFirstBit;
+        config.stereo_mono = StereoMono::Mono;
+        config.clock_strobe = ClockStrobe::Falling;
+        config.master_clock_divider = configure_clock(rate);
+
+        let (sub_block_a, _) = sai::split_subblocks(sai1);
+        let sai = Sai::new_asynchronous(sub_block_a, sck, sd, fs, dma, dma_buf, config);
+
+        info!("SAI1 running at {} Hz", rate.hz());
+        Self { sai, amp, rate }
+    }
+
+    pub fn rate(&self) -> SampleRate {
+        self.rate
+    }
+
+    pub fn set_amp(&mut self, on: bool) {
+        if on {
+            self.amp.set_high();
+        } else {
+            self.amp.set_low();
+        }
+    }
+
+    /// Queue samples, waits while the DMA buffer is full
+    pub async fn write(&mut self, samples: &[u16]) -> Result<(), sai::Error> {
+        self.sai.write(samples).await
+    }
+}
+
+/// Keeps the SAI fed from MIXER
+#[embassy_executor::task]
+pub async fn audio_task(mut audio: Audio<'static>) {
+    let mut samples = [0i16; CHUNK];
+    let mut words = [0u16; CHUNK];
+
+    audio.set_amp(true);
+    loop {
+        MIXER.lock().await.fill(&mut samples);
+        apply_volume(&mut samples, volume());
+
+        for (w, s) in words.iter_mut().zip(&samples) {
+            *w = *s as u16;
+        }
+        if let Err(e) = audio.write(&words).await {
+            // we fell behind the DMA, the ring buffer starts over
+            warn!("audio underrun: {}", e);
+        }
+    }
+}
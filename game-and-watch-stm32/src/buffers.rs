@@ -0,0 +1,42 @@
+// Framebuffer allocation.
+//
+// The buffers are too big to be zeroed by the startup code in a reasonable
+// time and some of them live outside the main RAM region, so they go into
+// NOLOAD sections and get cleared when they're handed out.
+//
+// Sections:
+//   .uninit.framebuffers  AXI SRAM (the RAM region), plenty of space
+//   .ahbsram              AHB SRAM1/2 (the AHBRAM region), 128K, handy for
+//                         smaller buffers to take load off the AXI bus
+
+/// Hand out `$n` zeroed framebuffers of `$len` pixels of type `$ty`
+/// (TargetPixelType if left out) from a static placed in link section
+/// `$section`. Panics if the same call site runs twice.
+///
+/// Evaluates to `[&'static mut [$ty]; $n]`.
+macro_rules! framebuffers {
+    ($section:literal, $n:literal, $len:expr) => {
+        framebuffers!($section, $n, $len, TargetPixelType)
+    };
+    ($section:literal, $n:literal, $len:expr, $ty:ty) => {{
+        use core::mem::MaybeUninit;
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        #[link_section = $section]
+        static mut BUFFERS: MaybeUninit<[[$ty; $len]; $n]> = MaybeUninit::uninit();
+        static TAKEN: AtomicBool = AtomicBool::new(false);
+
+        assert!(!TAKEN.swap(true, Ordering::AcqRel), "framebuffers already taken");
+
+        // SAFETY: the flag above makes sure this is the only reference to
+        // BUFFERS, and all-zeros is a valid value for it
+        let buffers = unsafe {
+            let buffers = &mut *core::ptr::addr_of_mut!(BUFFERS);
+            core::ptr::write_bytes(buffers.as_mut_ptr(), 0, 1);
+            buffers.assume_init_mut()
+        };
+        buffers.each_mut().map(|b| b.as_mut_slice())
+    }};
+}
+
+pub(crate) use framebuffers;
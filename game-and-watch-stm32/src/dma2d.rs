@@ -0,0 +1,118 @@
+use embassy_stm32::{pac, peripherals};
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+use game_and_watch_core::{
+    blitter::{span, BlendBlitter, Blitter},
+    framebuffer::TargetPixelType,
+};
+
+// There's no embassy driver for the DMA2D (Chrom-ART) yet so this pokes
+// the registers directly. See RM0455 section 19.
+
+// CR.MODE
+const MODE_M2M: u32 = 0b000 << 16;
+const MODE_M2M_BLEND: u32 = 0b010 << 16;
+const MODE_R2M: u32 = 0b011 << 16;
+const CR_START: u32 = 1 << 0;
+
+// xPFCCR.CM
+const CM_ARGB8888: u32 = 0b0000;
+const CM_RGB565: u32 = 0b0010;
+
+// ISR / IFCR
+const TEIF: u32 = 1 << 0;
+const TCIF: u32 = 1 << 1;
+const CEIF: u32 = 1 << 5;
+
+/// Blitter backed by the DMA2D. Every operation waits for the transfer to
+/// finish so it can be used from the (blocking) DrawTarget methods.
+pub struct Dma2dBlitter {
+    _dma2d: peripherals::DMA2D,
+}
+
+impl Dma2dBlitter {
+    pub fn new(dma2d: peripherals::DMA2D) -> Self {
+        pac::RCC.ahb3enr().modify(|w| w.set_dma2den(true));
+        Self { _dma2d: dma2d }
+    }
+
+    /// Set up the output side and the transfer size
+    fn output(&mut self, dst: &mut [TargetPixelType], dst_width: usize, area: &Rectangle) {
+        let (start, len) = span(area, dst_width);
+        let address = dst[start..].as_mut_ptr() as u32;
+
+        pac::DMA2D.opfccr().write(|w| w.0 = CM_RGB565);
+        pac::DMA2D.omar().write(|w| w.0 = address);
+        pac::DMA2D.oor().write(|w| w.0 = (dst_width - len) as u32);
+        pac::DMA2D.nlr().write(|w| w.0 = ((len as u32) << 16) | area.size.height);
+    }
+
+    /// Start the transfer and wait for it
+    fn run(&mut self, mode: u32) {
+        pac::DMA2D.cr().write(|w| w.0 = mode | CR_START);
+
+        loop {
+            let isr = pac::DMA2D.isr().read().0;
+            if isr & (TEIF | CEIF) != 0 {
+                defmt::error!("DMA2D transfer error {=u32:x}", isr);
+                break;
+            }
+            if isr & TCIF != 0 {
+                break;
+            }
+        }
+
+        pac::DMA2D.ifcr().write(|w| w.0 = TEIF | TCIF | CEIF);
+    }
+}
+
+impl Blitter for Dma2dBlitter {
+    fn fill(&mut self, dst: &mut [TargetPixelType], dst_width: usize, area: &Rectangle, color: TargetPixelType) {
+        self.output(dst, dst_width, area);
+        pac::DMA2D.ocolr().write(|w| w.0 = color as u32);
+        self.run(MODE_R2M);
+    }
+
+    fn copy(
+        &mut self,
+        src: &[TargetPixelType],
+        src_width: usize,
+        src_origin: Point,
+        dst: &mut [TargetPixelType],
+        dst_width: usize,
+        area: &Rectangle,
+    ) {
+        let (src_start, len) = span(&Rectangle::new(src_origin, area.size), src_width);
+
+        pac::DMA2D.fgmar().write(|w| w.0 = src[src_start..].as_ptr() as u32);
+        pac::DMA2D.fgor().write(|w| w.0 = (src_width - len) as u32);
+        pac::DMA2D.fgpfccr().write(|w| w.0 = CM_RGB565);
+        self.output(dst, dst_width, area);
+        self.run(MODE_M2M);
+    }
+}
+
+impl BlendBlitter for Dma2dBlitter {
+    fn blend_argb8888(
+        &mut self,
+        src: &[u32],
+        src_width: usize,
+        src_origin: Point,
+        dst: &mut [TargetPixelType],
+        dst_width: usize,
+        area: &Rectangle,
+    ) {
+        let (src_start, len) = span(&Rectangle::new(src_origin, area.size), src_width);
+        let (dst_start, _) = span(area, dst_width);
+
+        // foreground is the image, background is what's already on screen
+        pac::DMA2D.fgmar().write(|w| w.0 = src[src_start..].as_ptr() as u32);
+        pac::DMA2D.fgor().write(|w| w.0 = (src_width - len) as u32);
+        pac::DMA2D.fgpfccr().write(|w| w.0 = CM_ARGB8888);
+        pac::DMA2D.bgmar().write(|w| w.0 = dst[dst_start..].as_ptr() as u32);
+        pac::DMA2D.bgor().write(|w| w.0 = (dst_width - len) as u32);
+        pac::DMA2D.bgpfccr().write(|w| w.0 = CM_RGB565);
+        self.output(dst, dst_width, area);
+        self.run(MODE_M2M_BLEND);
+    }
+}
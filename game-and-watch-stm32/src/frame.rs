@@ -0,0 +1,65 @@
+use embassy_time::Instant;
+
+use game_and_watch_core::{
+    blitter::Blitter,
+    frame_pacer::{FramePacer, FrameStats, RefreshClock, TargetFps},
+    framebuffer::{FrameSink, PixelStorage, SwapChain},
+};
+
+/// A [`FrameSink`] that can wait for the display's next vertical blank
+/// whether or not a frame is queued
+#[allow(async_fn_in_trait)]
+pub trait Vsync<P>: FrameSink<P> {
+    async fn next_vblank(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Presents frames at a steady rate.
+///
+/// Instead of sleeping on a timer until the frame's slot this sleeps on the
+/// display's vblanks, one refresh at a time, and queues the frame on the
+/// last vblank before its slot so the flip lands on it. The LTDC only
+/// latches the new address on its reload event during vertical blanking
+/// and `swap` waits for that, so the flip itself is tear free.
+pub struct FrameScheduler {
+    pacer: FramePacer,
+    refresh: RefreshClock,
+}
+
+impl FrameScheduler {
+    pub fn new(target: TargetFps) -> Self {
+        Self {
+            pacer: FramePacer::new(target),
+            // corrected from the vblanks once they come in
+            refresh: RefreshClock::new(TargetFps::Fps60.frame_period_us()),
+        }
+    }
+
+    pub fn set_target(&mut self, target: TargetFps) {
+        self.pacer.set_target(target);
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.pacer.stats()
+    }
+
+    /// The display's refresh period as measured from its vblanks
+    pub fn refresh_period_us(&self) -> u64 {
+        self.refresh.period_us()
+    }
+
+    pub async fn present<const N: usize, B: Blitter<P>, P: PixelStorage, S: Vsync<P>>(
+        &mut self,
+        disp: &mut SwapChain<'_, N, B, P>,
+        sink: &mut S,
+    ) -> Result<(), S::Error> {
+        let deadline = self.pacer.work_done(Instant::now().as_micros());
+        while self.refresh.too_early(deadline, Instant::now().as_micros()) {
+            sink.next_vblank().await?;
+            self.refresh.vblank(Instant::now().as_micros());
+        }
+
+        let result = disp.swap(sink).await;
+        self.pacer.presented(Instant::now().as_micros());
+        result
+    }
+}
@@ -5,7 +5,9 @@ use embassy_stm32::{
 
 use embassy_time::{Instant, Duration};
 
-use button_driver::{Button, ButtonConfig, Mode, State};
+use button_driver::{Button, ButtonConfig, Mode};
+
+pub use game_and_watch_core::input::{ButtonClick, ButtonReading};
 
 // FIXME Pin type safety
 
@@ -94,16 +96,16 @@ impl <'a> Buttons <'a> {
 
     pub fn raw_read_all(&mut self) -> ButtonReading {
         ButtonReading {
-            left: self.left.raw_state().clone(),
-            right: self.right.raw_state().clone(),
-            up: self.up.raw_state().clone(),
-            down: self.down.raw_state().clone(),
-            a: self.a.raw_state().clone(),
-            b: self.b.raw_state().clone(),
-            game: self.game.raw_state().clone(),
-            time: self.time.raw_state().clone(),
-            pause: self.pause.raw_state().clone(),
-            power: self.power.raw_state().clone(),
+            left: self.left.raw_state().is_held(),
+            right: self.right.raw_state().is_held(),
+            up: self.up.raw_state().is_held(),
+            down: self.down.raw_state().is_held(),
+            a: self.a.raw_state().is_held(),
+            b: self.b.raw_state().is_held(),
+            game: self.game.raw_state().is_held(),
+            time: self.time.raw_state().is_held(),
+            pause: self.pause.raw_state().is_held(),
+            power: self.power.raw_state().is_held(),
         }
     }
 
@@ -151,31 +153,3 @@ impl<'a> From<ButtonPins<'a>> for Buttons<'a> {
         }
     }
 }
-
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub struct ButtonReading {
-    pub left: State<Instant>,
-    pub right: State<Instant>,
-    pub up: State<Instant>,
-    pub down:  State<Instant>,
-    pub a: State<Instant>,
-    pub b: State<Instant>,
-    pub time: State<Instant>,
-    pub game:  State<Instant>,
-    pub pause: State<Instant>,
-    pub power: State<Instant>,
-}
-
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub struct ButtonClick {
-    pub left: bool,
-    pub right: bool,
-    pub up: bool,
-    pub down: bool,
-    pub a: bool,
-    pub b: bool,
-    pub time: bool,
-    pub game: bool,
-    pub pause: bool,
-    pub power: bool,
-}
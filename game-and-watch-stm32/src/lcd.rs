@@ -1,37 +1,117 @@
-pub const WIDTH: usize = 320;
-pub const HEIGHT: usize = 240;
+use core::marker::PhantomData;
 
-use embassy_stm32::{gpio::{Output}, spi::{self, Spi}, mode::Blocking,
+use embassy_stm32::{gpio::{Output}, spi::{self, Spi}, mode::Async, pac,
     ltdc::{self, Ltdc, LtdcConfiguration, LtdcLayerConfig, PolarityActive, PolarityEdge},
+    peripherals::TIM3,
+    timer::{simple_pwm::SimplePwm, Channel},
 };
 use embassy_time::{Timer};
-use embedded_graphics::draw_target::DrawTarget;
+use crate::frame::Vsync;
+
 use embedded_graphics::prelude::*;
-use embedded_graphics::pixelcolor::Rgb565;
 
+use game_and_watch_core::{
+    backlight::MAX_BRIGHTNESS,
+    display::DisplayBackend,
+    framebuffer::{Frame, FrameSink, TargetPixelType, WIDTH, HEIGHT},
+    palette::Palette,
+    panel::{Command, Panel, PanelBus, PowerError, PowerState, PowerStep, SequenceError},
+};
+
+// CS timing. There's no datasheet for the panel, so these aren't from one:
+// they're conservative guesses, generous next to the 10s of ns such
+// controllers usually need and far below the 2ms the reference code
+// (retro-go's gw_lcd.c) sleeps around every write. Known to work is only
+// that the stock init sequence gets through with them.
+const CS_SETUP_NS: u32 = 500;
+const CS_HOLD_NS: u32 = 500;
+const CS_IDLE_NS: u32 = 1000;
+
+// Power sequencing, again no datasheet so these follow the reference
+// code: rails settle, then a 15ms reset pulse with 1ms either side
+const RAILS_SETTLE_MS: u64 = 20;
+const RESET_SETUP_MS: u64 = 1;
+const RESET_PULSE_MS: u64 = 15;
+const RESET_RECOVERY_MS: u64 = 1;
+
+// PLL1_P, see the clock setup in main
+const SYSCLK_MHZ: u32 = 280;
+
+fn delay_ns(ns: u32) {
+    cortex_m::asm::delay(ns * SYSCLK_MHZ / 1000);
+}
+
+/// The panel's SPI with its chip select. Bytes go out by DMA, so other
+/// tasks keep running while a command sequence is sent.
+pub struct LcdSpi<'a> {
+    cs: Output<'a>,
+    spi: Spi<'a, Async>,
+}
+
+impl LcdSpi<'_> {
+    /// One CS framed transaction. The controller takes a command when CS
+    /// goes back up, so each one needs its own frame.
+    async fn frame(&mut self, bytes: &[u8; 2]) -> Result<(), spi::Error> {
+        self.cs.set_low();
+        delay_ns(CS_SETUP_NS);
+        let result = self.spi.write(bytes).await;
+        delay_ns(CS_HOLD_NS);
+        self.cs.set_high();
+        delay_ns(CS_IDLE_NS);
+        result
+    }
+}
+
+impl<'a> PanelBus for LcdSpi<'a> {
+    type Error = spi::Error;
+
+    async fn write(&mut self, bytes: [u8; 2]) -> Result<(), Self::Error> {
+        self.frame(&bytes).await
+    }
+
+    /// The whole sequence goes out in one call holding the SPI, with CS
+    /// toggled around each command and a DMA transfer for its two bytes.
+    /// It can't be one transfer for all of them: CS is a plain GPIO (PB12)
+    /// and the controller needs it to go up after every command.
+    async fn write_sequence(&mut self, commands: &[Command]) -> Result<(), SequenceError<Self::Error>> {
+        for (sent, bytes) in commands.iter().map(|c| c.bytes()).enumerate() {
+            self.frame(&bytes).await.map_err(|error| SequenceError { sent, error })?;
+        }
+        Ok(())
+    }
+}
+
+// The backlight current is split over three lines, each one adds about a
+// third of the full brightness. backlight1 and 2 are plain GPIOs and
+// backlight3 (PA6) is on TIM3_CH1, so a level is made up of whole lines
+// plus PWM on the third one for everything in between.
 pub struct Lcd<'a> {
     backlight1: Output<'a>,
     backlight2: Output<'a>,
-    backlight3: Output<'a>,
+    backlight3: SimplePwm<'a, TIM3>,
     disable_3v3: Output<'a>,
     enable_1v8: Output<'a>,
     reset: Output<'a>,
-    cs: Output<'a>,
-    spi:  Spi<'a, Blocking>,
+    panel: Panel<LcdSpi<'a>>,
     backlight_state: bool,
+    brightness: u8,
+    state: PowerState,
 }
 
 impl<'a> Lcd<'a> {
     pub fn new(
         backlight1: Output<'a>,
         backlight2: Output<'a>,
-        backlight3: Output<'a>,
+        mut backlight3: SimplePwm<'a, TIM3>,
         disable_3v3: Output<'a>,
         enable_1v8: Output<'a>,
         reset: Output<'a>,
         cs: Output<'a>,
-        spi: Spi<'a, Blocking>,
+        spi: Spi<'a, Async>,
     ) -> Self {
+        backlight3.set_duty(Channel::Ch1, 0);
+        backlight3.enable(Channel::Ch1);
+
         Self {
             backlight1,
             backlight2,
@@ -39,86 +119,175 @@ impl<'a> Lcd<'a> {
             disable_3v3,
             enable_1v8,
             reset,
-            cs,
-            spi,
+            panel: Panel::new(LcdSpi { cs, spi }),
             backlight_state: false,
+            brightness: MAX_BRIGHTNESS,
+            state: PowerState::Off,
         }
     }
 
-    async fn spi_write(&mut self, buf: &[u8]) -> Result<(), spi::Error> {
-        self.cs.set_low();
-        Timer::after_millis(2).await;
-        self.spi.blocking_write(buf)?;
-        Timer::after_millis(2).await;
-        self.cs.set_high();
-        Timer::after_millis(2).await;
-        Ok(())
+    /// Direct access to the panel commands
+    pub fn panel(&mut self) -> &mut Panel<LcdSpi<'a>> {
+        &mut self.panel
     }
 
-    async fn reset(&mut self) {
-        self.reset.set_high();
-        Timer::after_millis(1).await;
-        self.reset.set_low();
-        Timer::after_millis(15).await;
-        self.reset.set_high();
-        Timer::after_millis(1).await;
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    fn enter(&mut self, to: PowerState) {
+        self.state = to;
+        // the backlight only ever lights a configured panel
+        self.apply_backlight();
     }
 
+    /// Any state -> Off. Cuts the backlight first and leaves reset and CS
+    /// low so nothing back powers the controller through its inputs.
     pub fn power_off(&mut self) {
-        self.set_backlight_off();
+        self.enter(PowerStep::PowerOff.target());
+        self.panel.bus_mut().cs.set_low();
+        self.reset.set_low();
         self.disable_3v3.set_high();
         self.enable_1v8.set_low();
+        self.panel.mark_reset();
     }
 
-    pub fn power_on(&mut self) {
-        self.set_backlight_on();
+    /// Off -> RailsUp
+    pub async fn rails_up(&mut self) -> Result<(), PowerError<spi::Error>> {
+        let next = self.state.step(PowerStep::RailsUp)?;
         self.disable_3v3.set_low();
         self.enable_1v8.set_high();
+        Timer::after_millis(RAILS_SETTLE_MS).await;
+        self.panel.bus_mut().cs.set_high();
+        self.enter(next);
+        Ok(())
+    }
+
+    /// Pulse the reset line, from any state with the rails up
+    pub async fn reset(&mut self) -> Result<(), PowerError<spi::Error>> {
+        let next = self.state.step(PowerStep::Reset)?;
+        // unlike the other steps this one can't fail, and the panel stops
+        // being configured as soon as reset goes low
+        self.enter(next);
+        self.reset.set_high();
+        Timer::after_millis(RESET_SETUP_MS).await;
+        self.reset.set_low();
+        Timer::after_millis(RESET_PULSE_MS).await;
+        self.reset.set_high();
+        Timer::after_millis(RESET_RECOVERY_MS).await;
+        self.panel.mark_reset();
+        Ok(())
+    }
+
+    /// Reset -> Configured, sends the init sequence
+    pub async fn configure(&mut self) -> Result<(), PowerError<spi::Error>> {
+        let next = self.state.step(PowerStep::Configure)?;
+        self.panel.init().await?;
+        self.enter(next);
+        Ok(())
     }
 
+    pub fn is_backlight_on(&self) -> bool {
+        self.backlight_state
+    }
+
+    /// Power cycle and bring the panel all the way up to Configured
     pub async fn init (
         &mut self
-    ) -> Result<(), spi::Error> {
+    ) -> Result<(), PowerError<spi::Error>> {
         // reference impl https://github.com/ghidraninja/game-and-watch-base/blob/main/Core/Src/lcd.c 
         // other reference impl that makes a bit more sense 
         // https://github.com/kbeckmann/game-and-watch-retro-go/blob/main/Core/Src/gw_lcd.c
 
-        self.cs.set_high();
         self.power_off();
-        self.power_on();
-        Timer::after_millis(20).await;
-        self.reset().await;
-
-        self.spi_write(&[0x08, 0x80]).await?;
-        self.spi_write(&[0x6e, 0x80]).await?;
-        self.spi_write(&[0x80, 0x80]).await?;
-        self.spi_write(&[0x68, 0x00]).await?;
-        self.spi_write(&[0xd0, 0x00]).await?;
-        self.spi_write(&[0x1b, 0x00]).await?;
-        self.spi_write(&[0xe0, 0x00]).await?;
-        self.spi_write(&[0x6a, 0x80]).await?;
-        self.spi_write(&[0x80, 0x00]).await?;
-        self.spi_write(&[0x14, 0x80]).await?;
+        self.rails_up().await?;
+        self.reset().await?;
+        self.configure().await
+    }
 
+    /// Configured -> Sleeping. Blanks the panel, the LTDC can keep running.
+    /// There's no known sleep-in command, so this doesn't save much.
+    pub async fn sleep(&mut self) -> Result<(), PowerError<spi::Error>> {
+        let next = self.state.step(PowerStep::Sleep)?;
+        self.panel.sleep().await?;
+        self.enter(next);
         Ok(())
     }
 
+    /// Sleeping -> Configured
+    pub async fn wake(&mut self) -> Result<(), PowerError<spi::Error>> {
+        let next = self.state.step(PowerStep::Wake)?;
+        self.panel.wake().await?;
+        self.enter(next);
+        Ok(())
+    }
+
+    /// Get to a state that's safe to leave the panel in while the rest of
+    /// the device sleeps. A configured panel is blanked, one that's
+    /// halfway through powering up gets switched off.
+    pub async fn suspend(&mut self) -> Result<(), PowerError<spi::Error>> {
+        match self.state {
+            PowerState::Configured => self.sleep().await,
+            PowerState::Sleeping | PowerState::Off => Ok(()),
+            PowerState::RailsUp | PowerState::Reset => {
+                self.power_off();
+                Ok(())
+            }
+        }
+    }
+
+    /// Undo `suspend`, waking the panel or starting it from scratch
+    pub async fn resume(&mut self) -> Result<(), PowerError<spi::Error>> {
+        match self.state {
+            PowerState::Configured => Ok(()),
+            PowerState::Sleeping => match self.wake().await {
+                Ok(()) => Ok(()),
+                // the controller lost its state, start over
+                Err(_) => self.init().await,
+            },
+            _ => self.init().await,
+        }
+    }
+
     pub fn set_backlight_off(
         &mut self
     ) {
-        self.backlight1.set_low();
-        self.backlight2.set_low();
-        self.backlight3.set_low();
         self.backlight_state = false;
+        self.apply_backlight();
     }
 
     pub fn set_backlight_on(
         &mut self
     ) {
-        self.backlight1.set_high();
-        self.backlight2.set_high();
-        self.backlight3.set_high();
         self.backlight_state = true;
+        self.apply_backlight();
+    }
+
+    /// Brightness while the backlight is on, 0..=255
+    pub fn set_brightness(&mut self, level: u8) {
+        self.brightness = level;
+        self.apply_backlight();
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    fn apply_backlight(&mut self) {
+        let lit = self.backlight_state && self.state == PowerState::Configured;
+        let level = if lit { self.brightness as u32 } else { 0 };
+
+        // 0..=765 in thirds of the full range
+        let scaled = level * 3;
+        let lines = scaled / MAX_BRIGHTNESS as u32;
+        let partial = scaled % MAX_BRIGHTNESS as u32;
+
+        self.backlight1.set_level((lines >= 1).into());
+        self.backlight2.set_level((lines >= 2).into());
+
+        let max_duty = self.backlight3.get_max_duty() as u32;
+        let duty = if lines >= 3 { max_duty } else { max_duty * partial / MAX_BRIGHTNESS as u32 };
+        self.backlight3.set_duty(Channel::Ch1, duty as _);
     }
 
     pub fn toggle_backlight(
@@ -133,95 +302,210 @@ impl<'a> Lcd<'a> {
 }
 
 
-pub type TargetPixelType = u16;
+/// Pixel types a layer can scan out, each tied to its layer format
+pub trait LayerPixel: Copy {
+    fn is_format(format: ltdc::PixelFormat) -> bool;
+}
 
-// A simple double buffer
-pub struct DoubleBuffer<'a> {
-    buf0: &'a mut [TargetPixelType],
-    buf1: &'a mut [TargetPixelType],
-    is_buf0: bool,
-    layer_config: LtdcLayerConfig,
+impl LayerPixel for TargetPixelType {
+    fn is_format(format: ltdc::PixelFormat) -> bool {
+        matches!(format, ltdc::PixelFormat::RGB565)
+    }
 }
 
-impl<'a> DoubleBuffer<'a> {
-    pub fn new(
-        buf0: &'a mut [TargetPixelType],
-        buf1: &'a mut [TargetPixelType],
-        layer_config: LtdcLayerConfig,
-    ) -> Self {
+/// Palette indices, for a layer configured as ltdc::PixelFormat::L8 with
+/// its CLUT filled by load_clut
+impl LayerPixel for u8 {
+    fn is_format(format: ltdc::PixelFormat) -> bool {
+        matches!(format, ltdc::PixelFormat::L8)
+    }
+}
+
+// Presents frames of `P` pixels by pointing an LTDC layer at them
+pub struct LtdcFrameSink<'a, T: ltdc::Instance, P: LayerPixel = TargetPixelType> {
+    ltdc: Ltdc<'a, T>,
+    layer: ltdc::LtdcLayer,
+    queued: Option<usize>,
+    _pixel: PhantomData<P>,
+}
+
+// LTDC_SRCR
+const SRCR_IMR: u32 = 1 << 0;
+const SRCR_VBR: u32 = 1 << 1;
+
+// LTDC_ICR
+const ICR_CRRIF: u32 = 1 << 3;
+
+// LTDC_LxCR
+const CR_CLUTEN: u32 = 1 << 4;
+
+// LTDC_LxCFBLR, the line length field is the row size in bytes + 7
+const CFBLR_LINE_EXTRA: u32 = 7;
+
+fn layer_index(layer: ltdc::LtdcLayer) -> usize {
+    match layer {
+        ltdc::LtdcLayer::Layer1 => 0,
+        ltdc::LtdcLayer::Layer2 => 1,
+    }
+}
+
+impl<'a, T: ltdc::Instance, P: LayerPixel> LtdcFrameSink<'a, T, P> {
+    /// Panics if the layer isn't set up for `P` pixels
+    pub fn new(ltdc: Ltdc<'a, T>, layer_config: &LtdcLayerConfig) -> Self {
+        assert!(P::is_format(layer_config.pixel_format), "layer format doesn't match the frame pixels");
         Self {
-            buf0,
-            buf1,
-            is_buf0: true,
-            layer_config,
+            ltdc,
+            layer: layer_config.layer,
+            queued: None,
+            _pixel: PhantomData,
         }
     }
+}
 
-    pub fn current(&mut self) -> &mut [TargetPixelType] {
-        if self.is_buf0 {
-            self.buf0
-        } else {
-            self.buf1
+impl<'a, T: ltdc::Instance, P: LayerPixel> FrameSink<P> for LtdcFrameSink<'a, T, P> {
+    type Error = ltdc::Error;
+
+    fn queue(&mut self, frame: Frame<'_, P>) -> Result<(), Self::Error> {
+        // Ltdc::set_buffer always waits for the reload, so write the
+        // address and request the reload ourselves
+        let address = frame.pixels.as_ptr() as usize;
+        let bytes_per_pixel = core::mem::size_of::<P>() as u32;
+        let pitch = frame.stride as u32 * bytes_per_pixel;
+        let line_length = frame.size.width * bytes_per_pixel;
+
+        // scrolling is just a different start address, with the pitch
+        // skipping the part of each row that's off screen
+        let regs = pac::LTDC.layer(layer_index(self.layer));
+        regs.cfbar().write(|w| w.0 = address as u32);
+        regs.cfblr().write(|w| w.0 = (pitch << 16) | (line_length + CFBLR_LINE_EXTRA));
+        // a reload flag left over from an earlier flip would end the wait
+        // for this one straight away
+        pac::LTDC.icr().write(|w| w.0 = ICR_CRRIF);
+        pac::LTDC.srcr().write(|w| w.0 = SRCR_VBR);
+        self.queued = Some(address);
+        Ok(())
+    }
+
+    async fn wait_for_vblank(&mut self) -> Result<(), Self::Error> {
+        let Some(address) = self.queued.take() else {
+            return Ok(());
+        };
+
+        // VBR clears once the shadow registers have been reloaded
+        if pac::LTDC.srcr().read().0 & SRCR_VBR == 0 {
+            return Ok(());
         }
+
+        // Still pending, let the driver sleep on the reload interrupt. If the
+        // reload sneaks in first this costs an extra frame but never tears.
+        self.ltdc.set_buffer(self.layer, address as *const _).await
     }
+}
 
-    pub async fn swap<T: ltdc::Instance>(&mut self, ltdc: &mut Ltdc<'_, T>) -> Result<(), ltdc::Error> {
-        let buf = self.current();
-        let frame_buffer = buf.as_ptr();
-        self.is_buf0 = !self.is_buf0;
-        ltdc.set_buffer(self.layer_config.layer, frame_buffer as *const _).await
+impl<'a, T: ltdc::Instance, P: LayerPixel> Vsync<P> for LtdcFrameSink<'a, T, P> {
+    async fn next_vblank(&mut self) -> Result<(), Self::Error> {
+        // reload the address the layer already has, only for the reload
+        // interrupt that comes with it at the next vblank. If a frame is
+        // queued this is its flip.
+        let address = pac::LTDC.layer(layer_index(self.layer)).cfbar().read().0;
+        pac::LTDC.icr().write(|w| w.0 = ICR_CRRIF);
+        self.ltdc.set_buffer(self.layer, address as *const _).await
     }
+}
 
-    /// Clears the buffer
-    pub fn clear(&mut self) {
-        let buf = self.current();
-        let black = Rgb565::new(0, 0, 0).into_storage();
+/// The panel and the LTDC together, as seen by the game
+pub struct LcdDisplay<'a, T: ltdc::Instance, P: LayerPixel = TargetPixelType> {
+    lcd: Lcd<'a>,
+    frame_sink: LtdcFrameSink<'a, T, P>,
+}
 
-        for a in buf.iter_mut() {
-            *a = black; // solid black
+impl<'a, T: ltdc::Instance, P: LayerPixel> LcdDisplay<'a, T, P> {
+    pub fn new(lcd: Lcd<'a>, frame_sink: LtdcFrameSink<'a, T, P>) -> Self {
+        Self {
+            lcd,
+            frame_sink,
         }
     }
 }
 
-impl<'a> DrawTarget for DoubleBuffer<'a> {
-    type Color = Rgb565;
-    type Error = ();
+#[derive(Debug, defmt::Format)]
+pub enum LcdError {
+    Ltdc(ltdc::Error),
+    Power(PowerError<spi::Error>),
+}
 
-    /// Draw a pixel
-    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
-    where
-        I: IntoIterator<Item = Pixel<Self::Color>>,
-    {
-        let size = self.size();
-        let width = size.width as i32;
-        let height = size.height as i32;
-        let buf = self.current();
+impl<'a, T: ltdc::Instance, P: LayerPixel> FrameSink<P> for LcdDisplay<'a, T, P> {
+    type Error = LcdError;
 
-        for pixel in pixels {
-            let Pixel(point, color) = pixel;
+    fn queue(&mut self, frame: Frame<'_, P>) -> Result<(), Self::Error> {
+        self.frame_sink.queue(frame).map_err(LcdError::Ltdc)
+    }
 
-            if point.x >= 0 && point.y >= 0 && point.x < width && point.y < height {
-                let index = point.y * width + point.x;
-                let raw_color = color.into_storage();
-                buf[index as usize] = raw_color;
-            } else {
-                // Ignore invalid points
-                defmt::error!("Invalid address");
-            }
+    async fn wait_for_vblank(&mut self) -> Result<(), Self::Error> {
+        self.frame_sink.wait_for_vblank().await.map_err(LcdError::Ltdc)
+    }
+}
+
+impl<'a, T: ltdc::Instance, P: LayerPixel> Vsync<P> for LcdDisplay<'a, T, P> {
+    async fn next_vblank(&mut self) -> Result<(), Self::Error> {
+        self.frame_sink.next_vblank().await.map_err(LcdError::Ltdc)
+    }
+}
+
+impl<'a, T: ltdc::Instance, P: LayerPixel> DisplayBackend<P> for LcdDisplay<'a, T, P> {
+    fn set_backlight(&mut self, on: bool) {
+        if on {
+            self.lcd.set_backlight_on();
+        } else {
+            self.lcd.set_backlight_off();
         }
+    }
+
+    fn backlight(&self) -> bool {
+        self.lcd.is_backlight_on()
+    }
+
+    fn set_brightness(&mut self, level: u8) {
+        self.lcd.set_brightness(level);
+    }
 
+    fn brightness(&self) -> u8 {
+        self.lcd.brightness()
+    }
+
+    async fn power_on(&mut self) -> Result<(), LcdError> {
+        self.lcd.resume().await.map_err(LcdError::Power)?;
+        self.lcd.set_backlight_on();
         Ok(())
     }
+
+    async fn power_off(&mut self) -> Result<(), LcdError> {
+        self.lcd.suspend().await.map_err(LcdError::Power)
+    }
+
+    fn is_powered(&self) -> bool {
+        self.lcd.state() == PowerState::Configured
+    }
 }
 
-impl<'a> OriginDimensions for DoubleBuffer<'a> {
-    /// Return the size of the display
-    fn size(&self) -> Size {
-        Size::new(
-            (self.layer_config.window_x1 - self.layer_config.window_x0) as _,
-            (self.layer_config.window_y1 - self.layer_config.window_y0) as _,
-        )
+/// Load `palette` into the CLUT of `layer` and switch the lookup on. The
+/// CLUT isn't shadowed, so to avoid glitches call this right after a swap
+/// while the panel is still in vertical blanking.
+pub fn load_clut(layer: ltdc::LtdcLayer, palette: &Palette) {
+    let regs = pac::LTDC.layer(layer_index(layer));
+    for (i, c) in palette.colors().iter().enumerate() {
+        regs.clutwr().write(|w| w.0 = ((i as u32) << 24) | ((c.r() as u32) << 16) | ((c.g() as u32) << 8) | c.b() as u32);
     }
+    regs.cr().modify(|w| w.0 |= CR_CLUTEN);
+    pac::LTDC.srcr().write(|w| w.0 = SRCR_IMR);
+}
+
+/// Size of the area covered by a layer
+pub fn layer_size(layer_config: &LtdcLayerConfig) -> Size {
+    Size::new(
+        (layer_config.window_x1 - layer_config.window_x0) as _,
+        (layer_config.window_y1 - layer_config.window_y0) as _,
+    )
 }
 
 pub static LTDC_CONFIG: LtdcConfiguration  = LtdcConfiguration {
@@ -10,21 +10,52 @@ use input::*;
 mod spiflash;
 use spiflash::*;
 
+mod dma2d;
+use dma2d::*;
+
+mod frame;
+use frame::*;
+
+mod buffers;
+use buffers::framebuffers;
+
+mod overlay;
+use overlay::*;
+
+mod screenshot;
+
+mod audio;
+
 use embedded_graphics::{
     prelude::*,
-    image::Image, primitives::Rectangle, pixelcolor::Rgb565,
+    primitives::Rectangle, pixelcolor::Rgb565,
     mono_font::{ascii, MonoTextStyle},
     text::Text,
 };
 
+use game_and_watch_core::{
+    display::DisplayBackend,
+    frame_pacer::TargetFps,
+    framebuffer::{TripleBuffer, TargetPixelType, WIDTH, HEIGHT},
+    game::{self, GameState},
+    audio::SampleRate,
+    blitter::check_blitter,
+    mixer::VOICE_MAX_VOLUME,
+    stream::{LoopPoints, Pcm, Stream, StreamControl},
+    synth::{Adsr, Instrument, Waveform},
+    tracker::{pattern, Player, Row, Song},
+    overlay::{Argb4444, OverlayBuffer},
+    screenshot::ScreenshotFormat,
+};
+
 use mux::{Fmcsel, Persel};
 use tinybmp::Bmp;
 
 use embassy_stm32::{
-    bind_interrupts, flash::{self, Bank1Region, Flash}, gpio::{AfType, Flex, Input, Level, Output, OutputType, Pull, Speed}, ltdc::{self, Ltdc}, mode::Blocking, pac, peripherals, rcc::{mux::Saisel, SupplyConfig, *}, spi::{Config as SpiConfig, Spi}, time::mhz, Config, PeripheralRef
+    bind_interrupts, flash::{self, Bank1Region, Flash}, gpio::{AfType, Flex, Input, Level, Output, OutputType, Pull, Speed}, ltdc::{self, Ltdc}, pac, peripherals, rcc::{mux::Saisel, SupplyConfig, *}, spi::{Config as SpiConfig, Spi}, time::{khz, mhz}, timer::{low_level::CountingMode, simple_pwm::{PwmPin, SimplePwm}}, Config, PeripheralRef
 };
 
-use embassy_time::Timer;
+use embassy_time::{Instant, Timer};
 use embassy_executor::Spawner;
 use embassy_sync::{mutex::Mutex, blocking_mutex::raw::CriticalSectionRawMutex};
 
@@ -36,87 +67,53 @@ bind_interrupts!(struct Irqs {
     LTDC => ltdc::InterruptHandler<peripherals::LTDC>;
 });
 
-static mut FRONT_BUFFER: [TargetPixelType; WIDTH * HEIGHT] = [0u16; WIDTH * HEIGHT];
-static mut BACK_BUFFER: [TargetPixelType; WIDTH * HEIGHT] = [0u16; WIDTH * HEIGHT];
 static BUTTONS: Mutex<CriticalSectionRawMutex, Option<Buttons>> = Mutex::new(None);
 
 // this doesn't really need a mutex because it's only modified once but
 // it's better than making it static mut I suppose
 static FERRIS: Mutex<CriticalSectionRawMutex, Option<Bmp<Rgb565>>> = Mutex::new(None);
-static AUDIO_BUF: Mutex<CriticalSectionRawMutex, [u8; 44100]> = Mutex::new([0u8; 44100]);
 
 // Probe-rs fails to flash the extflash if I try this :(
 //#[used]
 //#[unsafe(link_section = "._extflash")]
 //static FLASH_DATA: [u8; 338598] = *include_bytes!("../assets/crab_rave.raw_s16le_pcm");
 
-struct GameState {
-    pub ferris_pos: Point,
-    pub button_reading: Option<ButtonReading>,
-    pub button_clicks: Option<ButtonClick>,
-}
-
-impl GameState {
-    pub fn new() -> Self
-    {
-        Self {
-            ferris_pos: Point::new(120, 125),
-            button_reading: None,
-            button_clicks: None,
-        }
-    }
-}
-
-async fn draw(gs: &GameState, display: &mut DoubleBuffer<'_>)
-{
-    display.clear();
-    display.fill_solid(&Rectangle::new(Point::new(0, 0), Size::new(320, 240)), RgbColor::RED).unwrap();
-    let text_style =
-        MonoTextStyle::new(&ascii::FONT_9X18, RgbColor::WHITE);
-    Text::new("Hello Rust!", Point::new(120, 100), text_style)
-        .draw(display)
-        .unwrap();
-
-    {
-        let ferris = FERRIS.lock().await;
-        if let Some(f) = *ferris {
-            let ferris_img = Image::new(&f, gs.ferris_pos);
-            ferris_img.draw(display).unwrap();
-        }
-    }
-}
+// Until then the clip has to be written to the start of the external
+// flash by hand, it's streamed from there through the memory mapping
+const CRAB_RAVE_LEN: usize = 338598;
+const CRAB_RAVE_RATE: SampleRate = SampleRate::Hz44100;
+
+static MUSIC: StreamControl = StreamControl::new();
+
+/// Played on every click of A, like the original's beeps
+const BEEP_INSTRUMENTS: [Instrument; 1] = [Instrument {
+    waveform: Waveform::Pulse { duty: 128 },
+    envelope: Adsr { attack_ms: 1, decay_ms: 80, sustain: 0, release_ms: 10 },
+    volume: 160,
+}];
+const BEEP_PATTERN: [Row; 2] = pattern([["A-5 0", "---", "---", "---"], ["E-6 0", "---", "---", "---"]]);
+static BEEP: Song = Song {
+    instruments: &BEEP_INSTRUMENTS,
+    patterns: &[&BEEP_PATTERN],
+    sequence: &[0],
+    loop_to: None,
+    row_ms: 40,
+};
 
-async fn update(gs: &mut GameState, lcd: &mut Lcd<'_>) {
+async fn update<B: DisplayBackend>(gs: &mut GameState, display: &mut B) {
+    let mut input = None;
     {
         // read input state
         // MUTEX HELD!
         let mut buttons = BUTTONS.lock().await;
         if let Some(b) = buttons.as_mut() {
-            gs.button_reading = Some(b.raw_read_all());
-            gs.button_clicks = Some(b.read_clicks());
+            input = Some((b.raw_read_all(), b.read_clicks()));
             b.reset_all();
         }
     }
 
-    if let Some(button_state) = gs.button_reading {
-        if button_state.left.is_held() {
-            gs.ferris_pos.x -= 1;
-        }
-        if button_state.right.is_held() {
-            gs.ferris_pos.x += 1;
-        }
-        if button_state.up.is_held() {
-            gs.ferris_pos.y -= 1;
-        }
-        if button_state.down.is_held() {
-            gs.ferris_pos.y +=1;
-        }
-    }
-
-    if let Some(clicks) = gs.button_clicks {
-        if clicks.power {
-            lcd.toggle_backlight();
-        }
+    if let Some((reading, clicks)) = input {
+        game::update(gs, display, reading, clicks, Instant::now().as_micros());
     }
 }
 
@@ -190,7 +187,15 @@ async fn main(spawner: Spawner) {
 
     let pa4 = Output::new(cp.PA4, Level::Low, Speed::Low);
     let pa5 = Output::new(cp.PA5, Level::Low, Speed::Low);
-    let pa6 = Output::new(cp.PA6, Level::Low, Speed::Low);
+    let pa6 = SimplePwm::new(
+        cp.TIM3,
+        Some(PwmPin::new_ch1(cp.PA6, OutputType::PushPull)),
+        None,
+        None,
+        None,
+        khz(20),
+        CountingMode::EdgeAlignedUp,
+    );
 
     let disable_3v3 = Output::new(cp.PD1, Level::High, Speed::Low);
     let enable_1v8  = Output::new(cp.PD4, Level::Low, Speed::Low);
@@ -199,11 +204,12 @@ async fn main(spawner: Spawner) {
     let mut spi_config = SpiConfig::default();
     spi_config.frequency = mhz(18);
 
-    let spi = Spi::<Blocking>::new_blocking_txonly(
+    let spi = Spi::new_txonly(
         cp.SPI2,
         cp.PB13,
         cp.PB15,
-        spi_config 
+        cp.DMA1_CH0,
+        spi_config
     );
 
     // initialize ltdc pins
@@ -256,6 +262,7 @@ async fn main(spawner: Spawner) {
     let mut lcd = Lcd::new(pa4, pa5, pa6, disable_3v3, enable_1v8, reset, cs, spi);
 
     lcd.init().await.unwrap();
+    lcd.set_backlight_on();
 
     let mut ltdc = Ltdc::new(
         cp.LTDC
@@ -265,10 +272,42 @@ async fn main(spawner: Spawner) {
 
     ltdc.init_layer(&LTDC_LAYER_CONFIG, None);
 
-    let mut disp = DoubleBuffer::new(
-        unsafe { FRONT_BUFFER.as_mut() } ,
-        unsafe { BACK_BUFFER.as_mut() } ,
-        LTDC_LAYER_CONFIG
+    // HUD bar across the top on the overlay layer
+    let hud_area = Rectangle::new(Point::zero(), Size::new(WIDTH as u32, 20));
+    let mut hud_overlay = Overlay::new(&mut ltdc, OverlayFormat::Argb4444, hud_area, framebuffers!(".ahbsram", 2, WIDTH * 20), None);
+    if let Some(buf) = hud_overlay.back() {
+        let mut hud = OverlayBuffer::new(buf, hud_area.size);
+        hud.clear(Argb4444::new(0x8, 0, 0, 0)).unwrap();
+        Text::new("GAME & WATCH", Point::new(4, 14), MonoTextStyle::new(&ascii::FONT_9X18, Argb4444::from(Rgb565::WHITE)))
+            .draw(&mut hud)
+            .unwrap();
+    }
+    hud_overlay.flip();
+    hud_overlay.set_alpha(0xc0);
+
+    let mut display: LcdDisplay<'_, _, TargetPixelType> = LcdDisplay::new(lcd, LtdcFrameSink::new(ltdc, &LTDC_LAYER_CONFIG));
+
+    // the DMA2D has to give the same pixels as the software blitter the
+    // host tools and tests use
+    let mut blitter = Dma2dBlitter::new(cp.DMA2D);
+    match check_blitter(&mut blitter) {
+        Ok(()) => info!("DMA2D matches the software blitter"),
+        Err(e) => error!("DMA2D doesn't match the software blitter: {}", e),
+    }
+
+    // RAM budget, the 1024K AXI SRAM region:
+    //   3 framebuffers of 320x240 RGB565      460,800 B (450K)
+    //   executor task arena (Cargo.toml)       524,288 B (512K)
+    //   everything else in .data/.bss and the stack share the last ~62K
+    // The stack grows down from the end of RAM towards these with nothing
+    // to stop it, so memory.x refuses to link with less than 32K left over.
+    // A fourth buffer or a bigger virtual size for scrolling won't fit
+    // without shrinking the arena. The overlays and the audio buffer are in
+    // AHB SRAM and don't count here.
+    let mut disp = TripleBuffer::with_blitter(
+        framebuffers!(".uninit.framebuffers", 3, WIDTH * HEIGHT),
+        layer_size(&LTDC_LAYER_CONFIG),
+        blitter
     );
 
     info!("Initialised Display...");
@@ -315,17 +354,63 @@ async fn main(spawner: Spawner) {
         debug!("First word of spiflash: {=u32:x}", core::ptr::read_volatile(0x90000000 as *const u32));
     }*/
 
+    // speaker
+    let [audio_buf] = framebuffers!(".ahbsram", 1, audio::DMA_LEN, u16);
+    let audio = audio::Audio::new(
+        cp.SAI1,
+        cp.PE5,
+        cp.PE4,
+        cp.PE6,
+        cp.DMA1_CH1,
+        audio_buf,
+        Output::new(cp.PE3, Level::Low, Speed::Low),
+        CRAB_RAVE_RATE,
+    );
+    let extflash = spiflash.into_memory_mapped();
+    {
+        let music = Stream::new(Pcm::new(&extflash[..CRAB_RAVE_LEN]))
+            .with_loop(LoopPoints { start: 0, end: u32::MAX, count: None })
+            .with_control(&MUSIC);
+        audio::MIXER.lock().await.play_stream(music, VOICE_MAX_VOLUME, 0);
+    }
+    spawner.spawn(audio::audio_task(audio)).unwrap();
+
     // Initialize state
     let mut gs = GameState::new();
+    gs.backlight.set_auto_dim(Some(30_000_000), 48);
 
     // start polling for input asynchronously
     spawner.spawn(input_task()).unwrap();
 
+    let mut frames = FrameScheduler::new(TargetFps::Fps30);
+
     // main loop
     loop { 
-        update(&mut gs, &mut lcd).await; 
-        draw(&gs, &mut disp).await;
-        disp.swap(&mut ltdc).await.unwrap();
+        update(&mut gs, &mut display).await; 
+        if gs.button_clicks.is_some_and(|c| c.a) {
+            audio::MIXER.lock().await.play_song(Player::new(&BEEP, CRAB_RAVE_RATE), VOICE_MAX_VOLUME, 0);
+        }
+        {
+            let ferris = FERRIS.lock().await;
+            game::render(&mut gs, &mut disp, ferris.as_ref()).unwrap();
+        }
+        frames.present(&mut disp, &mut display).await.unwrap();
+        hud_overlay.vblank();
+
+        if gs.screenshot_requested {
+            gs.screenshot_requested = false;
+            screenshot::dump_rtt(&disp.front(), ScreenshotFormat::Qoi);
+        }
+
+        let music = MUSIC.take_events();
+        if music.looped || music.ended {
+            debug!("music {} at sample {}", music, MUSIC.position());
+        }
+
+        let stats = frames.stats();
+        if stats.frames % 300 == 0 {
+            debug!("frame {}us busy {}% dropped {}", stats.frame_time_us, stats.cpu_busy_percent(), stats.dropped_frames);
+        }
    }
 }
 
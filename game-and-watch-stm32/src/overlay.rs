@@ -0,0 +1,193 @@
+use embassy_stm32::{
+    ltdc::{self, Ltdc, LtdcLayer, LtdcLayerConfig},
+    pac,
+};
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+use game_and_watch_core::{
+    framebuffer::{WIDTH, HEIGHT},
+    palette::Palette,
+};
+
+use crate::lcd::load_clut;
+
+// Layer2 as an overlay on top of the game layer, for HUDs, menus and
+// notifications. The LTDC blends it in hardware so the game framebuffer
+// never has to be touched.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum OverlayFormat {
+    /// 16 bit with per pixel alpha, see game_and_watch_core::overlay
+    Argb4444,
+    /// 8 bit palette index, needs a Palette
+    L8,
+}
+
+// LTDC_SRCR
+const SRCR_IMR: u32 = 1 << 0;
+const SRCR_VBR: u32 = 1 << 1;
+
+// LTDC_LxCR
+const CR_LEN: u32 = 1 << 0;
+
+const OVERLAY_LAYER: usize = 1;
+
+/// Layer2 with two buffers of `P`, one on screen and one to draw the next
+/// overlay picture in. The overlay owns both, so nothing can write to the
+/// one the LTDC is reading.
+pub struct Overlay<P: 'static> {
+    config: LtdcLayerConfig,
+    buffers: [&'static mut [P]; 2],
+    /// The buffer on screen, or about to be after a flip
+    front: usize,
+    /// A flip was asked for and no vertical blank has been seen since.
+    /// SRCR.VBR can't tell, the game layer sets it for its own flips too.
+    flip_pending: bool,
+}
+
+impl<P> Overlay<P> {
+    /// Set up Layer2 over `area`, showing the first buffer. The area has
+    /// to be on screen and each buffer must hold `area.size` pixels of
+    /// `format`. L8 overlays need a `palette` for their CLUT.
+    pub fn new<T: ltdc::Instance>(
+        ltdc: &mut Ltdc<'_, T>,
+        format: OverlayFormat,
+        area: Rectangle,
+        buffers: [&'static mut [P]; 2],
+        palette: Option<&Palette>,
+    ) -> Self {
+        assert!(
+            area.intersection(&screen()) == area && !area.is_zero_sized(),
+            "overlay must be on screen"
+        );
+        assert!(format != OverlayFormat::L8 || palette.is_some(), "L8 overlay needs a palette");
+        let bytes = area.size.width as usize * area.size.height as usize * bytes_per_pixel(format);
+        assert!(buffers.iter().all(|b| core::mem::size_of_val(*b) >= bytes), "overlay buffer too small");
+
+        let bottom_right = area.top_left + area.size;
+        let config = LtdcLayerConfig {
+            pixel_format: match format {
+                OverlayFormat::Argb4444 => ltdc::PixelFormat::ARGB4444,
+                OverlayFormat::L8 => ltdc::PixelFormat::L8,
+            },
+            layer: LtdcLayer::Layer2,
+            window_x0: area.top_left.x as u16,
+            window_x1: bottom_right.x as u16,
+            window_y0: area.top_left.y as u16,
+            window_y1: bottom_right.y as u16,
+        };
+
+        ltdc.init_layer(&config, None);
+        if let Some(palette) = palette {
+            load_clut(LtdcLayer::Layer2, palette);
+        }
+
+        pac::LTDC.layer(OVERLAY_LAYER).cfbar().write(|w| w.0 = buffers[0].as_ptr() as u32);
+        pac::LTDC.srcr().write(|w| w.0 = SRCR_IMR);
+
+        Self { config, buffers, front: 0, flip_pending: false }
+    }
+
+    pub fn size(&self) -> Size {
+        Size::new(
+            (self.config.window_x1 - self.config.window_x0) as u32,
+            (self.config.window_y1 - self.config.window_y0) as u32,
+        )
+    }
+
+    /// Replace the CLUT of an L8 overlay, see load_clut for when
+    pub fn set_palette(&mut self, palette: &Palette) {
+        load_clut(LtdcLayer::Layer2, palette);
+    }
+
+    /// The last flip is waiting for the next vertical blank. Until then
+    /// the LTDC may still be reading the back buffer.
+    pub fn is_flip_pending(&mut self) -> bool {
+        // with no reload waiting at all, ours has happened too. The game
+        // layer asks for one every frame though, so this alone could keep
+        // the overlay waiting for a long time.
+        if self.flip_pending && pac::LTDC.srcr().read().0 & SRCR_VBR == 0 {
+            self.flip_pending = false;
+        }
+        self.flip_pending
+    }
+
+    /// Tell the overlay a vertical blank has passed since the last flip,
+    /// e.g. once the game layer's swap has waited for one
+    pub fn vblank(&mut self) {
+        self.flip_pending = false;
+    }
+
+    /// The buffer that isn't on screen, to draw the next picture in.
+    /// `None` while the last flip is still pending.
+    pub fn back(&mut self) -> Option<&mut [P]> {
+        if self.is_flip_pending() {
+            return None;
+        }
+        Some(&mut *self.buffers[1 - self.front])
+    }
+
+    /// Show the back buffer from the next frame on. The old front buffer
+    /// becomes the back buffer once the flip is done.
+    pub fn flip(&mut self) {
+        self.front = 1 - self.front;
+        self.set_buffer(self.front);
+        self.flip_pending = true;
+    }
+
+    fn set_buffer(&mut self, index: usize) {
+        pac::LTDC.layer(OVERLAY_LAYER).cfbar().write(|w| w.0 = self.buffers[index].as_ptr() as u32);
+        pac::LTDC.srcr().write(|w| w.0 = SRCR_VBR);
+    }
+
+    /// Constant alpha for the whole layer, multiplied with the pixel alpha
+    pub fn set_alpha(&mut self, alpha: u8) {
+        pac::LTDC.layer(OVERLAY_LAYER).cacr().write(|w| w.0 = alpha as u32);
+        pac::LTDC.srcr().write(|w| w.0 = SRCR_VBR);
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        pac::LTDC.layer(OVERLAY_LAYER).cr().modify(|w| {
+            if enabled {
+                w.0 |= CR_LEN;
+            } else {
+                w.0 &= !CR_LEN;
+            }
+        });
+        pac::LTDC.srcr().write(|w| w.0 = SRCR_VBR);
+    }
+
+    /// Move the window, clamped so it stays fully on screen
+    pub fn set_position(&mut self, pos: Point) {
+        let width = self.config.window_x1 - self.config.window_x0;
+        let height = self.config.window_y1 - self.config.window_y0;
+        let x0 = pos.x.clamp(0, WIDTH as i32 - width as i32) as u16;
+        let y0 = pos.y.clamp(0, HEIGHT as i32 - height as i32) as u16;
+
+        self.config.window_x0 = x0;
+        self.config.window_x1 = x0 + width;
+        self.config.window_y0 = y0;
+        self.config.window_y1 = y0 + height;
+
+        // same maths as Ltdc::init_layer, relative to the back porches
+        let bpcr = pac::LTDC.bpcr().read().0;
+        let ahbp = (bpcr >> 16) & 0xfff;
+        let avbp = bpcr & 0x7ff;
+
+        let layer = pac::LTDC.layer(OVERLAY_LAYER);
+        layer.whpcr().write(|w| w.0 = ((ahbp + self.config.window_x1 as u32) << 16) | (ahbp + self.config.window_x0 as u32 + 1));
+        layer.wvpcr().write(|w| w.0 = ((avbp + self.config.window_y1 as u32) << 16) | (avbp + self.config.window_y0 as u32 + 1));
+        pac::LTDC.srcr().write(|w| w.0 = SRCR_VBR);
+    }
+}
+
+fn bytes_per_pixel(format: OverlayFormat) -> usize {
+    match format {
+        OverlayFormat::Argb4444 => 2,
+        OverlayFormat::L8 => 1,
+    }
+}
+
+fn screen() -> Rectangle {
+    Rectangle::new(Point::zero(), Size::new(WIDTH as u32, HEIGHT as u32))
+}
@@ -0,0 +1,25 @@
+use defmt::info;
+
+use game_and_watch_core::{
+    framebuffer::Frame,
+    screenshot::{self, ScreenshotFormat},
+};
+
+// Screenshots go out over defmt-RTT as `screenshot data [..]` lines. Save
+// the log and game-and-watch-host's `screenshot` tool turns it back into
+// a PNG. If RTT drops lines the byte count at the end won't match and the
+// tool says so, run the probe in blocking mode to avoid that.
+
+/// Encode `frame` and stream it over RTT. Blocks until it's all out, a
+/// 320x240 QOI is usually a few 10s of kB.
+pub fn dump_rtt(frame: &Frame<'_>, format: ScreenshotFormat) {
+    info!("screenshot begin {=str}", format.extension());
+
+    let mut total = 0u32;
+    screenshot::encode(frame, format, |chunk| {
+        info!("screenshot data {=[u8]}", chunk);
+        total += chunk.len() as u32;
+    });
+
+    info!("screenshot end {=u32}", total);
+}
@@ -4,6 +4,10 @@ use embassy_stm32::{
     mode::Blocking, ospi::{AddressSize, ChipSelectHighTime, SckPin, D0Pin, D1Pin, D2Pin, D3Pin, NSSPin, DummyCycles, FIFOThresholdLevel, Instance, MemorySize, MemoryType, Ospi, OspiWidth,     TransferConfig, WrapSize}, peripherals::{self, OCTOSPI1, PA1, PB1, PB2, PD12, PE11, PE2}, rcc::frequency, Peripheral, PeripheralRef
 };
 
+/// Where the OCTOSPI maps the flash in memory mapped mode
+pub const MAPPED_BASE: usize = 0x9000_0000;
+pub const FLASH_SIZE: usize = 1024 * 1024;
+
 #[repr(u8)]
 enum FlashCommand {
     CMD_WRSR = 0x01,
@@ -175,4 +179,14 @@ impl<'a, T: embassy_stm32::ospi::Instance> SpiFlash<'a, T>
 
         Timer::after_millis(20).await;
     }
+
+    /// Hand over the flash for good and get its contents through the
+    /// memory mapping `init` set up. The peripheral is never dropped after
+    /// this, so the mapping and the slice stay valid.
+    pub fn into_memory_mapped(self) -> &'static [u8] {
+        core::mem::forget(self);
+        // SAFETY: the region is mapped read only from here on, and nothing
+        // can send the flash another command now that the driver is gone
+        unsafe { core::slice::from_raw_parts(MAPPED_BASE as *const u8, FLASH_SIZE) }
+    }
 }
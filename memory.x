@@ -1,18 +1,33 @@
-MEMORY
-{
-  ITCMRAM  (xrw) : ORIGIN = 0x00000000, LENGTH = 64K
-  DTCMRAM  (xrw) : ORIGIN = 0x20000000, LENGTH = 128K
-  RAM      (xrw) : ORIGIN = 0x24000000, LENGTH = 1024K
-  FLASH    (xr ) : ORIGIN = 0x8000000,  LENGTH = 128K
-  EXTFLASH (xr ) : ORIGIN = 0x90000000, LENGTH = 1024K
-}
-
-SECTIONS
-{
-  ._extflash :
-  {
-    . = ALIGN(4);
-    _extflash = .;       /* define a global symbols to point at the external flash */
-    KEEP(*(._extflash))
-  } >EXTFLASH 
-}
+MEMORY
+{
+  ITCMRAM  (xrw) : ORIGIN = 0x00000000, LENGTH = 64K
+  DTCMRAM  (xrw) : ORIGIN = 0x20000000, LENGTH = 128K
+  RAM      (xrw) : ORIGIN = 0x24000000, LENGTH = 1024K
+  AHBRAM   (xrw) : ORIGIN = 0x30000000, LENGTH = 128K
+  FLASH    (xr ) : ORIGIN = 0x8000000,  LENGTH = 128K
+  EXTFLASH (xr ) : ORIGIN = 0x90000000, LENGTH = 1024K
+}
+
+SECTIONS
+{
+  ._extflash :
+  {
+    . = ALIGN(4);
+    _extflash = .;       /* define a global symbols to point at the external flash */
+    KEEP(*(._extflash))
+  } >EXTFLASH 
+}
+
+SECTIONS
+{
+  /* not zeroed at startup, see game-and-watch-stm32/src/buffers.rs */
+  .ahbsram (NOLOAD) : ALIGN(4)
+  {
+    *(.ahbsram .ahbsram.*)
+    . = ALIGN(4);
+  } >AHBRAM
+} INSERT AFTER .uninit;
+
+/* the stack grows down from the end of RAM into whatever the framebuffers
+   and the task arena leave, see the RAM budget in game-and-watch-stm32/src/main.rs */
+ASSERT(ORIGIN(RAM) + LENGTH(RAM) - __euninit >= 32K, "less than 32K of RAM left for the stack");